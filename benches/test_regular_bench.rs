@@ -7,6 +7,7 @@ fn fibonacci(n: u64) -> u64 {
     }
 }
 
+#[allow(clippy::needless_return)]
 fn bench_empty() {
     return;
 }
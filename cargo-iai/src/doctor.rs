@@ -0,0 +1,242 @@
+//! `cargo iai doctor`: checks everything a run depends on, one after the other, and says how to fix
+//! whatever is missing. Problems that only degrade the results (like an unknown clock rate) are
+//! warnings; the others make the command fail.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+use crate::{build, cargo, split};
+
+enum Outcome {
+    Ok(String),
+    Warning(String, &'static str),
+    Failure(String, &'static str),
+}
+
+pub(crate) fn run(args: &[String]) -> i32 {
+    println!("iai doctor");
+    let mut failed = false;
+    let mut report = |check: &str, outcome: Outcome| {
+        let (status, message, fix) = match outcome {
+            Outcome::Ok(message) => ("ok", message, None),
+            Outcome::Warning(message, fix) => ("warning", message, Some(fix)),
+            Outcome::Failure(message, fix) => {
+                failed = true;
+                ("FAILED", message, Some(fix))
+            }
+        };
+        println!("  {:<9}{:<14}{}", status, check, message);
+        if let Some(fix) = fix {
+            println!("  {:<23}fix: {}", "", fix);
+        }
+    };
+
+    let valgrind = valgrind();
+    let has_valgrind = matches!(valgrind, Outcome::Ok(_));
+    report("valgrind", valgrind);
+    if cfg!(target_os = "linux") {
+        report("setarch", setarch());
+        report("/proc", proc());
+    }
+    for outcome in results_dirs() {
+        report("results", outcome);
+    }
+    report("clock", clock());
+    if has_valgrind {
+        let (cargo_args, _) = split(args);
+        report("calibration", calibration(cargo_args));
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Runs `program` with `args` and returns its output, if it succeeded.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn valgrind() -> Outcome {
+    let Some(version) = output("valgrind", &["--version"]) else {
+        return Outcome::Failure(
+            "not found on the $PATH".to_owned(),
+            "install valgrind, e.g. with `apt install valgrind` or `dnf install valgrind`",
+        );
+    };
+    if output("valgrind", &["--tool=cachegrind", "--help"]).is_none() {
+        return Outcome::Failure(
+            format!("{} can't run cachegrind", version),
+            "install the complete valgrind package; some distributions split off the tools",
+        );
+    }
+    Outcome::Ok(format!("{} with cachegrind", version))
+}
+
+fn setarch() -> Outcome {
+    let Some(arch) = output("uname", &["-m"]) else {
+        return Outcome::Warning(
+            "unable to tell the architecture".to_owned(),
+            "make sure `uname` is on the $PATH",
+        );
+    };
+    match output("setarch", &[&arch, "-R", "true"]) {
+        Some(_) => Outcome::Ok(format!("can disable address randomization on {}", arch)),
+        None => Outcome::Warning(
+            format!("`setarch {} -R` doesn't work", arch),
+            "install util-linux, or allow seccomp's personality() in the container; without it \
+             address randomization adds noise to the results",
+        ),
+    }
+}
+
+fn proc() -> Outcome {
+    let unreadable: Vec<&str> = ["/proc/self/status", "/proc/self/stat", "/proc/cpuinfo"]
+        .iter()
+        .copied()
+        .filter(|file| std::fs::read_to_string(file).is_err())
+        .collect();
+    if unreadable.is_empty() {
+        Outcome::Ok("readable".to_owned())
+    } else {
+        Outcome::Warning(
+            format!("unable to read {}", unreadable.join(", ")),
+            "mount /proc; --rusage and the clock rate depend on it",
+        )
+    }
+}
+
+/// Whether iai can store its results in the `target/iai` of every package of the workspace.
+fn results_dirs() -> Vec<Outcome> {
+    let metadata = cargo()
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| serde_json::from_slice::<Value>(&output.stdout).ok());
+    let Some(metadata) = metadata else {
+        let failure = Outcome::Failure(
+            "unable to read the workspace metadata".to_owned(),
+            "run `cargo iai doctor` inside a cargo project",
+        );
+        return vec![failure];
+    };
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    packages
+        .iter()
+        .filter_map(|package| package["manifest_path"].as_str())
+        .map(|manifest| {
+            let dir = Path::new(manifest).parent().unwrap().join("target/iai");
+            // Without creating the directory, if the first run hasn't yet
+            let existing = dir.ancestors().find(|dir| dir.exists()).unwrap();
+            let probe = existing.join(".iai-doctor");
+            let writable = std::fs::write(&probe, "").and_then(|()| std::fs::remove_file(&probe));
+            match writable {
+                Ok(()) => Outcome::Ok(format!("{} is writable", dir.display())),
+                Err(e) => Outcome::Failure(
+                    format!("{} isn't writable: {}", existing.display(), e),
+                    "fix the permissions, or set `output-dir` in iai.toml to somewhere writable",
+                ),
+            }
+        })
+        .collect()
+}
+
+fn clock() -> Outcome {
+    if let Ok(hz) = std::env::var("IAI_CLOCK_HZ") {
+        return Outcome::Ok(format!("{} from IAI_CLOCK_HZ", hz));
+    }
+    let detected = if cfg!(target_os = "linux") {
+        std::fs::read_to_string("/proc/cpuinfo")
+            .ok()
+            .and_then(|cpuinfo| {
+                cpuinfo.lines().find_map(|line| {
+                    let (key, mhz) = line.split_once(':')?;
+                    (key.trim() == "cpu MHz").then(|| format!("{} MHz", mhz.trim()))
+                })
+            })
+    } else if cfg!(target_os = "macos") {
+        output("sysctl", &["-n", "hw.cpufrequency"]).map(|hz| format!("{} Hz", hz))
+    } else {
+        output("sysctl", &["-n", "machdep.tsc_freq"]).map(|hz| format!("{} Hz", hz))
+    };
+    match detected {
+        Some(rate) => Outcome::Ok(format!("{} detected", rate)),
+        None => Outcome::Warning(
+            "unable to detect the clock rate, so no times are estimated".to_owned(),
+            "set IAI_CLOCK_HZ (e.g. to 3.5GHz) or `clock` in iai.toml",
+        ),
+    }
+}
+
+/// Builds the benchmarks and measures the harness of the first one, as `--self-test` does.
+fn calibration(cargo_args: &[String]) -> Outcome {
+    let executables = match build(cargo_args) {
+        Ok(executables) => executables,
+        Err(problem) => {
+            return Outcome::Failure(problem, "run `cargo bench --no-run` to see what went wrong")
+        }
+    };
+    let Some(executable) = executables.first() else {
+        return Outcome::Warning(
+            "no bench targets to try".to_owned(),
+            "add a bench target with `harness = false` that calls `iai::main!`",
+        );
+    };
+    let output = executable
+        .command()
+        .arg("--self-test")
+        .stdin(Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let last = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            return Outcome::Failure(
+                format!(
+                    "{} failed: {}",
+                    executable.target,
+                    last.unwrap_or("no output").trim()
+                ),
+                "run `cargo bench --bench <target> -- --self-test` to see the whole output",
+            );
+        }
+        Err(e) => {
+            return Outcome::Failure(
+                format!("unable to run {}: {}", executable.target, e),
+                "check that the bench target builds and runs",
+            )
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let calibration = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Calibration:"))
+        .map(|instructions| instructions.split_whitespace().next().unwrap_or("?"));
+    match calibration {
+        Some(instructions) => Outcome::Ok(format!(
+            "{} instructions of overhead in {}",
+            instructions, executable.target
+        )),
+        None => Outcome::Failure(
+            format!("{} didn't run the harness", executable.target),
+            "make sure the bench target uses `iai::main!` and depends on this version of iai",
+        ),
+    }
+}
@@ -0,0 +1,324 @@
+//! `cargo iai`: builds the bench targets of a cargo project, runs every one of them with the Iai
+//! harness and sums them up in one report.
+//!
+//! ```text
+//! cargo iai [run] [<cargo bench options>] [-- <harness options>]
+//! cargo iai list [<cargo bench options>]
+//! cargo iai compare <old.json> <new.json>
+//! cargo iai clean
+//! cargo iai doctor [<cargo bench options>]
+//! ```
+//!
+//! `run` reads the `--json` output of every bench executable, so the harness options are those of
+//! a single `cargo bench` (filters, `--threshold`, `--baseline` and so on). `compare` reads two
+//! files of that output, as saved from `cargo bench -- --json`, and shows the change between them.
+//! `clean` removes the results iai keeps in the `target/iai` of every package of the workspace.
+//! `doctor` checks the setup: valgrind, `setarch`, `/proc`, the result directories, the clock rate
+//! and a trial run of the harness.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+mod doctor;
+
+fn main() {
+    // `cargo iai` runs us as `cargo-iai iai ...`
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("iai") {
+        args.remove(0);
+    }
+    let command = match args.first().map(String::as_str) {
+        Some("run" | "list" | "compare" | "clean" | "doctor" | "help") => args.remove(0),
+        Some("-h" | "--help") => "help".to_owned(),
+        _ => "run".to_owned(),
+    };
+    let result = match command.as_str() {
+        "run" => run(&args),
+        "list" => list(&args),
+        "compare" => compare(&args),
+        "clean" => clean(),
+        "doctor" => Ok(doctor::run(&args)),
+        _ => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+    };
+    let code = result.unwrap_or_else(|problem| {
+        eprintln!("cargo-iai: {}.", problem);
+        1
+    });
+    std::process::exit(code);
+}
+
+const USAGE: &str = "\
+Usage: cargo iai [run] [<cargo bench options>] [-- <harness options>]
+       cargo iai list [<cargo bench options>]
+       cargo iai compare <old.json> <new.json>
+       cargo iai clean
+       cargo iai doctor [<cargo bench options>]";
+
+fn cargo() -> Command {
+    Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
+
+/// A bench executable built by cargo.
+struct Executable {
+    target: String,
+    path: PathBuf,
+    /// The directory of the package, where cargo would run it.
+    package_dir: PathBuf,
+}
+
+impl Executable {
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.current_dir(&self.package_dir)
+            .env("CARGO_MANIFEST_DIR", &self.package_dir)
+            .arg("--bench");
+        cmd
+    }
+}
+
+/// Builds the bench targets with `cargo bench --no-run`, passing on `cargo_args`.
+fn build(cargo_args: &[String]) -> Result<Vec<Executable>, String> {
+    let output = cargo()
+        .args([
+            "bench",
+            "--no-run",
+            "--message-format=json-render-diagnostics",
+        ])
+        .args(cargo_args)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("unable to run cargo: {}", e))?;
+    if !output.status.success() {
+        return Err("building the benchmarks failed".to_owned());
+    }
+    let mut executables = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let is_bench = message["target"]["kind"]
+            .as_array()
+            .is_some_and(|kinds| kinds.iter().any(|kind| kind == "bench"));
+        let (Some(path), Some(manifest), Some(target), true) = (
+            message["executable"].as_str(),
+            message["manifest_path"].as_str(),
+            message["target"]["name"].as_str(),
+            message["reason"] == "compiler-artifact" && is_bench,
+        ) else {
+            continue;
+        };
+        executables.push(Executable {
+            target: target.to_owned(),
+            path: path.into(),
+            package_dir: Path::new(manifest).parent().unwrap().to_owned(),
+        });
+    }
+    Ok(executables)
+}
+
+/// Splits the arguments at `--` into those for cargo and those for the harness.
+fn split(args: &[String]) -> (&[String], &[String]) {
+    match args.iter().position(|arg| arg == "--") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[]),
+    }
+}
+
+/// Runs every bench target. Like the other commands, returns the exit code or what kept it from
+/// running.
+fn run(args: &[String]) -> Result<i32, String> {
+    let (cargo_args, harness_args) = split(args);
+    let executables = build(cargo_args)?;
+    let mut rows = Vec::new();
+    let mut failed = Vec::new();
+    for executable in &executables {
+        eprintln!("Running {}", executable.target);
+        let output = executable
+            .command()
+            .arg("--json")
+            .args(harness_args)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("unable to run {}: {}", executable.target, e))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        rows.extend(ran_events(&stdout).map(|mut row| {
+            row.name = format!("{}/{}", executable.target, row.name);
+            row
+        }));
+        if !output.status.success() {
+            failed.push(executable.target.as_str());
+        }
+    }
+
+    print(&rows);
+    let regressed = rows.iter().filter(|row| row.regressed).count();
+    println!(
+        "\n{} bench targets, {} benches, {} regressed",
+        executables.len(),
+        rows.len(),
+        regressed
+    );
+    if failed.is_empty() {
+        Ok(0)
+    } else {
+        eprintln!("cargo-iai: {} failed", failed.join(", "));
+        Ok(1)
+    }
+}
+
+fn list(args: &[String]) -> Result<i32, String> {
+    let (cargo_args, _) = split(args);
+    let mut code = 0;
+    for executable in build(cargo_args)? {
+        let output = executable
+            .command()
+            .arg("--list")
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("unable to run {}: {}", executable.target, e))?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            println!("{}/{}", executable.target, line);
+        }
+        if !output.status.success() {
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+fn compare(args: &[String]) -> Result<i32, String> {
+    let [old, new] = args else {
+        return Err(
+            "compare takes the old and the new results, e.g. `cargo iai compare old.json new.json`"
+                .to_owned(),
+        );
+    };
+    let read = |path: &String| {
+        std::fs::read_to_string(path).map_err(|e| format!("unable to read {}: {}", path, e))
+    };
+    let (old, new) = (read(old)?, read(new)?);
+    let old: Vec<Row> = ran_events(&old).collect();
+    let rows: Vec<Row> = ran_events(&new)
+        .map(|mut row| {
+            let previous = old.iter().find(|previous| previous.name == row.name);
+            row.old = previous.map(|previous| previous.new);
+            row
+        })
+        .collect();
+    print(&rows);
+    Ok(0)
+}
+
+fn clean() -> Result<i32, String> {
+    let output = cargo()
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("unable to run cargo: {}", e))?;
+    let metadata: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|_| "unable to read the workspace metadata".to_owned())?;
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    for package in packages {
+        let Some(manifest) = package["manifest_path"].as_str() else {
+            continue;
+        };
+        let results = Path::new(manifest).parent().unwrap().join("target/iai");
+        if results.exists() {
+            std::fs::remove_dir_all(&results)
+                .map_err(|e| format!("unable to remove {}: {}", results.display(), e))?;
+            println!("Removed {}", results.display());
+        }
+    }
+    Ok(0)
+}
+
+/// The instruction count and estimated cycles of one run.
+#[derive(Clone, Copy)]
+struct Counts {
+    instructions: u64,
+    cycles: u64,
+}
+
+struct Row {
+    name: String,
+    new: Counts,
+    old: Option<Counts>,
+    regressed: bool,
+}
+
+/// The benchmarks in `--json` output.
+fn ran_events(output: &str) -> impl Iterator<Item = Row> + '_ {
+    let counts = |stats: &Value| {
+        Some(Counts {
+            instructions: stats["instruction_reads"].as_u64()?,
+            cycles: stats["cycles"].as_u64()?,
+        })
+    };
+    output.lines().filter_map(move |line| {
+        let event: Value = serde_json::from_str(line).ok()?;
+        if event["event"] != "ran" {
+            return None;
+        }
+        Some(Row {
+            name: event["benchmark"].as_str()?.to_owned(),
+            new: counts(&event["stats"])?,
+            old: counts(&event["old_stats"]),
+            regressed: event["regressed"] == true,
+        })
+    })
+}
+
+/// Prints one line per benchmark, with the changes since the old counts.
+fn print(rows: &[Row]) {
+    let change = |new: u64, old: Option<u64>| match old {
+        Some(old) if old == new => "No change".to_owned(),
+        Some(old) => format!(
+            "{:+.3}%",
+            (new as f64 - old as f64) / old.max(1) as f64 * 100.0
+        ),
+        None => String::new(),
+    };
+    let mut table = vec![[
+        "Benchmark".to_owned(),
+        "Instructions".to_owned(),
+        "Change".to_owned(),
+        "Estimated Cycles".to_owned(),
+        "Change".to_owned(),
+        String::new(),
+    ]];
+    for row in rows {
+        let old = row.old;
+        table.push([
+            row.name.clone(),
+            row.new.instructions.to_string(),
+            change(row.new.instructions, old.map(|old| old.instructions)),
+            row.new.cycles.to_string(),
+            change(row.new.cycles, old.map(|old| old.cycles)),
+            if row.regressed { "REGRESSED" } else { "" }.to_owned(),
+        ]);
+    }
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|i| {
+            table
+                .iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    println!();
+    for row in &table {
+        let mut line = format!("{:<width$}", row[0], width = widths[0]);
+        for (cell, &width) in row.iter().zip(&widths).skip(1) {
+            line.push_str(&format!("  {:>width$}", cell, width = width));
+        }
+        println!("{}", line.trim_end());
+    }
+}
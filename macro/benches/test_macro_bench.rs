@@ -25,3 +25,8 @@ fn bench_fibonacci() -> u64 {
 fn bench_fibonacci_long() -> u64 {
     fibonacci(black_box(30))
 }
+
+#[iai(tolerance = "2%")]
+fn bench_fibonacci_tolerance() -> u64 {
+    fibonacci(black_box(20))
+}
@@ -1,46 +1,373 @@
-extern crate proc_macro;
-use proc_macro::TokenStream;
-use proc_macro2::{Ident, TokenTree};
-use quote::quote_spanned;
-
-#[proc_macro_attribute]
-pub fn iai(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let item = proc_macro2::TokenStream::from(item);
-
-    let span = proc_macro2::Span::call_site();
-
-    let function_name = find_name(item.clone());
-    let wrapper_function_name = Ident::new(&format!("wrap_{}", function_name.to_string()), span);
-    let const_name = Ident::new(&format!("IAI_FUNC_{}", function_name.to_string()), span);
-    let name_literal = function_name.to_string();
-
-    let output = quote_spanned!(span=>
-        #item
-
-        fn #wrapper_function_name() {
-            let _ = iai::black_box(#function_name());
-        }
-
-        #[test_case]
-        const #const_name : (&'static str, fn()) = (#name_literal, #wrapper_function_name);
-    );
-
-    output.into()
-}
-
-fn find_name(stream: proc_macro2::TokenStream) -> Ident {
-    let mut iter = stream.into_iter();
-    while let Some(tok) = iter.next() {
-        if let TokenTree::Ident(ident) = tok {
-            if ident == "fn" {
-                break;
-            }
-        }
-    }
-
-    if let Some(TokenTree::Ident(name)) = iter.next() {
-        name
-    } else {
-        panic!("Unable to find function name")
-    }
-}
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use proc_macro2::{Delimiter, Ident, Literal, Span, TokenTree};
+use quote::{quote, quote_spanned};
+
+#[proc_macro_attribute]
+pub fn iai(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = proc_macro2::TokenStream::from(item);
+
+    let options = match Options::parse(attr.into()) {
+        Ok(options) => options,
+        Err((span, message)) => return quote_spanned!(span=> compile_error!(#message);).into(),
+    };
+
+    let span = proc_macro2::Span::call_site();
+
+    let function_name = find_name(item.clone());
+    let is_async = is_async(item.clone());
+
+    if let (Some(teardown), None) = (&options.teardown, &options.setup) {
+        let message = "`teardown` needs a `setup` to tear down";
+        return quote_spanned!(teardown.span()=> compile_error!(#message);).into();
+    }
+    if let (Some(executor), false) = (&options.executor, is_async) {
+        let message = "`executor` is for `async fn` benchmarks";
+        return quote_spanned!(executor.span()=> compile_error!(#message);).into();
+    }
+    let executor = options
+        .executor
+        .as_ref()
+        .map_or_else(|| quote!(iai::block_on), |e| quote!(#e));
+
+    // Named after the function and the module it is in, unless given a name
+    let base_name = options
+        .name
+        .clone()
+        .unwrap_or_else(|| function_name.to_string());
+
+    // One benchmark per type argument, or just the function itself
+    let instances = if options.types.is_empty() {
+        vec![(base_name, function_name.to_string(), quote!(#function_name))]
+    } else {
+        options
+            .types
+            .iter()
+            .enumerate()
+            .map(|(index, ty)| {
+                (
+                    format!("{}::<{}>", base_name, type_name(ty.clone())),
+                    format!("{}_{}", function_name, index),
+                    quote!(#function_name::<#ty>),
+                )
+            })
+            .collect()
+    };
+
+    let mut output = item;
+    for (name_literal, suffix, callee) in instances {
+        let wrapper_function_name = Ident::new(&format!("wrap_{}", suffix), span);
+        let const_name = Ident::new(&format!("IAI_FUNC_{}", suffix), span);
+
+        let call = match &options.setup {
+            Some(setup) => {
+                let teardown = options
+                    .teardown
+                    .as_ref()
+                    .map_or_else(|| quote!(::std::mem::drop), |t| quote!(#t));
+                let routine = if is_async {
+                    quote!(|input| #executor(#callee(input)))
+                } else {
+                    quote!(#callee)
+                };
+                quote!(iai::bench_with_setup(#setup, #routine, #teardown))
+            }
+            None if is_async => quote!(iai::black_box(#executor(#callee()))),
+            None => quote!(iai::black_box(#callee())),
+        };
+
+        let name = if options.name.is_some() {
+            quote!(#name_literal)
+        } else {
+            quote!(iai::strip_crate(
+                concat!(module_path!(), "::", #name_literal)
+            ))
+        };
+        let mut benchmark = quote!(iai::Benchmark::new(#name, #wrapper_function_name));
+        if let Some(tolerance) = &options.tolerance {
+            benchmark = quote!(#benchmark.tolerance(#tolerance));
+        }
+        if let Some(budget) = &options.budget {
+            benchmark = quote!(#benchmark.budget(#budget));
+        }
+        if !options.artifacts.is_empty() {
+            let artifacts = &options.artifacts;
+            benchmark = quote!(#benchmark.artifacts(&[#(#artifacts),*]));
+        }
+        if options.ignore {
+            benchmark = quote!(#benchmark.ignore());
+        }
+
+        output.extend(quote_spanned!(span=>
+            fn #wrapper_function_name() {
+                use iai::{ReturnedResult as _, ReturnedValue as _};
+                (&iai::Returned(#call)).check();
+            }
+
+            #[test_case]
+            const #const_name : iai::Benchmark = #benchmark;
+        ));
+    }
+
+    output.into()
+}
+
+/// Arguments of the attribute, e.g. `#[iai(tolerance = "2%")]`.
+#[derive(Default)]
+struct Options {
+    tolerance: Option<Literal>,
+    budget: Option<Literal>,
+    /// Constants of type `iai::Artifact`, from `artifact = INDEX` (once per artifact).
+    artifacts: Vec<Ident>,
+    /// A function making the input of the benchmark, which then takes it as `&mut`.
+    setup: Option<Ident>,
+    /// A function taking the input once the benchmark is done with it.
+    teardown: Option<Ident>,
+    /// The type arguments to instantiate a generic benchmark with, from `types(u32, String)`.
+    types: Vec<proc_macro2::TokenStream>,
+    /// The function driving an `async fn` benchmark to completion, instead of `iai::block_on`.
+    executor: Option<Ident>,
+    /// Only run with `--ignored` or `--include-ignored`, from `ignore`.
+    ignore: bool,
+    /// The name of the benchmark, instead of the path of the function.
+    name: Option<String>,
+}
+
+type Error = (Span, String);
+
+impl Options {
+    fn parse(attr: proc_macro2::TokenStream) -> Result<Options, Error> {
+        let mut options = Options::default();
+        let mut iter = attr.into_iter();
+        while let Some(tok) = iter.next() {
+            let key = match tok {
+                TokenTree::Ident(key) => key,
+                other => return Err((other.span(), "expected an option name".to_owned())),
+            };
+            match key.to_string().as_str() {
+                "tolerance" => {
+                    let value = expect_value(&key, &mut iter)?;
+                    let tolerance = string_value(&value)
+                        .filter(|t| is_valid_tolerance(t))
+                        .ok_or_else(|| {
+                            (
+                                value.span(),
+                                "tolerance must be a string like \"2%\" or \"1500\"".to_owned(),
+                            )
+                        })?;
+                    options.tolerance = Some(Literal::string(&tolerance));
+                }
+                "budget" => {
+                    let value = expect_value(&key, &mut iter)?;
+                    let budget = string_value(&value)
+                        .filter(|b| is_valid_budget(b))
+                        .ok_or_else(|| {
+                            (
+                                value.span(),
+                                "budget must be a string like \"50us @ 48MHz\"".to_owned(),
+                            )
+                        })?;
+                    options.budget = Some(Literal::string(&budget));
+                }
+                "artifact" => {
+                    let artifact = expect_ident(
+                        &key,
+                        &mut iter,
+                        "artifact must name a constant of type `iai::Artifact`",
+                    )?;
+                    options.artifacts.push(artifact);
+                }
+                "setup" => {
+                    let setup = expect_ident(&key, &mut iter, "setup must name a function")?;
+                    options.setup = Some(setup);
+                }
+                "teardown" => {
+                    let teardown = expect_ident(&key, &mut iter, "teardown must name a function")?;
+                    options.teardown = Some(teardown);
+                }
+                "ignore" => options.ignore = true,
+                "name" => {
+                    let value = expect_value(&key, &mut iter)?;
+                    let name = string_value(&value)
+                        .filter(|name| !name.is_empty())
+                        .ok_or_else(|| (value.span(), "name must be a string".to_owned()))?;
+                    options.name = Some(name);
+                }
+                "executor" => {
+                    let executor = expect_ident(&key, &mut iter, "executor must name a function")?;
+                    options.executor = Some(executor);
+                }
+                "types" => {
+                    options.types = match iter.next() {
+                        Some(TokenTree::Group(group))
+                            if group.delimiter() == Delimiter::Parenthesis =>
+                        {
+                            split_types(group.stream())
+                        }
+                        _ => return Err((key.span(), "expected `types(...)`".to_owned())),
+                    };
+                    if options.types.is_empty() {
+                        return Err((key.span(), "types needs at least one type".to_owned()));
+                    }
+                }
+                other => return Err((key.span(), format!("unknown iai option `{}`", other))),
+            }
+            match iter.next() {
+                None => break,
+                Some(TokenTree::Punct(p)) if p.as_char() == ',' => {}
+                Some(other) => return Err((other.span(), "expected `,`".to_owned())),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Consumes `= <literal>` following `key`.
+fn expect_value(key: &Ident, iter: &mut impl Iterator<Item = TokenTree>) -> Result<Literal, Error> {
+    match iter.next() {
+        Some(TokenTree::Punct(p)) if p.as_char() == '=' => {}
+        _ => return Err((key.span(), format!("expected `{} = ...`", key))),
+    }
+    match iter.next() {
+        Some(TokenTree::Literal(value)) => Ok(value),
+        _ => Err((
+            key.span(),
+            format!("expected a literal value for `{}`", key),
+        )),
+    }
+}
+
+/// Consumes `= <name>` following `key`, failing with `message` if it isn't a name.
+fn expect_ident(
+    key: &Ident,
+    iter: &mut impl Iterator<Item = TokenTree>,
+    message: &str,
+) -> Result<Ident, Error> {
+    match iter.next() {
+        Some(TokenTree::Punct(p)) if p.as_char() == '=' => {}
+        _ => return Err((key.span(), format!("expected `{} = ...`", key))),
+    }
+    match iter.next() {
+        Some(TokenTree::Ident(ident)) => Ok(ident),
+        _ => Err((key.span(), message.to_owned())),
+    }
+}
+
+/// Splits the contents of `types(...)` at the commas that aren't part of a type, like the one of
+/// `HashMap<u32, u64>`.
+fn split_types(stream: proc_macro2::TokenStream) -> Vec<proc_macro2::TokenStream> {
+    let mut types = Vec::new();
+    let mut current = proc_macro2::TokenStream::new();
+    let mut depth = 0;
+    for tok in stream {
+        match &tok {
+            TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+            TokenTree::Punct(p) if p.as_char() == '>' => depth -= 1,
+            TokenTree::Punct(p) if p.as_char() == ',' && depth == 0 => {
+                types.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.extend([tok]);
+    }
+    if !current.is_empty() {
+        types.push(current);
+    }
+    types
+}
+
+/// A type as it would be written, for the name of the benchmark: `Vec<u8>` rather than the
+/// `Vec < u8 >` of `to_string()`.
+fn type_name(stream: proc_macro2::TokenStream) -> String {
+    let mut name = String::new();
+    let mut after_word = false;
+    for tok in stream {
+        let word = matches!(tok, TokenTree::Ident(_) | TokenTree::Literal(_));
+        if word && after_word {
+            name.push(' ');
+        }
+        match &tok {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                name.push_str(open);
+                name.push_str(&type_name(group.stream()));
+                name.push_str(close);
+            }
+            TokenTree::Punct(p) if p.as_char() == ',' || p.as_char() == ';' => {
+                name.push(p.as_char());
+                name.push(' ');
+            }
+            other => name.push_str(&other.to_string()),
+        }
+        after_word = word;
+    }
+    name
+}
+
+/// The contents of a plain string literal, if `lit` is one.
+fn string_value(lit: &Literal) -> Option<String> {
+    let repr = lit.to_string();
+    let inner = repr.strip_prefix('"')?.strip_suffix('"')?;
+    if inner.contains('\\') {
+        return None;
+    }
+    Some(inner.to_owned())
+}
+
+fn is_valid_tolerance(tolerance: &str) -> bool {
+    let tolerance = tolerance.trim();
+    match tolerance.strip_suffix('%') {
+        Some(pct) => pct.trim().parse::<f64>().is_ok_and(|p| p >= 0.0),
+        None => tolerance.replace('_', "").parse::<u64>().is_ok(),
+    }
+}
+
+/// Mirrors `Budget::parse` in iai: a time and a clock rate, like `50us @ 48MHz`.
+fn is_valid_budget(budget: &str) -> bool {
+    let number_with_unit = |s: &str, units: &[&str]| {
+        let s = s.trim().to_lowercase();
+        units.iter().any(|unit| {
+            s.strip_suffix(unit)
+                .and_then(|number| number.trim().parse::<f64>().ok())
+                .is_some_and(|number| number.is_finite() && number >= 0.0)
+        })
+    };
+    match budget.split_once('@') {
+        Some((time, clock)) => {
+            number_with_unit(time, &["ns", "us", "μs", "µs", "ms", "s"])
+                && number_with_unit(clock, &["khz", "mhz", "ghz", "hz"])
+        }
+        None => false,
+    }
+}
+
+/// Whether the function is an `async fn`.
+fn is_async(stream: proc_macro2::TokenStream) -> bool {
+    stream
+        .into_iter()
+        .take_while(|tok| !matches!(tok, TokenTree::Ident(ident) if ident == "fn"))
+        .any(|tok| matches!(tok, TokenTree::Ident(ident) if ident == "async"))
+}
+
+fn find_name(stream: proc_macro2::TokenStream) -> Ident {
+    let mut iter = stream.into_iter();
+    while let Some(tok) = iter.next() {
+        if let TokenTree::Ident(ident) = tok {
+            if ident == "fn" {
+                break;
+            }
+        }
+    }
+
+    if let Some(TokenTree::Ident(name)) = iter.next() {
+        name
+    } else {
+        panic!("Unable to find function name")
+    }
+}
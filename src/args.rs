@@ -0,0 +1,403 @@
+//! Command-line arguments understood by the runner, i.e. whatever follows `cargo bench --`.
+
+use std::path::PathBuf;
+
+use crate::{
+    config::{cache_profile, cache_profile_names, CacheProfile, Tool},
+    export::Export,
+    filter::{Filter, Regex},
+    matrix,
+    policy::{Metric, Tolerance},
+    report::{Format, Sort},
+    Error,
+};
+
+#[derive(Default)]
+pub(crate) struct Args {
+    pub(crate) format: Format,
+    pub(crate) list: bool,
+    pub(crate) layout_sensitivity: bool,
+    pub(crate) dhat: bool,
+    pub(crate) massif: bool,
+    pub(crate) rusage: bool,
+    /// Also measure a second call of every benchmark, after the first warmed up the caches.
+    pub(crate) warm: bool,
+    pub(crate) baseline_dir: Option<PathBuf>,
+    pub(crate) save_baseline: Option<String>,
+    pub(crate) baseline: Option<String>,
+    pub(crate) compare_with: Option<PathBuf>,
+    pub(crate) export_baseline: Option<PathBuf>,
+    pub(crate) import_baseline: Option<PathBuf>,
+    pub(crate) exports: Vec<Export>,
+    /// Append a table of the results to `$GITHUB_STEP_SUMMARY`, when it is set.
+    pub(crate) github_summary: bool,
+    /// Combinations of cargo feature arguments to measure the suite under, one after the other.
+    pub(crate) feature_matrix: Vec<String>,
+    /// Rustup toolchains to build and measure the suite with, one after the other.
+    pub(crate) toolchains: Vec<String>,
+    pub(crate) pgo: bool,
+    /// The version of the crate on crates.io to measure the suite against, or `latest`.
+    pub(crate) against_published: Option<String>,
+    /// Time the benchmarks by the wall clock when valgrind is unavailable.
+    pub(crate) wall_clock_fallback: bool,
+    /// Fail instead of skipping the benchmarks when valgrind is unavailable.
+    pub(crate) require_valgrind: bool,
+    /// Count with `perf stat` instead of cachegrind (`IAI_BACKEND=perf`).
+    pub(crate) perf: bool,
+    pub(crate) threshold: Option<Tolerance>,
+    pub(crate) threshold_profile: Option<String>,
+    /// Metrics that fail the run when they grow by more than their tolerance.
+    pub(crate) fail_on_regression: Vec<(Metric, Tolerance)>,
+    pub(crate) accepted_regressions: Option<PathBuf>,
+    pub(crate) accept: bool,
+    pub(crate) sort: Option<Sort>,
+    pub(crate) only_changed: bool,
+    pub(crate) verbose: bool,
+    pub(crate) self_test: bool,
+    pub(crate) tool: Option<Tool>,
+    /// Simulate branch prediction as well.
+    pub(crate) branch_sim: bool,
+    /// Calibrate every benchmark against a run of its own that skips the call.
+    pub(crate) per_bench_calibration: bool,
+    /// Only count the call of every benchmark, by scoping the instrumentation with client requests.
+    pub(crate) client_requests: bool,
+    /// The simulated caches, instead of those of the configuration.
+    pub(crate) cpu_profile: Option<CacheProfile>,
+    /// Which benchmarks to run.
+    pub(crate) filter: Filter,
+    /// Run only the ignored benchmarks (`--ignored`), or them too (`--include-ignored`).
+    pub(crate) ignored: bool,
+    pub(crate) include_ignored: bool,
+    pub(crate) min_instructions: Option<u64>,
+    /// How many of the most expensive functions to report for every benchmark.
+    pub(crate) top_functions: Option<usize>,
+}
+
+impl Args {
+    pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, Error> {
+        let mut parsed = Args {
+            threshold: std::env::var("IAI_THRESHOLD")
+                .ok()
+                .map(|t| parse_tolerance("IAI_THRESHOLD", &t))
+                .transpose()?,
+            threshold_profile: std::env::var("IAI_THRESHOLD_PROFILE").ok(),
+            fail_on_regression: std::env::var("IAI_FAIL_ON_REGRESSION")
+                .map(|gates| parse_gates("IAI_FAIL_ON_REGRESSION", &gates))
+                .unwrap_or(Ok(Vec::new()))?,
+            min_instructions: std::env::var("IAI_MIN_INSTRUCTIONS")
+                .ok()
+                .map(|n| parse_count("IAI_MIN_INSTRUCTIONS", &n))
+                .transpose()?,
+            top_functions: std::env::var("IAI_TOP_FUNCTIONS")
+                .ok()
+                .map(|n| parse_count("IAI_TOP_FUNCTIONS", &n).map(|n| n as usize))
+                .transpose()?,
+            tool: std::env::var("IAI_TOOL")
+                .ok()
+                .map(|t| parse_tool("IAI_TOOL", &t))
+                .transpose()?,
+            cpu_profile: std::env::var("IAI_CPU_PROFILE")
+                .ok()
+                .map(|p| parse_cpu_profile("IAI_CPU_PROFILE", &p))
+                .transpose()?,
+            github_summary: std::env::var_os("IAI_GITHUB_SUMMARY").is_some(),
+            branch_sim: std::env::var_os("IAI_BRANCH_SIM").is_some(),
+            per_bench_calibration: std::env::var_os("IAI_PER_BENCH_CALIBRATION").is_some(),
+            client_requests: std::env::var_os("IAI_CLIENT_REQUESTS").is_some(),
+            wall_clock_fallback: std::env::var_os("IAI_WALL_CLOCK").is_some(),
+            require_valgrind: std::env::var_os("IAI_REQUIRE_VALGRIND").is_some(),
+            perf: match std::env::var("IAI_BACKEND").as_deref() {
+                Ok("perf") => true,
+                Ok("cachegrind") | Ok("") | Err(_) => false,
+                Ok(backend) => {
+                    return Err(Error::Argument(format!(
+                        "invalid backend {:?} for IAI_BACKEND (expected cachegrind or perf)",
+                        backend
+                    )))
+                }
+            },
+            accepted_regressions: std::env::var_os("IAI_ACCEPTED_REGRESSIONS").map(PathBuf::from),
+            ..Args::default()
+        };
+        if let Ok(backends) = std::env::var("IAI_BACKENDS") {
+            for backend in backends.split(',') {
+                parsed.enable_backend(backend.trim())?;
+            }
+        }
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |what: &str| {
+                args.next()
+                    .ok_or_else(|| Error::Argument(format!("{} requires {}", arg, what)))
+            };
+            match arg.as_str() {
+                "--json" => parsed.format = Format::Json,
+                "--format" => {
+                    let format = value("a format")?;
+                    parsed.format = Format::parse(&format).ok_or_else(|| {
+                        Error::Argument(format!(
+                            "invalid format {:?} for {} (expected pretty, json, markdown, junit or bmf)",
+                            format, arg
+                        ))
+                    })?
+                }
+                "--list" => parsed.list = true,
+                "--layout-sensitivity" => parsed.layout_sensitivity = true,
+                "--dhat" => parsed.dhat = true,
+                "--massif" => parsed.massif = true,
+                "--rusage" => parsed.rusage = true,
+                "--warm" => parsed.warm = true,
+                "--backends" => {
+                    for backend in value("a list of backends")?.split(',') {
+                        parsed.enable_backend(backend.trim())?;
+                    }
+                }
+                "--baseline-dir" => parsed.baseline_dir = Some(value("a directory")?.into()),
+                "--save-baseline" => parsed.save_baseline = Some(value("a baseline name")?),
+                "--baseline" => parsed.baseline = Some(value("a baseline name")?),
+                "--compare-with" => parsed.compare_with = Some(value("a file path")?.into()),
+                "--export-baseline" => parsed.export_baseline = Some(value("a file path")?.into()),
+                "--import-baseline" => parsed.import_baseline = Some(value("a file path")?.into()),
+                "--export" => {
+                    let export = value("<format>:<path>")?;
+                    let export = Export::parse(&export).ok_or_else(|| {
+                        Error::Argument(format!(
+                            "invalid export {:?} for {} (expected csv:<path> or html[:<path>])",
+                            export, arg
+                        ))
+                    })?;
+                    parsed.exports.push(export)
+                }
+                "--threshold" => {
+                    parsed.threshold = Some(parse_tolerance(&arg, &value("a tolerance")?)?)
+                }
+                "--fail-on-regression" => {
+                    let gates = parse_gates(&arg, &value("<metric>=<tolerance>")?)?;
+                    parsed.fail_on_regression.extend(gates)
+                }
+                "--threshold-profile" => parsed.threshold_profile = Some(value("a profile name")?),
+                "--github-summary" => parsed.github_summary = true,
+                "--pgo" => parsed.pgo = true,
+                "--wall-clock-fallback" => parsed.wall_clock_fallback = true,
+                "--require-valgrind" => parsed.require_valgrind = true,
+                "--against-published" => {
+                    parsed.against_published = Some(value("a version, or latest")?)
+                }
+                "--toolchains" => {
+                    parsed.toolchains = value("a list of toolchains")?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|toolchain| !toolchain.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                }
+                "--feature-matrix" => {
+                    parsed.feature_matrix = matrix::parse(&value("a list of feature combinations")?)
+                }
+                "--accept" => parsed.accept = true,
+                "--accepted-regressions" => {
+                    parsed.accepted_regressions = Some(value("a file path")?.into())
+                }
+                "--only-changed" => parsed.only_changed = true,
+                "--exact" => parsed.filter.exact = true,
+                "--filter-regex" => {
+                    let source = value("a regular expression")?;
+                    let regex = Regex::parse(&source).map_err(|e| {
+                        Error::Argument(format!(
+                            "invalid regular expression {:?} for {}: {}",
+                            source, arg, e
+                        ))
+                    })?;
+                    parsed.filter.regexes.push(regex)
+                }
+                "--ignored" => parsed.ignored = true,
+                "--include-ignored" => parsed.include_ignored = true,
+                "-v" | "--verbose" => parsed.verbose = true,
+                "--self-test" => parsed.self_test = true,
+                "--tool" => parsed.tool = Some(parse_tool(&arg, &value("a tool")?)?),
+                "--branch-sim" => parsed.branch_sim = true,
+                "--per-bench-calibration" => parsed.per_bench_calibration = true,
+                "--client-requests" => parsed.client_requests = true,
+                "--cpu-profile" => {
+                    parsed.cpu_profile = Some(parse_cpu_profile(&arg, &value("a CPU profile")?)?)
+                }
+                "--min-instructions" => {
+                    parsed.min_instructions = Some(parse_count(&arg, &value("a count")?)?)
+                }
+                "--top-functions" => {
+                    parsed.top_functions = Some(parse_count(&arg, &value("a count")?)? as usize)
+                }
+                "--sort" => {
+                    let sort = value("a sort order")?;
+                    let sort = Sort::parse(&sort).ok_or_else(|| {
+                        Error::Argument(format!(
+                            "invalid sort order {:?} (expected name, cycles, instructions or delta, \
+                             optionally followed by :asc or :desc)",
+                            sort
+                        ))
+                    })?;
+                    parsed.sort = Some(sort)
+                }
+                // Passed by cargo
+                "--bench" => {}
+                _ if arg.starts_with('-') => {
+                    return Err(Error::Argument(format!("unknown argument {}", arg)))
+                }
+                _ => parsed.filter.patterns.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Enables one of the backends that run besides cachegrind, as listed in `--backends` or
+    /// `IAI_BACKENDS`.
+    fn enable_backend(&mut self, backend: &str) -> Result<(), Error> {
+        match backend {
+            "dhat" => self.dhat = true,
+            "massif" => self.massif = true,
+            "rusage" => self.rusage = true,
+            // Always measured
+            "cachegrind" | "" => {}
+            _ => {
+                return Err(Error::Argument(format!(
+                    "invalid backend {:?} (expected dhat, massif or rusage)",
+                    backend
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_cpu_profile(source: &str, name: &str) -> Result<CacheProfile, Error> {
+    cache_profile(name).ok_or_else(|| {
+        Error::Argument(format!(
+            "invalid CPU profile {:?} for {} (expected one of {})",
+            name,
+            source,
+            cache_profile_names()
+        ))
+    })
+}
+
+fn parse_count(source: &str, value: &str) -> Result<u64, Error> {
+    value
+        .replace('_', "")
+        .parse()
+        .map_err(|_| Error::Argument(format!("invalid count {:?} for {}", value, source)))
+}
+
+fn parse_tool(source: &str, value: &str) -> Result<Tool, Error> {
+    Tool::parse(value).ok_or_else(|| {
+        Error::Argument(format!(
+            "invalid tool {:?} for {} (expected cachegrind or callgrind)",
+            value, source
+        ))
+    })
+}
+
+/// Parses a comma-separated list of gates like `instructions=2%,cycles=5%`.
+fn parse_gates(source: &str, value: &str) -> Result<Vec<(Metric, Tolerance)>, Error> {
+    value
+        .split(',')
+        .filter(|gate| !gate.trim().is_empty())
+        .map(|gate| {
+            Metric::parse_gate(gate).map_err(|e| {
+                Error::Argument(format!("invalid gate {:?} for {}: {}", gate, source, e))
+            })
+        })
+        .collect()
+}
+
+fn parse_tolerance(source: &str, value: &str) -> Result<Tolerance, Error> {
+    Tolerance::parse(value).ok_or_else(|| {
+        Error::Argument(format!(
+            "invalid tolerance {:?} for {} (expected e.g. \"2%\" or \"1500\")",
+            value, source
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} parsed", args),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_flags_and_values() {
+        let args = parse(&[
+            "--bench",
+            "--format",
+            "markdown",
+            "--threshold",
+            "2%",
+            "--min-instructions",
+            "1_000",
+            "--tool",
+            "callgrind",
+            "--backends",
+            "dhat,rusage",
+            "--baseline-dir",
+            "baselines",
+            "-v",
+            "--only-changed",
+        ])
+        .unwrap();
+        assert_eq!(args.format, Format::Markdown);
+        assert_eq!(args.threshold, Some(Tolerance::Percent(2.0)));
+        assert_eq!(args.min_instructions, Some(1000));
+        assert_eq!(args.tool, Some(Tool::Callgrind));
+        assert!(args.dhat && args.rusage && !args.massif);
+        assert_eq!(args.baseline_dir, Some(PathBuf::from("baselines")));
+        assert!(args.verbose && args.only_changed && !args.list);
+        assert_eq!(parse(&["--json"]).unwrap().format, Format::Json);
+    }
+
+    #[test]
+    fn collects_filters() {
+        let args = parse(&["parse", "--exact", "--filter-regex", "^print_", "json"]).unwrap();
+        assert_eq!(args.filter.patterns, ["parse", "json"]);
+        assert!(args.filter.exact);
+        assert!(args.filter.matches("print_json") && !args.filter.matches("parse_json"));
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(error(&["--bogus"]), "unknown argument --bogus");
+        assert_eq!(error(&["parse", "-x"]), "unknown argument -x");
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_values() {
+        assert_eq!(error(&["--threshold"]), "--threshold requires a tolerance");
+        assert_eq!(
+            error(&["--threshold", "lots"]),
+            "invalid tolerance \"lots\" for --threshold (expected e.g. \"2%\" or \"1500\")"
+        );
+        assert_eq!(
+            error(&["--top-functions", "ten"]),
+            "invalid count \"ten\" for --top-functions"
+        );
+        assert_eq!(
+            error(&["--backends", "perf"]),
+            "invalid backend \"perf\" (expected dhat, massif or rusage)"
+        );
+        assert_eq!(
+            error(&["--tool", "memcheck"]),
+            "invalid tool \"memcheck\" for --tool (expected cachegrind or callgrind)"
+        );
+        assert!(error(&["--format", "xml"]).starts_with("invalid format \"xml\" for --format"));
+        assert!(error(&["--filter-regex", "(parse"])
+            .starts_with("invalid regular expression \"(parse\" for --filter-regex: "));
+    }
+}
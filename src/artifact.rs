@@ -0,0 +1,91 @@
+//! Expensive inputs shared by several benchmarks, like a prebuilt index.
+//!
+//! The harness builds every artifact the selected benchmarks declare once, before any of them
+//! runs, and stores it in `target/iai/artifacts`. Each benchmark process loads the artifacts of its
+//! benchmark before starting it, and benchmarks that declare any are calibrated against a run that
+//! only loads them (like extern benchmarks are against loading their library), so neither building
+//! nor loading them ends up in the measurement.
+
+use std::{path::PathBuf, sync::Mutex};
+
+use crate::{file_safe, Benchmark, Config, Error};
+
+/// Artifacts loaded by the running benchmark process; they live until it exits.
+static LOADED: Mutex<Vec<(&'static str, &'static [u8])>> = Mutex::new(Vec::new());
+
+/// A serialized input that is built once per run and shared by the benchmarks that declare it with
+/// [`Benchmark::artifacts`].
+///
+/// ```ignore
+/// const INDEX: Artifact = Artifact::new("index", || build_index().to_bytes());
+///
+/// #[iai(artifact = INDEX)]
+/// fn lookup() -> bool {
+///     Index::from_bytes(INDEX.get()).contains("needle")
+/// }
+/// ```
+///
+/// `get` only hands out the bytes, so deserializing them is measured; use a format that is cheap
+/// to read back, or one that can be used in place.
+#[derive(Clone, Copy, Debug)]
+pub struct Artifact {
+    /// Identifies the artifact between benchmarks; artifacts with the same name are built once.
+    pub name: &'static str,
+    pub build: fn() -> Vec<u8>,
+}
+
+impl Artifact {
+    pub const fn new(name: &'static str, build: fn() -> Vec<u8>) -> Artifact {
+        Artifact { name, build }
+    }
+
+    /// The bytes built by the harness. Panics in benchmarks that didn't declare this artifact.
+    pub fn get(&self) -> &'static [u8] {
+        LOADED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map(|&(_, bytes)| bytes)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Artifact {:?} wasn't declared by the running benchmark",
+                    self.name
+                )
+            })
+    }
+}
+
+fn artifact_file(config: &Config, artifact: &Artifact) -> PathBuf {
+    config
+        .results_dir()
+        .join("artifacts")
+        .join(file_safe(artifact.name))
+}
+
+/// Builds the artifacts of `benches`, each once, and stores them for the benchmark processes.
+pub(crate) fn build(benches: &[(usize, &Benchmark)], config: &Config) -> Result<(), Error> {
+    let mut built: Vec<&str> = Vec::new();
+    for artifact in benches.iter().flat_map(|(_, bench)| bench.artifacts) {
+        if built.contains(&artifact.name) {
+            continue;
+        }
+        let file = artifact_file(config, artifact);
+        let dir = file.parent().unwrap();
+        std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        std::fs::write(&file, (artifact.build)()).map_err(|e| Error::io(&file, e))?;
+        built.push(artifact.name);
+    }
+    Ok(())
+}
+
+/// Loads the artifacts of `bench` in the benchmark process, for [`Artifact::get`].
+pub(crate) fn load(bench: &Benchmark, config: &Config) -> Result<(), Error> {
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    for artifact in bench.artifacts {
+        let file = artifact_file(config, artifact);
+        let bytes = std::fs::read(&file).map_err(|e| Error::io(&file, e))?;
+        loaded.push((artifact.name, Box::leak(bytes.into_boxed_slice())));
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! The tools that measure benchmarks. Cachegrind (or callgrind) provides the instruction and cache
+//! metrics every benchmark gets; DHAT (`--dhat`), massif (`--massif`) and the kernel's resource
+//! accounting (`--rusage`) run every benchmark once more, to measure something cachegrind doesn't.
+//! Any number of them can be combined (e.g. `--backends dhat,rusage`), and their metrics end up in
+//! the same report and JSON object as the cachegrind ones. `perf stat` (`IAI_BACKEND=perf`) takes
+//! the place of cachegrind instead, see [`perf`](crate::perf).
+//!
+//! There is no backend for wasmtime: the benchmarks are native processes started from the bench
+//! executable, and measuring them in a WebAssembly runtime would need a `wasm32-wasi` build of the
+//! suite that `cargo bench` doesn't make.
+//!
+//! The valgrind tools' profiles are kept in `target/iai/<tool>.out.<name>` (or a subdirectory of it
+//! for builds with a profile other than `release`), with the previous run's next to them as
+//! `.old`, so that they can also be opened in the tool's own viewer.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use crate::{
+    basic_valgrind, file_safe, store_results, Benchmark, Config, Error, RunMetadata, Target,
+    BENCHMARK_PROCESS_VAR, HARNESS_VERSION,
+};
+
+/// One number a backend measured.
+#[derive(Debug)]
+pub(crate) struct Metric {
+    /// The name in the JSON output.
+    pub(crate) key: &'static str,
+    /// The name in the human-readable report, if it is shown there.
+    pub(crate) label: Option<&'static str>,
+    pub(crate) value: u64,
+}
+
+impl Metric {
+    pub(crate) fn new(key: &'static str, label: Option<&'static str>, value: u64) -> Metric {
+        Metric { key, label, value }
+    }
+}
+
+/// What one of these tools measures about a single run.
+pub(crate) trait Backend: Sized {
+    /// Names the metrics in the JSON output.
+    const KEY: &'static str;
+
+    /// The valgrind tool, which also names its output files.
+    fn tool(config: &Config) -> &'static str;
+
+    /// Options for the tool, besides where to write its output.
+    fn args(config: &Config) -> Vec<String>;
+
+    /// Arguments for the benchmark process, before those of the run.
+    fn child_args(_: &Config) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Runs `target` once under the tool (through `cmd`), writing the profile to `output_file`,
+    /// and returns what the benchmark process reported about itself. `child_args` are passed on to
+    /// the benchmark process after the harness arguments.
+    fn execute(
+        mut cmd: Command,
+        executable: &str,
+        target: Target,
+        config: &Config,
+        child_args: &[&str],
+        output_file: &Path,
+    ) -> Result<RunMetadata, Error> {
+        let tool = Self::tool(config);
+        cmd.arg(format!("--tool={}", tool))
+            .args(Self::args(config))
+            .arg(format!("--{}-out-file={}", tool, output_file.display()))
+            .arg(executable)
+            .env(BENCHMARK_PROCESS_VAR, "1")
+            .args(target.child_args())
+            .arg("--iai-harness")
+            .arg(HARNESS_VERSION)
+            .args(Self::child_args(config))
+            .args(child_args);
+        let output = run_child(tool, cmd)?;
+        Ok(RunMetadata::parse(&output.stderr))
+    }
+
+    fn parse(file: &Path) -> Result<Self, Error>;
+
+    /// Removes what the runtime does before reaching the benchmark. Peaks aren't additive, so for
+    /// them this is only an approximation; everything is clamped at zero.
+    fn subtract(&self, calibration: &Self) -> Self;
+
+    /// Everything that was measured, in the order of the report.
+    fn metrics(&self) -> Vec<Metric>;
+}
+
+/// The metrics of one backend for this run and the previous one, with the calibration subtracted.
+#[derive(Debug)]
+pub(crate) struct Measurement {
+    pub(crate) key: &'static str,
+    pub(crate) metrics: Vec<Metric>,
+    /// In the same order as `metrics`.
+    pub(crate) old: Option<Vec<Metric>>,
+}
+
+/// Runs the benchmark process of `cmd` under `tool`, which fails unless it exits successfully.
+pub(crate) fn run_child(tool: &'static str, mut cmd: Command) -> Result<Output, Error> {
+    let output = cmd.output().map_err(|error| Error::Spawn { tool, error })?;
+    if !output.status.success() {
+        return Err(Error::Crashed {
+            tool,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(output)
+}
+
+/// Measures what the runtime does before reaching a benchmark, with `B`.
+pub(crate) fn calibrate<B: Backend>(
+    executable: &str,
+    config: &Config,
+) -> Result<(B, Option<B>), Error> {
+    let target = Target::Calibration;
+    let (stats, old, _) = run::<B>(
+        basic_valgrind(),
+        executable,
+        target,
+        "iai_calibration",
+        config,
+        &[],
+    )?;
+    Ok((stats, old))
+}
+
+/// Measures a benchmark with `B`. Like with cachegrind, benchmarks with setup are calibrated
+/// against a run that only does the setup; everything else against `calibration`.
+pub(crate) fn measure<B: Backend>(
+    executable: &str,
+    target: Target,
+    bench: &Benchmark,
+    config: &Config,
+    calibration: &(B, Option<B>),
+) -> Result<Measurement, Error> {
+    let (stats, old, _) = run::<B>(
+        basic_valgrind(),
+        executable,
+        target,
+        bench.name,
+        config,
+        &[],
+    )?;
+    let load_calibration = if bench.has_setup() {
+        let name = format!("{}.load", bench.name);
+        let args = ["--iai-load-only"];
+        let (stats, old, _) = run::<B>(basic_valgrind(), executable, target, &name, config, &args)?;
+        Some((stats, old))
+    } else {
+        None
+    };
+    let (calibration, old_calibration) = load_calibration.as_ref().unwrap_or(calibration);
+    Ok(Measurement {
+        key: B::KEY,
+        metrics: stats.subtract(calibration).metrics(),
+        old: match (old, old_calibration) {
+            (Some(old), Some(old_calibration)) => Some(old.subtract(old_calibration).metrics()),
+            _ => None,
+        },
+    })
+}
+
+/// Runs `target` under `B`'s tool (through `cmd`) and returns what it measured, along with the
+/// results to compare against and what the benchmark process reported about itself.
+pub(crate) fn run<B: Backend>(
+    cmd: Command,
+    executable: &str,
+    target: Target,
+    name: &str,
+    config: &Config,
+    child_args: &[&str],
+) -> Result<(B, Option<B>, RunMetadata), Error> {
+    let output_file =
+        config
+            .results_dir()
+            .join(format!("{}.out.{}", B::tool(config), file_safe(name)));
+    let new_file = PathBuf::from(format!("{}.new", output_file.display()));
+    let dir = output_file.parent().unwrap();
+    std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+
+    let (new_stats, metadata) =
+        match run_once::<B>(cmd, executable, target, config, child_args, &new_file) {
+            Ok(run) => run,
+            Err(e) => {
+                let _ = std::fs::remove_file(&new_file);
+                return Err(e);
+            }
+        };
+    // A failed benchmark never ran to the end, so its results are neither kept nor compared
+    if let Some(failure) = metadata.failure {
+        let _ = std::fs::remove_file(&new_file);
+        return Err(Error::Failed(failure));
+    }
+    // Only rotate once the new measurement has parsed, so that a failed run can't replace the
+    // results it should have been compared against
+    let old_stats = store_results(config, &output_file, &new_file, B::parse)?;
+
+    Ok((new_stats, old_stats, metadata))
+}
+
+/// Runs `target` once with `B` (see [`Backend::execute`]) and parses what it measured.
+pub(crate) fn run_once<B: Backend>(
+    cmd: Command,
+    executable: &str,
+    target: Target,
+    config: &Config,
+    child_args: &[&str],
+    output_file: &Path,
+) -> Result<(B, RunMetadata), Error> {
+    let metadata = B::execute(cmd, executable, target, config, child_args, output_file)?;
+    Ok((B::parse(output_file)?, metadata))
+}
@@ -0,0 +1,443 @@
+//! Baselines that live outside of `target/iai`.
+//!
+//! A baseline directory (`--baseline-dir`) gets one file per bench target,
+//! `<dir>/<target>.baseline` (`<dir>/<target>.<profile>.baseline` for builds with a cargo profile
+//! other than `release`), holding one `<name> <instructions>` line per benchmark. Only
+//! instruction counts are recorded, since they are the one metric that stays comparable across
+//! machines with different caches. New benchmarks are added to the file automatically; existing
+//! entries are only rewritten with `--accept`, which leaves every other line (including comments)
+//! untouched so that the resulting diff is easy to review.
+//!
+//! Exported baselines (`--export-baseline`) instead carry the full cachegrind (or callgrind) totals
+//! of the last run of every benchmark, plus enough metadata to tell where they came from.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    build_profile, cachegrind, config::Tool, get_arch, result_file, write_atomically, Benchmark,
+    Config, Error,
+};
+
+/// Version of the exported baseline format. Bump this whenever the layout changes.
+const FORMAT_VERSION: u32 = 1;
+const FORMAT_HEADER: &str = "iai-baseline";
+
+pub(crate) struct BaselineDir {
+    path: PathBuf,
+    /// The file as it was loaded, so that it can be rewritten with minimal changes.
+    lines: Vec<String>,
+    entries: BTreeMap<String, u64>,
+    added: usize,
+    updated: usize,
+}
+
+impl BaselineDir {
+    /// Loads the baseline file for `executable` from `dir`. A missing file is treated as empty.
+    pub(crate) fn load(
+        dir: &Path,
+        executable: &str,
+        config: &Config,
+    ) -> Result<BaselineDir, Error> {
+        let path = match config.build_profile.as_deref() {
+            Some(profile) if profile != build_profile::DEFAULT => {
+                dir.join(format!("{}.{}.baseline", target_name(executable), profile))
+            }
+            _ => dir.join(format!("{}.baseline", target_name(executable))),
+        };
+        let lines: Vec<String> = match fs::read_to_string(&path) {
+            Ok(contents) => contents.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+        let mut entries = BTreeMap::new();
+        for line in &lines {
+            if let Some((name, count)) = parse_entry(line) {
+                entries.insert(name.to_owned(), count);
+            } else if !line.trim().is_empty() && !line.trim().starts_with('#') {
+                eprintln!("Ignoring malformed line in {}: {:?}", path.display(), line);
+            }
+        }
+        Ok(BaselineDir {
+            path,
+            lines,
+            entries,
+            added: 0,
+            updated: 0,
+        })
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<u64> {
+        self.entries.get(name).copied()
+    }
+
+    /// Records `instructions` for `name` if the baseline has no entry for it yet.
+    pub(crate) fn insert_missing(&mut self, name: &str, instructions: u64) {
+        if !self.entries.contains_key(name) {
+            self.entries.insert(name.to_owned(), instructions);
+            self.added += 1;
+        }
+    }
+
+    /// Records `instructions` for `name`, replacing the existing entry if it changed.
+    pub(crate) fn accept(&mut self, name: &str, instructions: u64) {
+        match self.entries.insert(name.to_owned(), instructions) {
+            None => self.added += 1,
+            Some(old) if old != instructions => self.updated += 1,
+            Some(_) => {}
+        }
+    }
+
+    /// Writes the baseline file back if any entries were added or updated.
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        if self.added == 0 && self.updated == 0 {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        let mut contents = String::new();
+        let mut written = std::collections::HashSet::new();
+        for line in &self.lines {
+            match parse_entry(line) {
+                Some((name, count)) if written.insert(name) => {
+                    let new = self.entries[name];
+                    if new == count {
+                        contents.push_str(line);
+                    } else {
+                        contents.push_str(&format!("{} {}", name, new));
+                    }
+                }
+                // Drop duplicate entries, only the last one was ever used
+                Some(_) => continue,
+                None => contents.push_str(line),
+            }
+            contents.push('\n');
+        }
+        for (name, count) in &self.entries {
+            if !written.contains(name.as_str()) {
+                contents.push_str(&format!("{} {}\n", name, count));
+            }
+        }
+        write_atomically(&self.path, &contents).map_err(|e| Error::io(&self.path, e))?;
+        eprintln!(
+            "Baseline {}: {} entries added, {} updated",
+            self.path.display(),
+            self.added,
+            self.updated
+        );
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(&str, u64)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (name, count) = line.rsplit_once(char::is_whitespace)?;
+    Some((name.trim(), count.parse().ok()?))
+}
+
+/// Strips the directory and the `-<hash>` suffix cargo adds to bench executables, so that the
+/// baseline file name is stable across builds. The hash is always 16 hex digits; shorter suffixes
+/// like the `256` of `sha-256` are part of the name.
+fn target_name(executable: &str) -> String {
+    let stem = Path::new(executable)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(executable);
+    match stem.rsplit_once('-') {
+        Some((name, hash)) if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            name.to_owned()
+        }
+        _ => stem.to_owned(),
+    }
+}
+
+/// Splits a `bench <name> <summary>` line, without the `bench`, into the name and the summary
+/// of its `events` counts. The summary is taken from the end, since names can have spaces.
+fn split_result(result: &str, events: usize) -> Option<(&str, &str)> {
+    let mut name_end = result.len();
+    for _ in 0..events {
+        name_end = result[..name_end].rfind(' ')?;
+    }
+    Some((&result[..name_end], &result[name_end + 1..]))
+}
+
+/// The machine the results come from, even when `uname` can't tell.
+fn machine() -> String {
+    get_arch().unwrap_or_else(|| std::env::consts::ARCH.to_owned())
+}
+
+/// The `desc:` lines cachegrind writes for the caches of `sim_args`, the `--I1`, `--D1` and `--LL`
+/// flags with their `<size>,<associativity>,<line size>`.
+fn cache_descs(sim_args: &str) -> String {
+    let mut descs = String::new();
+    for flag in sim_args.split_whitespace() {
+        let Some((level, geometry)) = flag.trim_start_matches('-').split_once('=') else {
+            continue;
+        };
+        let geometry: Vec<&str> = geometry.split(',').collect();
+        let [size, associativity, line] = geometry[..] else {
+            continue;
+        };
+        let associativity = match associativity {
+            "1" => "direct-mapped".to_owned(),
+            ways => format!("{}-way associative", ways),
+        };
+        descs.push_str(&format!(
+            "desc: {} cache:         {} B, {} B, {}\n",
+            level, size, line, associativity
+        ));
+    }
+    descs
+}
+
+/// Writes the results of the last run of every benchmark (and of the calibration run) to `path`.
+pub(crate) fn export(
+    path: &Path,
+    executable: &str,
+    config: &Config,
+    benches: &[&Benchmark],
+) -> Result<(), Error> {
+    let mut events = None;
+    let mut lines = Vec::new();
+    let names = std::iter::once("iai_calibration").chain(benches.iter().map(|bench| bench.name));
+    for name in names {
+        let file = result_file(config, config.tool, name);
+        if !file.exists() {
+            eprintln!(
+                "No results for {}, skipping it. Run the benchmarks first.",
+                name
+            );
+            continue;
+        }
+        match cachegrind::read_file(&file) {
+            Ok((counts, metadata)) => {
+                let file_events = metadata.events.join(" ");
+                if events.get_or_insert_with(|| file_events.clone()) != &file_events {
+                    eprintln!(
+                        "Results for {} were recorded with different events, skipping it.",
+                        name
+                    );
+                    continue;
+                }
+                let summary: Vec<String> = metadata
+                    .events
+                    .iter()
+                    .map(|event| counts[event].to_string())
+                    .collect();
+                lines.push(format!("bench {} {}", name, summary.join(" ")));
+            }
+            Err(e) => eprintln!(
+                "Unable to parse the results for {}, skipping it: {}",
+                name, e
+            ),
+        }
+    }
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut contents = format!("{} {}\n", FORMAT_HEADER, FORMAT_VERSION);
+    contents.push_str(&format!("iai {}\n", env!("CARGO_PKG_VERSION")));
+    contents.push_str(&format!("target {}\n", target_name(executable)));
+    contents.push_str(&format!("arch {}\n", machine()));
+    contents.push_str(&format!("tool {}\n", config.tool.name()));
+    if let Some(profile) = &config.build_profile {
+        contents.push_str(&format!("profile {}\n", profile));
+    }
+    contents.push_str(&format!("cache {}\n", config.cache.sim_args.join(" ")));
+    contents.push_str(&format!("created {}\n", created));
+    contents.push_str(&format!("events {}\n", events.unwrap_or_default()));
+    for line in lines {
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    write_atomically(path, &contents).map_err(|e| Error::io(path, e))?;
+    eprintln!("Exported baseline to {}", path.display());
+    Ok(())
+}
+
+/// Loads an exported baseline and installs it as the last run of each benchmark, so that the
+/// next run compares against it.
+pub(crate) fn import(
+    path: &Path,
+    executable: &str,
+    config: &Config,
+    benches: &[&Benchmark],
+) -> Result<(), Error> {
+    let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let mut lines = contents.lines();
+
+    match lines.next().and_then(|l| l.split_once(' ')) {
+        Some((FORMAT_HEADER, version)) => match version.trim().parse::<u32>() {
+            Ok(FORMAT_VERSION) => {}
+            Ok(version) => {
+                return Err(Error::output(
+                    path,
+                    format!(
+                        "uses baseline format version {}, but this version of iai only \
+                         understands version {}",
+                        version, FORMAT_VERSION
+                    ),
+                ))
+            }
+            Err(_) => return Err(Error::output(path, "malformed format version")),
+        },
+        _ => return Err(Error::output(path, "not an iai baseline file")),
+    }
+
+    let mut metadata = BTreeMap::new();
+    let mut results = Vec::new();
+    for line in lines {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        if key == "bench" {
+            results.push(value);
+        } else {
+            metadata.insert(key, value);
+        }
+    }
+
+    let target = target_name(executable);
+    if metadata.get("target").is_some_and(|t| *t != target) {
+        eprintln!(
+            "Warning: baseline was exported from bench target {}, importing it into {}.",
+            metadata["target"], target
+        );
+    }
+    let arch = machine();
+    if metadata.get("arch").is_some_and(|a| *a != arch) {
+        eprintln!(
+            "Warning: baseline was recorded on {}, this machine is {}.",
+            metadata["arch"], arch
+        );
+    }
+    // Baselines from before the tool was recorded are all from cachegrind
+    let tool = match metadata.get("tool") {
+        Some(tool) => Tool::parse(tool)
+            .ok_or_else(|| Error::output(path, format!("recorded with unknown tool {}", tool)))?,
+        None => Tool::Cachegrind,
+    };
+    if tool != config.tool {
+        eprintln!(
+            "Warning: baseline was recorded with {}, it is only compared against when running with `--tool {}`.",
+            tool.name(),
+            tool.name()
+        );
+    }
+    if let (Some(recorded), Some(profile)) = (metadata.get("profile"), &config.build_profile) {
+        if recorded != profile {
+            eprintln!(
+                "Warning: baseline was recorded with the {} profile, importing it for {}.",
+                recorded, profile
+            );
+        }
+    }
+    let cache = config.cache.sim_args.join(" ");
+    if metadata.get("cache").is_some_and(|c| *c != cache) {
+        eprintln!(
+            "Warning: baseline was recorded with cache configuration `{}`, not `{}`.",
+            metadata["cache"], cache
+        );
+    }
+    let events = match metadata.get("events") {
+        Some(events) if !events.is_empty() => *events,
+        _ => return Err(Error::output(path, "doesn't list its events")),
+    };
+
+    // Results are only compared against those of the same caches, which cachegrind describes
+    let cache_descs = metadata
+        .get("cache")
+        .map_or_else(String::new, |c| cache_descs(c));
+
+    let results_dir = config.results_dir();
+    fs::create_dir_all(&results_dir).map_err(|e| Error::io(&results_dir, e))?;
+    let mut imported = 0;
+    for result in results {
+        let (name, summary) = split_result(result, events.split_whitespace().count())
+            .ok_or_else(|| Error::output(path, format!("malformed result {:?}", result)))?;
+        if name != "iai_calibration" && !benches.iter().any(|bench| bench.name == name) {
+            eprintln!("Ignoring baseline for unknown benchmark {}", name);
+            continue;
+        }
+        let contents = format!(
+            "desc: imported from {}\n{}events: {}\nsummary: {}\n",
+            path.display(),
+            cache_descs,
+            events,
+            summary
+        );
+        let file = result_file(config, tool, name);
+        write_atomically(&file, &contents).map_err(|e| Error::io(&file, e))?;
+        imported += 1;
+    }
+    eprintln!("Imported {} results from {}", imported, path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        assert_eq!(parse_entry("parse 1200"), Some(("parse", 1200)));
+        assert_eq!(parse_entry("  parse\t1200  "), Some(("parse", 1200)));
+        // Names of parameterized benchmarks can have spaces, the count is the last word
+        assert_eq!(
+            parse_entry("parse large input 5000"),
+            Some(("parse large input", 5000))
+        );
+        for line in ["", "   ", "# parse 1200", "parse", "parse many", "parse -1"] {
+            assert_eq!(parse_entry(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn names_targets_without_the_hash() {
+        assert_eq!(
+            target_name("target/release/deps/parsing-0d3a5b210f18608b"),
+            "parsing"
+        );
+        assert_eq!(
+            target_name("target/release/deps/json-parsing-0d3a5b210f18608b"),
+            "json-parsing"
+        );
+        // Only cargo's hash is stripped, not every suffix that happens to be hex
+        for name in ["sha-256", "decode-bad", "json-parsing-abc123", "parsing-"] {
+            assert_eq!(target_name(name), name);
+        }
+        assert_eq!(target_name("benches/json-parsing"), "json-parsing");
+        assert_eq!(target_name("parsing-0d3a5b210f18608b.exe"), "parsing");
+    }
+
+    #[test]
+    fn splits_names_with_spaces_from_the_results() {
+        assert_eq!(split_result("parse 100 20", 2), Some(("parse", "100 20")));
+        assert_eq!(
+            split_result("parse large input 100 20", 2),
+            Some(("parse large input", "100 20"))
+        );
+        assert_eq!(split_result("100 20", 2), None);
+    }
+
+    #[test]
+    fn cache_descs_match_cachegrind() {
+        assert_eq!(
+            cache_descs("--I1=32768,8,64 --D1=32768,1,64"),
+            "desc: I1 cache:         32768 B, 64 B, 8-way associative\n\
+             desc: D1 cache:         32768 B, 64 B, direct-mapped\n"
+        );
+        assert_eq!(cache_descs(""), "");
+    }
+}
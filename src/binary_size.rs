@@ -0,0 +1,115 @@
+//! The size of the code under test, i.e. the `.text` section of the bench executable and of the
+//! shared libraries of extern benchmarks. Instruction counts are often traded for code size (by
+//! inlining or unrolling), so it is reported next to them, with the change since the previous run.
+//!
+//! The sizes are kept in `target/iai/text-size`, as `<file> <bytes>` lines. Only ELF files are
+//! understood; other executables are left out.
+
+use std::{fs, path::Path};
+
+use crate::{matrix, write_atomically, Benchmark, Config, Error};
+
+pub(crate) struct TextSize {
+    /// The bench target, or the path of the library.
+    pub(crate) file: String,
+    pub(crate) bytes: u64,
+    pub(crate) old: Option<u64>,
+}
+
+/// Measures the executable and the libraries of `benches`, and stores the sizes for the next run.
+pub(crate) fn measure(
+    executable: &str,
+    benches: &[(usize, &Benchmark)],
+    config: &Config,
+) -> Result<Vec<TextSize>, Error> {
+    let mut files = vec![(
+        matrix::bench_target(executable)
+            .unwrap_or(executable)
+            .to_owned(),
+        executable,
+    )];
+    for (_, bench) in benches {
+        if let Some(extern_c) = bench.extern_c {
+            if !files.iter().any(|(_, path)| *path == extern_c.library) {
+                files.push((extern_c.library.to_owned(), extern_c.library));
+            }
+        }
+    }
+
+    let file = config.results_dir().join("text-size");
+    let previous = fs::read_to_string(&file).unwrap_or_default();
+    let old = |name: &str| {
+        previous.lines().find_map(|line| {
+            let (file, bytes) = line.rsplit_once(' ')?;
+            (file == name).then(|| bytes.parse().ok())?
+        })
+    };
+    let sizes: Vec<TextSize> = files
+        .into_iter()
+        .filter_map(|(name, path)| {
+            let bytes = text_size(Path::new(path))?;
+            Some(TextSize {
+                old: old(&name),
+                file: name,
+                bytes,
+            })
+        })
+        .collect();
+    let contents: String = sizes
+        .iter()
+        .map(|size| format!("{} {}\n", size.file, size.bytes))
+        .collect();
+    if !sizes.is_empty() {
+        let dir = config.results_dir();
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        write_atomically(&file, &contents).map_err(|e| Error::io(&file, e))?;
+    }
+    Ok(sizes)
+}
+
+/// The size of the `.text` section of the ELF file at `path`.
+fn text_size(path: &Path) -> Option<u64> {
+    let elf = fs::read(path).ok()?;
+    if elf.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+    let wide = match elf.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let little_endian = *elf.get(5)? == 1;
+    let int = |offset: usize, len: usize| -> Option<u64> {
+        let bytes = elf.get(offset..offset + len)?;
+        let fold = |value: u64, &byte: &u8| value << 8 | u64::from(byte);
+        Some(if little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    };
+    // Offsets of the section header table in the file header, and of the fields of a section header
+    let (table, entry_size, count, names, offset, size, word) = if wide {
+        (0x28, 0x3A, 0x3C, 0x3E, 0x18, 0x20, 8)
+    } else {
+        (0x20, 0x2E, 0x30, 0x32, 0x10, 0x14, 4)
+    };
+    let table = int(table, word)? as usize;
+    let entry_size = int(entry_size, 2)? as usize;
+    let count = int(count, 2)? as usize;
+    let section = |index: usize| -> Option<(u64, u64, u64)> {
+        let header = table + index * entry_size;
+        Some((
+            int(header, 4)?,
+            int(header + offset, word)?,
+            int(header + size, word)?,
+        ))
+    };
+    let (_, names_offset, _) = section(int(names, 2)? as usize)?;
+    (0..count).find_map(|index| {
+        let (name, _, size) = section(index)?;
+        let start = (names_offset + name) as usize;
+        let name = elf.get(start..)?.split(|&b| b == 0).next()?;
+        (name == b".text").then_some(size)
+    })
+}
@@ -0,0 +1,27 @@
+//! The cargo profile the benchmarks were built with. Instruction counts of an unoptimized build say
+//! little about those of an optimized one, so results are kept apart per profile, and a suite can
+//! refuse to be measured with the wrong one (`Config::require_profile`).
+
+use std::path::Path;
+
+/// The profile cargo builds benchmarks with by default; its results stay directly in `target/iai`.
+pub(crate) const DEFAULT: &str = "release";
+
+/// Tells the profile from where cargo put the executable, i.e. `target/<profile>/deps/<name>` or
+/// `target/<profile>/examples/<name>`, possibly with a target triple before the profile. Cargo
+/// names the directory of the `dev` and `test` profiles `debug`, and the one of `bench` `release`.
+///
+/// When the executable isn't in such a directory, the `debug_assertions` the benchmarks were
+/// compiled with (as recorded by `main!`) at least tell a `dev` build from an optimized one.
+pub(crate) fn detect(executable: &str, debug_assertions: Option<bool>) -> Option<String> {
+    let dir = Path::new(executable).parent()?;
+    let dir = match dir.file_name()?.to_str()? {
+        "deps" | "examples" => dir.parent(),
+        _ => None,
+    };
+    match dir.and_then(Path::file_name).and_then(|name| name.to_str()) {
+        Some("debug") => Some("dev".to_owned()),
+        Some(profile) => Some(profile.to_owned()),
+        None => debug_assertions.and_then(|enabled| enabled.then(|| "dev".to_owned())),
+    }
+}
@@ -0,0 +1,102 @@
+//! Benchmarks registered at runtime, for suites that `main!` can't spell out: a benchmark per
+//! file of a directory, per case of a table, or per input computed in a loop.
+//!
+//! ```no_run
+//! # struct Document;
+//! # impl Document {
+//! #     fn parse(_input: &str) -> Document {
+//! #         Document
+//! #     }
+//! # }
+//! # fn make_input(size: usize) -> String {
+//! #     "x".repeat(size)
+//! # }
+//! fn main() {
+//!     let mut iai = iai::Iai::new();
+//!     for size in [10, 1000, 100_000] {
+//!         let input = make_input(size);
+//!         iai.bench(format!("parse/{}", size), move || Document::parse(&input));
+//!     }
+//!     iai.run();
+//! }
+//! ```
+//!
+//! The benchmark processes build the same suite again and look their benchmark up by name, so the
+//! names have to be unique and mustn't depend on anything that changes between processes. The
+//! work done before [`Iai::run`], like building the inputs above, is part of every measurement but
+//! subtracted with the calibration; expensive inputs are better built inside the benchmark and
+//! excluded with [`bench_with_setup`](crate::bench_with_setup).
+
+use std::fmt;
+
+use crate::{black_box, runner_with_config, Benchmark, Config, Reporter};
+
+/// A benchmark suite whose benchmarks are closures, registered one by one with [`Iai::bench`].
+#[derive(Debug, Default)]
+pub struct Iai {
+    benches: Vec<Benchmark>,
+    config: Config,
+}
+
+/// The closure of a benchmark registered with [`Iai::bench`]. It lives as long as the bench
+/// executable does.
+#[derive(Clone, Copy)]
+pub(crate) struct Closure(&'static dyn Fn());
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Closure")
+    }
+}
+
+impl Closure {
+    pub(crate) fn call(&self) {
+        (self.0)()
+    }
+}
+
+impl Iai {
+    /// An empty suite with the default configuration.
+    pub fn new() -> Iai {
+        Iai::default()
+    }
+
+    /// An empty suite with suite-wide defaults, as `main!(config = ...; ...)` takes them.
+    pub fn with_config(config: Config) -> Iai {
+        Iai {
+            benches: Vec::new(),
+            config,
+        }
+    }
+
+    /// Adds a benchmark that calls `routine`. What it returns is passed through [`black_box`].
+    pub fn bench<R>(
+        &mut self,
+        name: impl Into<String>,
+        routine: impl Fn() -> R + 'static,
+    ) -> &mut Iai {
+        fn unused() {}
+        let name: &'static str = Box::leak(name.into().into_boxed_str());
+        let closure: &'static dyn Fn() = Box::leak(Box::new(move || {
+            let _ = black_box(routine());
+        }));
+        self.benches.push(Benchmark {
+            closure: Some(Closure(closure)),
+            ..Benchmark::new(name, unused)
+        });
+        self
+    }
+
+    /// Also reports the results to `reporter`, like [`Config::reporter`].
+    pub fn reporter(&mut self, reporter: impl Reporter + 'static) -> &mut Iai {
+        self.config.reporters.push(reporter);
+        self
+    }
+
+    /// Runs the suite, like the `main` of `main!` does: measures the benchmarks, or the one this
+    /// benchmark process was started for.
+    pub fn run(self) {
+        let benches: Vec<&Benchmark> = self.benches.iter().collect();
+        runner_with_config(&benches, self.config);
+    }
+}
@@ -0,0 +1,369 @@
+//! Parsing of cachegrind (and callgrind) output files, for reuse by other tools.
+//!
+//! Only the header (`desc:`, `cmd:` and `events:`) and the `summary:` line are read, i.e. the
+//! totals of the whole run, into a [`CachegrindStats`]. Which events are present depends on how
+//! cachegrind was run: `Ir` is always there, the cache events need `--cache-sim=yes` and the branch
+//! events need `--branch-sim=yes`. Anything else is kept by name.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::CachegrindStats;
+
+/// Events recorded by cachegrind's branch prediction simulation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct BranchEvents {
+    /// `Bc`
+    pub(crate) conditional: u64,
+    /// `Bcm`
+    pub(crate) conditional_mispredicted: u64,
+    /// `Bi`
+    pub(crate) indirect: u64,
+    /// `Bim`
+    pub(crate) indirect_mispredicted: u64,
+}
+
+/// Why a cachegrind output file couldn't be parsed.
+#[derive(Debug)]
+pub enum CachegrindError {
+    /// The file couldn't be read.
+    Io(io::Error),
+    /// The file has no `events:` line.
+    MissingEvents,
+    /// The file has no `summary:` line, e.g. because the run was cut short.
+    MissingSummary,
+    /// A value in the `summary:` line isn't a count.
+    InvalidCount { event: String, value: String },
+    /// The `summary:` line has a different number of values than there are events.
+    CountMismatch { events: usize, counts: usize },
+    /// An event that is always recorded, or that belongs to a recorded group, is missing.
+    MissingEvent(&'static str),
+}
+
+impl fmt::Display for CachegrindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CachegrindError::Io(e) => write!(f, "unable to read cachegrind output: {}", e),
+            CachegrindError::MissingEvents => write!(f, "no events line in cachegrind output"),
+            CachegrindError::MissingSummary => write!(f, "no summary line in cachegrind output"),
+            CachegrindError::InvalidCount { event, value } => {
+                write!(f, "invalid count {:?} for event {}", value, event)
+            }
+            CachegrindError::CountMismatch { events, counts } => write!(
+                f,
+                "summary has {} counts, but there are {} events",
+                counts, events
+            ),
+            CachegrindError::MissingEvent(event) => {
+                write!(f, "event {} missing from cachegrind output", event)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CachegrindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CachegrindError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CachegrindError {
+    fn from(e: io::Error) -> Self {
+        CachegrindError::Io(e)
+    }
+}
+
+pub(crate) const CACHE_EVENTS: [&str; 8] =
+    ["I1mr", "ILmr", "Dr", "D1mr", "DLmr", "Dw", "D1mw", "DLmw"];
+pub(crate) const BRANCH_EVENTS: [&str; 4] = ["Bc", "Bcm", "Bi", "Bim"];
+
+/// Reads the totals out of the cachegrind output file at `path`, like
+/// [`CachegrindStats::from_file`] without the metadata.
+pub fn parse_cachegrind_output(path: impl AsRef<Path>) -> Result<CachegrindStats, CachegrindError> {
+    CachegrindStats::from_file(path).map(|(stats, _)| stats)
+}
+
+/// Checks that `counts` has `Ir`, which is always recorded, and all of the events of every group
+/// that has any of them recorded.
+pub(crate) fn check_events(counts: &HashMap<String, u64>) -> Result<(), CachegrindError> {
+    if !counts.contains_key("Ir") {
+        return Err(CachegrindError::MissingEvent("Ir"));
+    }
+    // A group counts as recorded as soon as any of its events is; the rest must be there too
+    for group in [&CACHE_EVENTS[..], &BRANCH_EVENTS[..]] {
+        if group.iter().any(|event| counts.contains_key(*event)) {
+            if let Some(event) = group.iter().find(|event| !counts.contains_key(**event)) {
+                return Err(CachegrindError::MissingEvent(event));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What a cachegrind (or callgrind) output file says about the run, besides the counts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CachegrindMetadata {
+    /// The `desc:` lines, like the caches that were simulated.
+    pub desc: Vec<String>,
+    /// The command that was measured (`cmd:`).
+    pub cmd: Option<String>,
+    /// The recorded events, in the order of the file (`events:`).
+    pub events: Vec<String>,
+}
+
+/// Reads the totals of every event in the cachegrind output file at `path`, by name, along with
+/// the metadata of the file.
+pub(crate) fn read_file(
+    path: impl AsRef<Path>,
+) -> Result<(HashMap<String, u64>, CachegrindMetadata), CachegrindError> {
+    let mut metadata = CachegrindMetadata::default();
+    let mut events_line = None;
+    let mut summary_line = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if let Some(desc) = line.strip_prefix("desc: ") {
+            metadata.desc.push(desc.trim().to_owned());
+        }
+        if let Some(cmd) = line.strip_prefix("cmd: ") {
+            metadata.cmd = Some(cmd.trim().to_owned());
+        }
+        if let Some(line) = line.strip_prefix("events: ") {
+            events_line = Some(line.trim().to_owned());
+        }
+        if let Some(line) = line.strip_prefix("summary: ") {
+            summary_line = Some(line.trim().to_owned());
+        }
+        // Callgrind may only write the totals at the end of the file
+        if let Some(line) = line.strip_prefix("totals: ") {
+            summary_line.get_or_insert_with(|| line.trim().to_owned());
+        }
+    }
+    let events_line = events_line.ok_or(CachegrindError::MissingEvents)?;
+    let summary_line = summary_line.ok_or(CachegrindError::MissingSummary)?;
+
+    let names: Vec<&str> = events_line.split_whitespace().collect();
+    let values: Vec<&str> = summary_line.split_whitespace().collect();
+    if names.len() != values.len() {
+        return Err(CachegrindError::CountMismatch {
+            events: names.len(),
+            counts: values.len(),
+        });
+    }
+    let mut counts = HashMap::with_capacity(names.len());
+    for (name, value) in names.into_iter().zip(values) {
+        let count = value
+            .parse::<u64>()
+            .map_err(|_| CachegrindError::InvalidCount {
+                event: name.to_owned(),
+                value: value.to_owned(),
+            })?;
+        counts.insert(name.to_owned(), count);
+        metadata.events.push(name.to_owned());
+    }
+    Ok((counts, metadata))
+}
+
+/// The events of one function, summed over all of its lines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FunctionCost {
+    /// The source file the function is in, as cachegrind names it (`fl=`).
+    pub file: String,
+    /// The name of the function (`fn=`).
+    pub function: String,
+    /// The totals of the events, by name.
+    pub events: HashMap<String, u64>,
+}
+
+impl FunctionCost {
+    /// Instructions executed in the function itself (`Ir`).
+    pub fn instruction_reads(&self) -> u64 {
+        self.events.get("Ir").copied().unwrap_or(0)
+    }
+}
+
+/// Reads the cost of every function out of the cachegrind (or callgrind) output file at `path`,
+/// most instructions first. Callgrind's calls are left out, so each function only has the cost of
+/// its own code, like in `cg_annotate`.
+pub fn parse_cachegrind_functions(
+    path: impl AsRef<Path>,
+) -> Result<Vec<FunctionCost>, CachegrindError> {
+    let mut names = Vec::new();
+    let mut positions = 1;
+    // Callgrind compresses repeated names into `(id)` once they were given as `(id) name`
+    let mut compressed: HashMap<(char, String), String> = HashMap::new();
+    let mut expand = |kind: char, name: &str| match name.strip_prefix('(') {
+        Some(rest) => {
+            let (id, name) = rest.split_once(')').unwrap_or((rest, ""));
+            let name = name.trim();
+            let key = (kind, id.to_owned());
+            if name.is_empty() {
+                compressed.get(&key).cloned().unwrap_or_default()
+            } else {
+                compressed.insert(key, name.to_owned());
+                name.to_owned()
+            }
+        }
+        None => name.to_owned(),
+    };
+    let mut costs: HashMap<(String, String), Vec<u64>> = HashMap::new();
+    let (mut file, mut function) = (String::new(), String::new());
+    let mut skip_call = false;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if let Some(events) = line.strip_prefix("events: ") {
+            names = events.split_whitespace().map(str::to_owned).collect();
+        } else if let Some(line) = line.strip_prefix("positions: ") {
+            positions = line.split_whitespace().count();
+        } else if let Some(name) = line.strip_prefix("fl=") {
+            file = expand('f', name);
+        } else if let Some(name) = line.strip_prefix("fi=").or(line.strip_prefix("fe=")) {
+            // Inlined code, which is still part of the function's cost
+            expand('f', name);
+        } else if let Some(name) = line.strip_prefix("fn=") {
+            function = expand('n', name);
+        } else if let Some(name) = line.strip_prefix("cfi=").or(line.strip_prefix("cfl=")) {
+            expand('f', name);
+        } else if let Some(name) = line.strip_prefix("cfn=") {
+            expand('n', name);
+        } else if line.starts_with("calls=") {
+            // The next line is the inclusive cost of the call, which belongs to the callee
+            skip_call = true;
+        } else if line.starts_with(|c: char| c.is_ascii_digit() || "+-*".contains(c)) {
+            if std::mem::take(&mut skip_call) {
+                continue;
+            }
+            let cost = costs
+                .entry((file.clone(), function.clone()))
+                .or_insert_with(|| vec![0; names.len()]);
+            // Trailing zeros may be left out
+            let values = line.split_whitespace().skip(positions);
+            for ((total, event), value) in cost.iter_mut().zip(&names).zip(values) {
+                *total += value
+                    .parse::<u64>()
+                    .map_err(|_| CachegrindError::InvalidCount {
+                        event: event.clone(),
+                        value: value.to_owned(),
+                    })?;
+            }
+        }
+    }
+    if names.is_empty() {
+        return Err(CachegrindError::MissingEvents);
+    }
+    let mut functions: Vec<FunctionCost> = costs
+        .into_iter()
+        .map(|((file, function), cost)| FunctionCost {
+            file,
+            function,
+            events: names.iter().cloned().zip(cost).collect(),
+        })
+        .collect();
+    functions.sort_by(|a, b| {
+        b.instruction_reads()
+            .cmp(&a.instruction_reads())
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(
+        name: &str,
+        contents: &str,
+    ) -> Result<(HashMap<String, u64>, CachegrindMetadata), CachegrindError> {
+        let path = std::env::temp_dir().join(format!("iai-cachegrind-{}.out", name));
+        std::fs::write(&path, contents).unwrap();
+        let read = read_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn reads_counts_and_metadata() {
+        let (counts, metadata) = read(
+            "full",
+            "desc: I1 cache:         32768 B, 64 B, 8-way associative\n\
+             cmd: target/release/deps/bench --iai-run 0\n\
+             events: Ir Dr Dw\n\
+             fl=src/lib.rs\n\
+             fn=parse\n\
+             3 100 20 10\n\
+             summary: 100 20 10\n",
+        )
+        .unwrap();
+        assert_eq!(counts["Ir"], 100);
+        assert_eq!(counts["Dw"], 10);
+        assert_eq!(
+            metadata,
+            CachegrindMetadata {
+                desc: vec!["I1 cache:         32768 B, 64 B, 8-way associative".to_owned()],
+                cmd: Some("target/release/deps/bench --iai-run 0".to_owned()),
+                events: vec!["Ir".to_owned(), "Dr".to_owned(), "Dw".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_the_totals_of_callgrind() {
+        let (counts, _) = read("totals", "events: Ir\ntotals: 42\n").unwrap();
+        assert_eq!(counts["Ir"], 42);
+        // A summary takes precedence, wherever it is
+        let (counts, _) = read("both", "events: Ir\ntotals: 42\nsummary: 40\n").unwrap();
+        assert_eq!(counts["Ir"], 40);
+    }
+
+    #[test]
+    fn rejects_incomplete_output() {
+        assert!(matches!(
+            read("no-events", "summary: 100\n"),
+            Err(CachegrindError::MissingEvents)
+        ));
+        assert!(matches!(
+            read("no-summary", "events: Ir\n"),
+            Err(CachegrindError::MissingSummary)
+        ));
+        assert!(matches!(
+            read("mismatch", "events: Ir Dr\nsummary: 100\n"),
+            Err(CachegrindError::CountMismatch {
+                events: 2,
+                counts: 1
+            })
+        ));
+        match read("invalid", "events: Ir\nsummary: many\n") {
+            Err(CachegrindError::InvalidCount { event, value }) => {
+                assert_eq!((event.as_str(), value.as_str()), ("Ir", "many"))
+            }
+            other => panic!("{:?}", other),
+        }
+        let missing = std::env::temp_dir().join("iai-cachegrind-missing.out");
+        assert!(matches!(read_file(missing), Err(CachegrindError::Io(_))));
+    }
+
+    #[test]
+    fn checks_the_groups_of_events() {
+        let counts = |events: &[&str]| events.iter().map(|e| (e.to_string(), 1)).collect();
+        assert!(check_events(&counts(&["Ir"])).is_ok());
+        assert!(check_events(&counts(&["Ir", "Bc", "Bcm", "Bi", "Bim"])).is_ok());
+        assert!(matches!(
+            check_events(&counts(&["Dr"])),
+            Err(CachegrindError::MissingEvent("Ir"))
+        ));
+        assert!(matches!(
+            check_events(&counts(&["Ir", "Bc", "Bcm"])),
+            Err(CachegrindError::MissingEvent("Bi"))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+//! What the installed valgrind can do, probed once before running anything so that missing tools
+//! or options are reported up front rather than as a cryptic failure halfway through the suite.
+
+use std::process::{Command, Stdio};
+
+use crate::{backend::Backend, Config};
+
+/// The tools iai knows how to drive.
+const TOOLS: [&str; 4] = ["cachegrind", "callgrind", "dhat", "massif"];
+
+/// Options worth reporting, since whether they exist depends on the valgrind version.
+const OPTIONAL_FLAGS: [&str; 3] = ["--cache-sim", "--branch-sim", "--instr-at-start"];
+
+pub(crate) struct Capabilities {
+    version: String,
+    /// The `--help` output of every tool that could be started.
+    tools: Vec<(&'static str, String)>,
+}
+
+/// Parses versions like `3.19.0`, `3.22` or `3.23.0.GIT`; missing components are zero.
+pub(crate) fn parse_version(version: &str) -> Option<[u32; 3]> {
+    let mut parsed = [0; 3];
+    let mut components = version.trim().split(['.', '-']);
+    parsed[0] = components.next()?.parse().ok()?;
+    for slot in &mut parsed[1..] {
+        match components.next().map(str::parse) {
+            Some(Ok(n)) => *slot = n,
+            _ => break,
+        }
+    }
+    Some(parsed)
+}
+
+impl Capabilities {
+    pub(crate) fn probe() -> Capabilities {
+        let version = Command::new("valgrind")
+            .arg("--version")
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+            .map(|v| v.trim_start_matches("valgrind-").to_owned())
+            .unwrap_or_default();
+        let tools = TOOLS
+            .iter()
+            .filter_map(|&tool| {
+                let output = Command::new("valgrind")
+                    .arg(format!("--tool={}", tool))
+                    .arg("--help")
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()?;
+                output
+                    .status
+                    .success()
+                    .then(|| (tool, String::from_utf8_lossy(&output.stdout).into_owned()))
+            })
+            .collect();
+        Capabilities { version, tools }
+    }
+
+    /// Fails if the installed valgrind is older than `minimum`, or its version can't be told.
+    pub(crate) fn check_version(&self, minimum: [u32; 3]) -> Result<(), String> {
+        let [major, minor, patch] = minimum;
+        match parse_version(&self.version) {
+            Some(version) if version >= minimum => Ok(()),
+            Some(_) => Err(format!(
+                "this suite needs valgrind {}.{}.{} or newer, but {} is installed",
+                major, minor, patch, self.version
+            )),
+            None => Err(format!(
+                "this suite needs valgrind {}.{}.{} or newer, but the installed version ({:?}) \
+                 couldn't be determined",
+                major, minor, patch, self.version
+            )),
+        }
+    }
+
+    fn help(&self, tool: &str) -> Option<&str> {
+        self.tools
+            .iter()
+            .find(|(name, _)| *name == tool)
+            .map(|(_, help)| help.as_str())
+    }
+
+    pub(crate) fn has_tool(&self, tool: &str) -> bool {
+        self.help(tool).is_some()
+    }
+
+    /// Whether `tool` accepts `flag`, given without its value (e.g. `--branch-sim`).
+    pub(crate) fn supports(&self, tool: &str, flag: &str) -> bool {
+        self.help(tool).is_some_and(|help| help.contains(flag))
+    }
+
+    /// Checks that `tool` can be run with every option iai or the user is going to pass it,
+    /// returning a description of the first problem.
+    pub(crate) fn check(&self, tool: &str, flags: &[&str]) -> Result<(), String> {
+        if !self.has_tool(tool) {
+            return Err(format!(
+                "valgrind {} can't run the {} tool",
+                self.version, tool
+            ));
+        }
+        for flag in flags {
+            let name = flag.split('=').next().unwrap_or(flag);
+            if name.starts_with("--") && !self.supports(tool, name) {
+                return Err(format!(
+                    "{} in valgrind {} doesn't support {}",
+                    tool, self.version, name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the tool of backend `B` can be run with the options it would be given.
+    pub(crate) fn check_backend<B: Backend>(&self, config: &Config) -> Result<(), String> {
+        let args = B::args(config);
+        let flags: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.check(B::tool(config), &flags)
+    }
+
+    /// e.g. `valgrind 3.19.0: cachegrind (--cache-sim, --branch-sim), callgrind, dhat`
+    pub(crate) fn describe(&self) -> String {
+        let tools: Vec<String> = self
+            .tools
+            .iter()
+            .map(|(tool, help)| {
+                let flags: Vec<&str> = OPTIONAL_FLAGS
+                    .iter()
+                    .copied()
+                    .filter(|flag| help.contains(flag))
+                    .collect();
+                if flags.is_empty() {
+                    tool.to_string()
+                } else {
+                    format!("{} ({})", tool, flags.join(", "))
+                }
+            })
+            .collect();
+        format!("valgrind {}: {}", self.version, tools.join(", "))
+    }
+}
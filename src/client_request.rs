@@ -0,0 +1,90 @@
+//! Valgrind client requests: the magic instruction sequences through which a program running under
+//! valgrind talks to its tool, and which do nothing outside of it. With `--client-requests` the
+//! harness turns the instrumentation on right before the call of the benchmark and off right
+//! after, so that only the call itself is counted, rather than relying on subtracting the
+//! calibration run.
+//!
+//! This needs valgrind 3.22 or newer for cachegrind; callgrind has always supported it. Only
+//! x86_64 and aarch64 know the requests, elsewhere they are ignored.
+
+/// The argument that makes a benchmark process scope the measurement with client requests.
+pub(crate) const CHILD_ARG: &str = "--iai-client-requests";
+
+/// `VG_USERREQ_TOOL_BASE` of `valgrind.h`.
+const fn tool_base(a: u8, b: u8) -> u64 {
+    ((a as u64) << 24) | ((b as u64) << 16)
+}
+
+/// From `cachegrind.h`.
+const CACHEGRIND_START_INSTRUMENTATION: u64 = tool_base(b'C', b'G');
+const CACHEGRIND_STOP_INSTRUMENTATION: u64 = tool_base(b'C', b'G') + 1;
+/// From `callgrind.h`.
+const CALLGRIND_START_INSTRUMENTATION: u64 = tool_base(b'C', b'T') + 4;
+const CALLGRIND_STOP_INSTRUMENTATION: u64 = tool_base(b'C', b'T') + 5;
+
+/// Starts counting again after [`stop_measurement`]. Together they exclude a part of a benchmark,
+/// like building its input, from what is measured:
+///
+/// ```no_run
+/// # fn make_input() -> Vec<u32> {
+/// #     vec![3, 1, 2]
+/// # }
+/// fn bench_sort() {
+///     iai::stop_measurement();
+///     let mut input = make_input();
+///     iai::start_measurement();
+///     input.sort();
+/// }
+/// ```
+///
+/// Outside of valgrind, this does nothing.
+pub fn start_measurement() {
+    // Whichever tool doesn't know the request ignores it
+    request(CACHEGRIND_START_INSTRUMENTATION);
+    request(CALLGRIND_START_INSTRUMENTATION);
+}
+
+/// Stops counting until [`start_measurement`]. Outside of valgrind, this does nothing.
+pub fn stop_measurement() {
+    request(CACHEGRIND_STOP_INSTRUMENTATION);
+    request(CALLGRIND_STOP_INSTRUMENTATION);
+}
+
+#[cfg(target_arch = "x86_64")]
+fn request(code: u64) {
+    let args: [u64; 6] = [code, 0, 0, 0, 0, 0];
+    // The rotations add up to 128 bits, so they leave rdi as it was
+    unsafe {
+        std::arch::asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") args.as_ptr(),
+            inout("rdx") 0u64 => _,
+            options(nostack),
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn request(code: u64) {
+    let args: [u64; 6] = [code, 0, 0, 0, 0, 0];
+    // The rotations add up to 128 bits, so they leave x12 as it was
+    unsafe {
+        std::arch::asm!(
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            in("x4") args.as_ptr(),
+            inout("x3") 0u64 => _,
+            options(nostack),
+        );
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn request(_code: u64) {}
@@ -0,0 +1,113 @@
+//! Comparing against the `--json` output of another run (`--compare-with <path>`), e.g. one
+//! downloaded as an artifact of a CI job on the target branch, rather than against local results.
+//!
+//! Only the `ran` events are used, and of those only the benchmark name and its `stats`, which
+//! already have the calibration subtracted.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{CachegrindStats, Error};
+
+pub(crate) struct Comparison {
+    stats: HashMap<String, CachegrindStats>,
+}
+
+impl Comparison {
+    pub(crate) fn load(path: &Path) -> Result<Comparison, Error> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let mut stats = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let event: Value = serde_json::from_str(line).map_err(|_| {
+                Error::output(
+                    path,
+                    format!(
+                        "line {} isn't JSON; it should be the output of a run with `--json`",
+                        number + 1
+                    ),
+                )
+            })?;
+            if event["event"] != "ran" {
+                continue;
+            }
+            let name = event["benchmark"].as_str().map(str::to_owned);
+            let parsed = CachegrindStats::deserialize(&event["stats"]).ok();
+            match name.zip(parsed) {
+                Some((name, parsed)) => {
+                    stats.insert(name, parsed);
+                }
+                None => {
+                    return Err(Error::output(
+                        path,
+                        format!("line {} isn't a benchmark result from `--json`", number + 1),
+                    ))
+                }
+            }
+        }
+        if stats.is_empty() {
+            return Err(Error::output(
+                path,
+                "no benchmark results; it should be the output of a run with `--json`",
+            ));
+        }
+        Ok(Comparison { stats })
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&CachegrindStats> {
+        self.stats.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, contents: &str) -> Result<Comparison, Error> {
+        let path = std::env::temp_dir().join(format!("iai-compare-{}.json", name));
+        fs::write(&path, contents).unwrap();
+        let comparison = Comparison::load(&path);
+        fs::remove_file(&path).unwrap();
+        comparison
+    }
+
+    #[test]
+    fn loads_results_without_extra() {
+        let comparison = load(
+            "without-extra",
+            r#"{"schema":1,"event":"run","benchmark":"parse"}
+{"schema":1,"event":"ran","benchmark":"parse","stats":{"instruction_reads":1200,"data_reads":300}}"#,
+        )
+        .unwrap();
+        let stats = comparison.get("parse").unwrap();
+        assert_eq!(stats.instruction_reads(), 1200);
+        assert_eq!(stats.data_reads(), 300);
+        assert!(comparison.get("other").is_none());
+    }
+
+    #[test]
+    fn loads_its_own_output() {
+        let stats = CachegrindStats::from_events(
+            [("Ir", 1200), ("Bc", 40)]
+                .iter()
+                .map(|&(event, count)| (event.to_owned(), count))
+                .collect(),
+        );
+        let line = serde_json::json!({"event": "ran", "benchmark": "parse", "stats": stats});
+        let comparison = load("round-trip", &line.to_string()).unwrap();
+        assert_eq!(comparison.get("parse"), Some(&stats));
+    }
+
+    #[test]
+    fn rejects_what_isnt_json_output() {
+        assert!(matches!(
+            load("not-json", "parse: 1200"),
+            Err(Error::Output { .. })
+        ));
+        assert!(matches!(
+            load("no-results", r#"{"event":"run","benchmark":"parse"}"#),
+            Err(Error::Output { .. })
+        ));
+    }
+}
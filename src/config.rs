@@ -0,0 +1,465 @@
+//! Suite-wide defaults that live next to the benchmark definitions, set through
+//! `main!(config = ...; ...)`. Command-line arguments and environment variables still take
+//! precedence over them.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    build_profile,
+    capabilities::parse_version,
+    policy::{parse_clock, Tolerance},
+    reporter::{Reporter, Reporters},
+    template::Template,
+    Error,
+};
+
+/// A simulated cache hierarchy, as cachegrind's `--I1`, `--D1` and `--LL` flags. The exact sizes
+/// matter less than having fixed sizes, since otherwise cachegrind would take them from the CPU
+/// and make benchmark runs even more incomparable between machines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CacheProfile {
+    pub(crate) name: &'static str,
+    pub(crate) sim_args: [&'static str; 3],
+}
+
+/// The names of the simulated caches, in the order of [`CacheProfile::sim_args`].
+pub(crate) const CACHE_LEVELS: [&str; 3] = ["I1", "D1", "LL"];
+
+impl CacheProfile {
+    /// The profile with the cache `level` (`I1`, `D1` or `LL`) replaced by `geometry`, given as
+    /// `<size>,<associativity>,<line size>`, if that's valid.
+    pub(crate) fn with_level(mut self, level: &str, geometry: &str) -> Option<CacheProfile> {
+        let index = CACHE_LEVELS.iter().position(|&known| known == level)?;
+        let geometry = geometry.replace(' ', "");
+        let valid = geometry.split(',').count() == 3
+            && geometry.split(',').all(|n| n.parse::<u64>().is_ok());
+        if !valid {
+            return None;
+        }
+        let flag = format!("--{}={}", level, geometry);
+        if flag != self.sim_args[index] {
+            self.name = "custom";
+            // Leaked since profiles are `Copy`; there are at most three of these per run
+            self.sim_args[index] = Box::leak(flag.into_boxed_str());
+        }
+        Some(self)
+    }
+
+    /// The profile with the levels set in `IAI_I1`, `IAI_D1` and `IAI_LL` replaced.
+    pub(crate) fn with_env(self) -> Result<CacheProfile, Error> {
+        CACHE_LEVELS.iter().try_fold(self, |profile, level| {
+            let var = format!("IAI_{}", level);
+            match std::env::var(&var) {
+                Ok(geometry) => profile.with_level(level, &geometry).ok_or_else(|| {
+                    Error::Argument(format!(
+                        "invalid cache {:?} for {} (expected <size>,<associativity>,<line size>)",
+                        geometry, var
+                    ))
+                }),
+                Err(_) => Ok(profile),
+            }
+        })
+    }
+
+    /// The `<size>,<associativity>,<line size>` of each level.
+    pub(crate) fn geometry(&self) -> [&'static str; 3] {
+        self.sim_args
+            .map(|flag| flag.split_once('=').map_or(flag, |(_, geometry)| geometry))
+    }
+}
+
+pub(crate) const CACHE_PROFILES: &[CacheProfile] = &[
+    CacheProfile {
+        name: "default",
+        sim_args: ["--I1=32768,8,64", "--D1=32768,8,64", "--LL=8388608,16,64"],
+    },
+    // Desktop Skylake, e.g. the i7-6700K
+    CacheProfile {
+        name: "skylake",
+        sim_args: ["--I1=32768,8,64", "--D1=32768,8,64", "--LL=8388608,16,64"],
+    },
+    // One Zen 2 core complex, e.g. the Ryzen 7 3700X
+    CacheProfile {
+        name: "zen2",
+        sim_args: ["--I1=32768,8,64", "--D1=32768,8,64", "--LL=16777216,16,64"],
+    },
+    // One Zen 3 core complex, e.g. the Ryzen 7 5800X
+    CacheProfile {
+        name: "zen3",
+        sim_args: ["--I1=32768,8,64", "--D1=32768,8,64", "--LL=33554432,16,64"],
+    },
+    // A performance core and its cluster's L2 of the Apple M1, with its 128-byte lines
+    CacheProfile {
+        name: "apple-m1",
+        sim_args: [
+            "--I1=196608,6,128",
+            "--D1=131072,8,128",
+            "--LL=12582912,12,128",
+        ],
+    },
+    // The Cortex-A72 of e.g. the Raspberry Pi 4, whose last level is the shared 1 MiB L2
+    CacheProfile {
+        name: "cortex-a72",
+        sim_args: ["--I1=49152,3,64", "--D1=32768,2,64", "--LL=1048576,16,64"],
+    },
+];
+
+/// The profile called `name`, if there is one.
+pub(crate) fn cache_profile(name: &str) -> Option<CacheProfile> {
+    CACHE_PROFILES
+        .iter()
+        .find(|profile| profile.name == name)
+        .copied()
+}
+
+/// The names of the profiles, for error messages.
+pub(crate) fn cache_profile_names() -> String {
+    let names: Vec<&str> = CACHE_PROFILES.iter().map(|profile| profile.name).collect();
+    names.join(", ")
+}
+
+/// The cycles of an L1 hit, an LL hit and a RAM access unless configured otherwise.
+// Itamar Turner-Trauring's formula from https://pythonspeed.com/articles/consistent-benchmarking-in-ci/
+pub(crate) const CYCLE_WEIGHTS: [u64; 3] = [1, 5, 35];
+
+/// Parses the weights of `IAI_CYCLE_WEIGHTS`, as `<l1>,<ll>,<ram>` (e.g. `1,5,35`).
+pub(crate) fn parse_cycle_weights(s: &str) -> Option<[u64; 3]> {
+    let mut weights = s.split(',').map(|weight| weight.trim().parse().ok());
+    let parsed = [weights.next()??, weights.next()??, weights.next()??];
+    weights.next().is_none().then_some(parsed)
+}
+
+/// The valgrind tool that measures the benchmarks. Callgrind counts the same events as
+/// cachegrind, but its profiles (kept in `target/iai/callgrind.out.<name>`) also attribute them to
+/// functions along the call graph, for viewing in `callgrind_annotate` or KCachegrind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Tool {
+    Cachegrind,
+    Callgrind,
+}
+
+impl Tool {
+    pub(crate) fn parse(name: &str) -> Option<Tool> {
+        match name {
+            "cachegrind" => Some(Tool::Cachegrind),
+            "callgrind" => Some(Tool::Callgrind),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Tool::Cachegrind => "cachegrind",
+            Tool::Callgrind => "callgrind",
+        }
+    }
+
+    /// The option that leaves the instrumentation off until a client request turns it on.
+    pub(crate) fn instrumentation_off(self) -> &'static str {
+        match self {
+            Tool::Cachegrind => "--instr-at-start=no",
+            Tool::Callgrind => "--instr-atstart=no",
+        }
+    }
+}
+
+/// Something to hand the raw cachegrind (or callgrind) output of every benchmark to, once iai has
+/// parsed it.
+#[derive(Clone, Debug)]
+pub(crate) enum PostProcessor {
+    /// Called with the name of the benchmark and the path of the output.
+    Callback(fn(&str, &Path)),
+    /// Run with the path of the output as its last argument and the name of the benchmark in
+    /// `IAI_BENCHMARK`.
+    Command(Vec<String>),
+}
+
+/// Configuration of a benchmark suite, passed to [`main!`](crate::main):
+///
+/// ```no_run
+/// # mod suite {
+/// # fn bench_method1() {}
+/// # fn bench_method2() {}
+/// iai::main!(
+///     config = iai::Config::default().cache("skylake").threshold("2%");
+///     bench_method1, bench_method2
+/// );
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub(crate) cache: CacheProfile,
+    pub(crate) tool: Tool,
+    pub(crate) threshold: Option<Tolerance>,
+    /// Named thresholds, one of which can be picked with `--threshold-profile`.
+    pub(crate) threshold_profiles: Vec<(String, Tolerance)>,
+    pub(crate) min_instructions: Option<u64>,
+    pub(crate) min_valgrind: Option<[u32; 3]>,
+    pub(crate) required_profile: Option<String>,
+    /// Whether the benchmarks were compiled with debug assertions, if `main!` told.
+    pub(crate) debug_assertions: Option<bool>,
+    /// The cargo profile the benchmarks were built with, detected when the runner starts.
+    pub(crate) build_profile: Option<String>,
+    /// The named baseline to save the results as (`--save-baseline`).
+    pub(crate) save_baseline: Option<String>,
+    /// The named baseline to compare against instead of the previous run (`--baseline`).
+    pub(crate) compare_baseline: Option<String>,
+    /// The variant of the suite being measured by `--feature-matrix` or `--toolchains`, as a
+    /// directory like `features/default` that keeps its results apart from those of the others.
+    pub(crate) variant: Option<String>,
+    /// See [`CYCLE_WEIGHTS`].
+    pub(crate) cycle_weights: [u64; 3],
+    /// Instead of `target/iai`.
+    pub(crate) output_dir: Option<PathBuf>,
+    /// Passed to cachegrind before `CACHEGRIND_FLAGS`.
+    pub(crate) cachegrind_flags: Vec<String>,
+    pub(crate) allow_aslr: bool,
+    /// Also simulate branch prediction, with `--branch-sim=yes`.
+    pub(crate) branch_sim: bool,
+    /// Calibrate every benchmark against a run that dispatches to it but skips the call, instead
+    /// of against the shared calibration.
+    pub(crate) per_bench_calibration: bool,
+    /// Only count the call of the benchmark, by turning the instrumentation on and off around it.
+    pub(crate) client_requests: bool,
+    /// How many of the most expensive functions to report for every benchmark.
+    pub(crate) top_functions: Option<usize>,
+    /// Instead of the clock rate in `/proc/cpuinfo`, for estimating times.
+    pub(crate) clock_hz: Option<u64>,
+    pub(crate) post_processors: Vec<PostProcessor>,
+    /// The layout of the human-readable report, see [`TEMPLATE`](crate::template::TEMPLATE).
+    pub(crate) template: Option<Template>,
+    pub(crate) reporters: Reporters,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            cache: CACHE_PROFILES[0],
+            tool: Tool::Cachegrind,
+            threshold: None,
+            threshold_profiles: Vec::new(),
+            min_instructions: None,
+            min_valgrind: None,
+            required_profile: None,
+            debug_assertions: None,
+            build_profile: None,
+            save_baseline: None,
+            compare_baseline: None,
+            variant: None,
+            cycle_weights: CYCLE_WEIGHTS,
+            output_dir: None,
+            cachegrind_flags: Vec::new(),
+            allow_aslr: false,
+            branch_sim: false,
+            per_bench_calibration: false,
+            client_requests: false,
+            top_functions: None,
+            clock_hz: None,
+            post_processors: Vec::new(),
+            template: None,
+            reporters: Reporters::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The defaults, to be changed with the methods below.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Simulates the caches of the named CPU instead of the generic defaults, like
+    /// `--cpu-profile`. Known profiles are `default`, `skylake`, `zen2`, `zen3`, `apple-m1` and
+    /// `cortex-a72`.
+    pub fn cache(mut self, profile: &str) -> Config {
+        self.cache = cache_profile(profile).unwrap_or_else(|| {
+            panic!(
+                "Unknown cache profile {:?} (expected one of {})",
+                profile,
+                cache_profile_names()
+            )
+        });
+        self
+    }
+
+    /// Simulates caches of the given sizes, each as `<size>,<associativity>,<line size>` like
+    /// cachegrind's `--I1`, `--D1` and `--LL` (e.g. `"32768,8,64"`). `IAI_I1`, `IAI_D1` and
+    /// `IAI_LL` take precedence.
+    pub fn cache_sizes(mut self, i1: &str, d1: &str, ll: &str) -> Config {
+        for (level, geometry) in CACHE_LEVELS.iter().zip([i1, d1, ll]) {
+            self.cache = self.cache.with_level(level, geometry).unwrap_or_else(|| {
+                panic!(
+                    "Invalid {} cache {:?} (expected <size>,<associativity>,<line size>)",
+                    level, geometry
+                )
+            });
+        }
+        self
+    }
+
+    /// Measures with `cachegrind` (the default) or `callgrind`, like `--tool`.
+    pub fn tool(mut self, tool: &str) -> Config {
+        self.tool = Tool::parse(tool).unwrap_or_else(|| {
+            panic!("Unknown tool {:?} (expected cachegrind or callgrind)", tool)
+        });
+        self
+    }
+
+    /// Sets the default regression threshold, e.g. `"2%"` or `"1500"`, like `--threshold`.
+    pub fn threshold(mut self, tolerance: &str) -> Config {
+        self.threshold = Some(Tolerance::parse(tolerance).unwrap_or_else(|| {
+            panic!(
+                "Invalid threshold {:?} (expected e.g. \"2%\" or \"1500\")",
+                tolerance
+            )
+        }));
+        self
+    }
+
+    /// Defines a named threshold, used instead of the default one when the profile is picked with
+    /// `--threshold-profile <name>` (or `IAI_THRESHOLD_PROFILE`). This lets one suite be lenient
+    /// locally and strict in CI:
+    ///
+    /// ```
+    /// iai::Config::default()
+    ///     .threshold_profile("local", "10%")
+    ///     .threshold_profile("ci", "2%")
+    ///     .threshold_profile("release-gate", "0.5%");
+    /// ```
+    pub fn threshold_profile(mut self, name: &str, tolerance: &str) -> Config {
+        let tolerance = Tolerance::parse(tolerance).unwrap_or_else(|| {
+            panic!(
+                "Invalid threshold {:?} for profile {:?} (expected e.g. \"2%\" or \"1500\")",
+                tolerance, name
+            )
+        });
+        self.threshold_profiles
+            .retain(|(existing, _)| existing != name);
+        self.threshold_profiles.push((name.to_owned(), tolerance));
+        self
+    }
+
+    /// Exempts benchmarks below this many instructions from percentage thresholds, like
+    /// `--min-instructions`.
+    pub fn min_instructions(mut self, instructions: u64) -> Config {
+        self.min_instructions = Some(instructions);
+        self
+    }
+
+    /// Refuses to run with a valgrind older than `version`, e.g. `"3.20"`, for suites relying on
+    /// the options or accuracy of newer releases.
+    pub fn min_valgrind(mut self, version: &str) -> Config {
+        self.min_valgrind = Some(parse_version(version).unwrap_or_else(|| {
+            panic!(
+                "Invalid valgrind version {:?} (expected e.g. \"3.20\")",
+                version
+            )
+        }));
+        self
+    }
+
+    /// Refuses to measure benchmarks built with another cargo profile than `profile`, e.g.
+    /// `"release"`, so that an accidental `cargo bench --profile dev` doesn't produce numbers.
+    pub fn require_profile(mut self, profile: &str) -> Config {
+        self.required_profile = Some(profile.to_owned());
+        self
+    }
+
+    /// Estimates times at this clock rate, e.g. `"3.5GHz"`, instead of the one `/proc/cpuinfo`
+    /// advertises (which most AMD and ARM CPUs don't). `IAI_CLOCK_HZ` takes precedence.
+    pub fn clock(mut self, clock: &str) -> Config {
+        self.clock_hz = Some(parse_clock(clock).unwrap_or_else(|| {
+            panic!(
+                "Invalid clock rate {:?} (expected e.g. \"3.5GHz\" or \"3500000000\")",
+                clock
+            )
+        }));
+        self
+    }
+
+    /// Calls `callback` with the name of every benchmark and the path of its raw cachegrind (or
+    /// callgrind) output once it is measured, for analyses of its own like a custom cost model.
+    /// The file is replaced by the next run, so read or copy it right away.
+    pub fn post_process(mut self, callback: fn(&str, &Path)) -> Config {
+        self.post_processors.push(PostProcessor::Callback(callback));
+        self
+    }
+
+    /// Estimates the cycles of a benchmark with these costs of an L1 hit, an LL hit and a RAM
+    /// access instead of the default 1, 5 and 35, e.g. to match the latencies of the actual
+    /// hardware or the estimates of other tools. `IAI_CYCLE_WEIGHTS` takes precedence.
+    pub fn cycle_weights(mut self, l1: u64, ll: u64, ram: u64) -> Config {
+        self.cycle_weights = [l1, ll, ram];
+        self
+    }
+
+    /// Also simulates branch prediction, like `--branch-sim`, and reports the mispredicted
+    /// conditional and indirect branches.
+    pub fn branch_sim(mut self, enabled: bool) -> Config {
+        self.branch_sim = enabled;
+        self
+    }
+
+    /// Calibrates every benchmark against a run of its own, like `--per-bench-calibration`: one
+    /// that dispatches to the benchmark and does its setup, but skips the call. This subtracts the
+    /// overhead of each benchmark exactly, e.g. the dispatch to the later ones of a long list, at
+    /// the price of a second run per benchmark.
+    pub fn per_bench_calibration(mut self, enabled: bool) -> Config {
+        self.per_bench_calibration = enabled;
+        self
+    }
+
+    /// Counts only the call of every benchmark, like `--client-requests`: the harness has valgrind
+    /// turn the instrumentation on right before it and off right after, instead of subtracting
+    /// what a calibration run counted. Needs valgrind 3.22 or newer for cachegrind.
+    pub fn client_requests(mut self, enabled: bool) -> Config {
+        self.client_requests = enabled;
+        self
+    }
+
+    /// Reports the `count` functions of every benchmark that executed the most instructions, like
+    /// `--top-functions`, to tell where a change in the totals comes from.
+    pub fn top_functions(mut self, count: usize) -> Config {
+        self.top_functions = Some(count);
+        self
+    }
+
+    /// Also reports the results to `reporter`, besides the format of `--format`.
+    pub fn reporter(mut self, reporter: impl Reporter + 'static) -> Config {
+        self.reporters.push(reporter);
+        self
+    }
+
+    /// Records whether the benchmarks were compiled with debug assertions. Called by `main!`.
+    #[doc(hidden)]
+    pub fn debug_assertions(mut self, enabled: bool) -> Config {
+        self.debug_assertions = Some(enabled);
+        self
+    }
+
+    /// Where the results of the last run are kept: `target/iai` (or the `output-dir` of
+    /// `iai.toml`) for the default profile, and a subdirectory per profile for the others so that
+    /// they aren't compared with each other. The same goes for the variants measured by
+    /// `--feature-matrix` and `--toolchains`.
+    pub(crate) fn results_dir(&self) -> PathBuf {
+        let root = self
+            .output_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("target/iai"));
+        let dir = match self.build_profile.as_deref() {
+            Some(profile) if profile != build_profile::DEFAULT => root.join(profile),
+            _ => root,
+        };
+        match &self.variant {
+            Some(variant) => dir.join(variant),
+            None => dir,
+        }
+    }
+
+    /// Where the results saved with `--save-baseline <name>` are kept.
+    pub(crate) fn named_baseline_dir(&self, name: &str) -> PathBuf {
+        self.results_dir()
+            .join("baselines")
+            .join(name.replace(['/', '\\'], "_"))
+    }
+}
@@ -0,0 +1,123 @@
+//! Heap profiling with DHAT (`--dhat`), to report how much each benchmark allocates.
+//!
+//! The totals are read back from DHAT's JSON profile by summing over its program points:
+//! `tb`/`tbk` are the bytes and blocks allocated over the whole run, `gb`/`gbk` the ones live at
+//! the global heap peak.
+
+use std::path::Path;
+
+use crate::{
+    backend::{Backend, Metric},
+    Config, Error,
+};
+
+/// Heap usage of one benchmark.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct HeapStats {
+    pub(crate) total_bytes: u64,
+    pub(crate) total_blocks: u64,
+    pub(crate) peak_bytes: u64,
+    pub(crate) peak_blocks: u64,
+}
+
+impl Backend for HeapStats {
+    const KEY: &'static str = "heap";
+
+    fn tool(_: &Config) -> &'static str {
+        "dhat"
+    }
+
+    fn args(_: &Config) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn parse(file: &Path) -> Result<HeapStats, Error> {
+        let profile = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+        let sum = |key: &str| -> Result<u64, Error> {
+            let key = format!("\"{}\":", key);
+            profile
+                .match_indices(&key)
+                .map(|(start, _)| {
+                    let value = profile[start + key.len()..].trim_start();
+                    let end = value
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(value.len());
+                    value[..end]
+                        .parse::<u64>()
+                        .map_err(|_| Error::output(file, format!("invalid {} in DHAT output", key)))
+                })
+                .sum()
+        };
+        Ok(HeapStats {
+            total_bytes: sum("tb")?,
+            total_blocks: sum("tbk")?,
+            peak_bytes: sum("gb")?,
+            peak_blocks: sum("gbk")?,
+        })
+    }
+
+    fn subtract(&self, calibration: &HeapStats) -> HeapStats {
+        HeapStats {
+            total_bytes: self.total_bytes.saturating_sub(calibration.total_bytes),
+            total_blocks: self.total_blocks.saturating_sub(calibration.total_blocks),
+            peak_bytes: self.peak_bytes.saturating_sub(calibration.peak_bytes),
+            peak_blocks: self.peak_blocks.saturating_sub(calibration.peak_blocks),
+        }
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        vec![
+            Metric::new("total_bytes", Some("Heap Allocated"), self.total_bytes),
+            Metric::new("total_blocks", Some("Heap Blocks"), self.total_blocks),
+            Metric::new("peak_bytes", Some("Heap Peak"), self.peak_bytes),
+            Metric::new("peak_blocks", None, self.peak_blocks),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, contents: &str) -> Result<HeapStats, Error> {
+        let path = std::env::temp_dir().join(format!("iai-dhat-{}.json", name));
+        std::fs::write(&path, contents).unwrap();
+        let stats = HeapStats::parse(&path);
+        std::fs::remove_file(&path).unwrap();
+        stats
+    }
+
+    #[test]
+    fn sums_the_program_points() {
+        let stats = parse(
+            "profile",
+            r#"{"dhatFileVersion":2,"mode":"heap","pps":[
+                {"tb":1024,"tbk":2,"tl":100,"mb":1024,"mbk":2,"gb":512,"gbk":1,"fs":[1,2]},
+                {"tb": 96,"tbk": 3,"tl":5,"mb":64,"mbk":1,"gb":0,"gbk":0,"fs":[3]}
+            ],"ftbl":["[root]","alloc","parse"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            HeapStats {
+                total_bytes: 1120,
+                total_blocks: 5,
+                peak_bytes: 512,
+                peak_blocks: 1,
+            }
+        );
+        // Nothing was allocated
+        assert_eq!(
+            parse("empty", r#"{"pps":[]}"#).unwrap(),
+            HeapStats::default()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_counts() {
+        let error = parse("invalid", r#"{"pps":[{"tb":"many"}]}"#).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("invalid \"tb\": in DHAT output"));
+    }
+}
@@ -0,0 +1,152 @@
+//! What can go wrong while measuring. An error of a single benchmark, like a crash of its process
+//! or an output file that can't be parsed, only fails that benchmark: it is reported, and the rest
+//! of the suite still runs. Only the errors before anything is measured, and those of the
+//! calibration, fail the whole run.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use crate::CachegrindError;
+
+/// Why a run, or one benchmark of it, couldn't be measured.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An argument on the command line, or an environment variable, is invalid.
+    Argument(String),
+    /// The settings of `source`, like `iai.toml` or an attribute of a benchmark, are invalid.
+    Settings { source: String, problem: String },
+    /// The suite requires a build profile (see
+    /// [`Config::require_profile`](crate::Config::require_profile)) that the benchmarks weren't
+    /// built with.
+    WrongProfile {
+        required: String,
+        built: Option<String>,
+    },
+    /// The named baseline to compare against doesn't exist.
+    MissingBaseline { name: String, dir: PathBuf },
+    /// The benchmarks already run under an instrumenter like valgrind.
+    Instrumented(&'static str),
+    /// Valgrind isn't installed or can't be run.
+    ValgrindMissing,
+    /// The installed valgrind lacks something the configuration needs.
+    Unsupported(String),
+    /// A tool, like valgrind, couldn't be started.
+    Spawn {
+        tool: &'static str,
+        error: io::Error,
+    },
+    /// The benchmark process exited unsuccessfully, e.g. because the benchmark panicked.
+    Crashed {
+        tool: &'static str,
+        status: ExitStatus,
+        /// What the benchmark process wrote to stderr.
+        stderr: String,
+    },
+    /// The benchmark returned `Err`, with its `Debug` output.
+    Failed(String),
+    /// A file of the results couldn't be read, written or moved.
+    Io { path: PathBuf, error: io::Error },
+    /// A cachegrind output file couldn't be parsed.
+    Cachegrind {
+        path: PathBuf,
+        error: CachegrindError,
+    },
+    /// The output of another tool couldn't be parsed.
+    Output { path: PathBuf, problem: String },
+    /// The `post-process` command of `iai.toml` failed on the output of the benchmark.
+    PostProcessor { command: String, problem: String },
+}
+
+impl Error {
+    pub(crate) fn io(path: &Path, error: io::Error) -> Error {
+        Error::Io {
+            path: path.to_owned(),
+            error,
+        }
+    }
+
+    pub(crate) fn settings(source: impl fmt::Display, problem: impl Into<String>) -> Error {
+        Error::Settings {
+            source: source.to_string(),
+            problem: problem.into(),
+        }
+    }
+
+    pub(crate) fn output(path: &Path, problem: impl Into<String>) -> Error {
+        Error::Output {
+            path: path.to_owned(),
+            problem: problem.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Argument(problem) => f.write_str(problem),
+            Error::Settings { source, problem } => {
+                write!(f, "invalid settings in {}: {}", source, problem)
+            }
+            Error::WrongProfile { required, built } => write!(
+                f,
+                "this suite must be measured with the {} profile, but the benchmarks were built \
+                 with {}. Run `cargo bench --profile {}`",
+                required,
+                built.as_deref().unwrap_or("an unknown profile"),
+                required
+            ),
+            Error::MissingBaseline { name, dir } => write!(
+                f,
+                "there is no baseline named {:?} in {}; save one with `--save-baseline {}`",
+                name,
+                dir.display(),
+                name
+            ),
+            Error::Instrumented(tool) => write!(
+                f,
+                "already running under {}; the benchmarks would measure {} instead of the code \
+                 under test. Run `cargo bench` directly",
+                tool, tool
+            ),
+            Error::ValgrindMissing => write!(
+                f,
+                "valgrind can't be run. Install it (e.g. `apt install valgrind`) and make sure it \
+                 is on the $PATH"
+            ),
+            Error::Unsupported(problem) => f.write_str(problem),
+            Error::Spawn { tool, error } => write!(f, "unable to run {}: {}", tool, error),
+            Error::Crashed {
+                tool,
+                status,
+                stderr,
+            } => {
+                write!(f, "the benchmark process in {} failed ({})", tool, status)?;
+                match stderr.trim() {
+                    "" => Ok(()),
+                    stderr => write!(f, ":\n{}", stderr),
+                }
+            }
+            Error::Failed(error) => f.write_str(error),
+            Error::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Error::Cachegrind { path, error } => write!(f, "{}: {}", path.display(), error),
+            Error::Output { path, problem } => write!(f, "{}: {}", path.display(), problem),
+            Error::PostProcessor { command, problem } => {
+                write!(f, "post-processor {} failed: {}", command, problem)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn { error, .. } | Error::Io { error, .. } => Some(error),
+            Error::Cachegrind { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
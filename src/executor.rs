@@ -0,0 +1,55 @@
+//! A minimal executor for `async` benchmarks, which polls the future on the benchmark's own thread
+//! and parks it while the future waits. It has no reactor, so futures that need one (like tokio's
+//! sockets and timers) need their own runtime, passed as `#[iai(executor = ...)]`.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread. `#[iai]` on an `async fn` uses this, unless
+/// it is given another executor with `executor = ...`, and it can also drive a future in a
+/// benchmark by hand:
+///
+/// ```no_run
+/// # struct Response;
+/// # struct Client;
+/// # impl Client {
+/// #     async fn fetch(&self, _path: &str) -> Response {
+/// #         Response
+/// #     }
+/// # }
+/// # #[allow(non_upper_case_globals)]
+/// # static client: Client = Client;
+/// fn bench_fetch() -> Response {
+///     iai::block_on(client.fetch("/index.html"))
+/// }
+/// ```
+///
+/// The poll loop itself is measured along with the future, which costs a handful of instructions
+/// per poll.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
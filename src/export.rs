@@ -0,0 +1,280 @@
+//! Writing the results to files in other formats once all benchmarks have run, with
+//! `--export <format>:<path>`:
+//!
+//! - `csv` has one row per benchmark, with every cachegrind counter, the summarized accesses and
+//!   cycles, and how much each of them changed since the results they were compared against
+//!   (empty when there are none).
+//! - `html` is a self-contained page (in `target/iai/report/index.html` unless a path is given)
+//!   with the current and old instructions and cycles of every benchmark, their changes and bars
+//!   comparing the cycles.
+
+use std::path::PathBuf;
+
+use crate::{
+    backend::Backend,
+    policy::Metric,
+    report::{xml_escape, BenchResult},
+    write_atomically, CachegrindStats, Config, Error,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Export {
+    Csv(PathBuf),
+    Html(Option<PathBuf>),
+}
+
+impl Export {
+    /// Parses `<format>:<path>`, e.g. `csv:target/iai.csv`, or just `html`.
+    pub(crate) fn parse(s: &str) -> Option<Export> {
+        let (format, path) = match s.split_once(':') {
+            Some((_, "")) => return None,
+            Some((format, path)) => (format, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+        match format {
+            "csv" => Some(Export::Csv(path?)),
+            "html" => Some(Export::Html(path)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write(&self, results: &[BenchResult], config: &Config) -> Result<(), Error> {
+        let (path, contents) = match self {
+            Export::Csv(path) => (path.clone(), csv(results)),
+            Export::Html(path) => (
+                path.clone()
+                    .unwrap_or_else(|| config.results_dir().join("report/index.html")),
+                html(results),
+            ),
+        };
+        let path = &path;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        write_atomically(path, &contents).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// The summary columns; instructions are already among the counters.
+const SUMMARY: [Metric; 4] = [
+    Metric::L1Accesses,
+    Metric::L2Accesses,
+    Metric::RamAccesses,
+    Metric::Cycles,
+];
+
+fn csv(results: &[BenchResult]) -> String {
+    let mut columns = vec!["benchmark"];
+    let counters: Vec<&str> = match results.first() {
+        Some(result) => result.stats.metrics().iter().map(|m| m.key).collect(),
+        None => Vec::new(),
+    };
+    columns.extend(&counters);
+    columns.extend(SUMMARY.iter().map(|metric| metric.name()));
+    let deltas: Vec<String> = columns[1..]
+        .iter()
+        .map(|column| format!("{}_delta", column))
+        .collect();
+    columns.extend(deltas.iter().map(String::as_str));
+    columns.push("regressed");
+
+    let mut csv = columns.join(",");
+    csv.push('\n');
+    for result in results {
+        let values = |stats: &CachegrindStats| -> Vec<u64> {
+            let counters = stats.metrics().into_iter().map(|m| m.value);
+            counters
+                .chain(
+                    SUMMARY
+                        .iter()
+                        .map(|metric| metric.value(stats, result.cycle_weights)),
+                )
+                .collect()
+        };
+        let new = values(&result.stats);
+        let old = result.old.as_ref().map(values);
+        let mut row = vec![field(result.bench.name)];
+        row.extend(new.iter().map(u64::to_string));
+        row.extend(new.iter().enumerate().map(|(i, &value)| {
+            let old = match &old {
+                Some(old) => Some(old[i]),
+                // The committed baseline only has the instruction count
+                None if i == 0 => result.committed,
+                None => None,
+            };
+            old.map_or_else(String::new, |old| (value as i128 - old as i128).to_string())
+        }));
+        row.push(result.regression.is_some().to_string());
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn html(results: &[BenchResult]) -> String {
+    let widest = results
+        .iter()
+        .flat_map(|result| std::iter::once(result.summary()).chain(result.old_summary()))
+        .map(|summary| summary.cycles())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let change = |new: u64, old: Option<u64>| match old {
+        Some(old) if old == new => r#"<td class="same">No change</td>"#.to_owned(),
+        Some(old) => {
+            let pct = (new as f64 - old as f64) / old.max(1) as f64 * 100.0;
+            let class = if new > old { "up" } else { "down" };
+            format!(r#"<td class="{}">{:+.3}%</td>"#, class, pct)
+        }
+        None => "<td></td>".to_owned(),
+    };
+    let bar = |value: u64, class: &str| {
+        let width = value as f64 / widest as f64 * 100.0;
+        format!(
+            r#"<div class="bar {}" style="width:{:.2}%" title="{}"></div>"#,
+            class, width, value
+        )
+    };
+
+    let mut rows = String::new();
+    for result in results {
+        let (new_cycles, old_cycles) = (
+            result.summary().cycles(),
+            result.old_summary().map(|old| old.cycles()),
+        );
+        let old_instructions = result.old_instructions();
+        let optional = |value: Option<u64>| value.map_or_else(String::new, |v| v.to_string());
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td>{}<td>{}</td><td>{}</td>{}<td>{}{}</td></tr>\n",
+            xml_escape(result.bench.name),
+            result.stats.instruction_reads(),
+            optional(old_instructions),
+            change(result.stats.instruction_reads(), old_instructions),
+            new_cycles,
+            optional(old_cycles),
+            change(new_cycles, old_cycles),
+            bar(new_cycles, "new"),
+            old_cycles.map_or_else(String::new, |old| bar(old, "old")),
+        ));
+    }
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>iai report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 0.3em 0.8em; text-align: right; border-bottom: 1px solid #ddd; }}
+th:first-child, td:first-child {{ text-align: left; font-family: monospace; }}
+td:last-child {{ width: 20em; }}
+.up {{ color: #c0392b; }}
+.down {{ color: #27ae60; }}
+.same {{ color: #888; }}
+.bar {{ height: 0.5em; margin: 1px 0; }}
+.bar.new {{ background: #2980b9; }}
+.bar.old {{ background: #bbb; }}
+</style>
+</head>
+<body>
+<h1>iai report</h1>
+<p>{} benchmarks. Old values are those of the results the run was compared against.</p>
+<table>
+<tr><th>Benchmark</th><th>Instructions</th><th>Old</th><th>Change</th><th>Estimated Cycles</th><th>Old</th><th>Change</th><th>Cycles (new, old)</th></tr>
+{}</table>
+</body>
+</html>
+"#,
+        results.len(),
+        rows
+    )
+}
+
+/// Quotes `value` if it contains anything that would end the field.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Benchmark;
+
+    fn bench() {}
+
+    #[test]
+    fn parses_the_export_formats() {
+        assert_eq!(
+            Export::parse("csv:target/iai.csv"),
+            Some(Export::Csv(PathBuf::from("target/iai.csv")))
+        );
+        assert_eq!(Export::parse("csv"), None);
+        assert_eq!(Export::parse("csv:"), None);
+        assert_eq!(Export::parse("html"), Some(Export::Html(None)));
+        assert_eq!(
+            Export::parse("html:report.html"),
+            Some(Export::Html(Some(PathBuf::from("report.html"))))
+        );
+        assert_eq!(Export::parse("xlsx:results.xlsx"), None);
+    }
+
+    #[test]
+    fn writes_a_row_per_benchmark() {
+        let parse = Benchmark::new("parse, quoted \"twice\"", bench);
+        let print = Benchmark::new("print", bench);
+        let results = [
+            BenchResult::of(
+                &parse,
+                &[("Ir", 1100), ("Dr", 300)],
+                Some(&[("Ir", 1000), ("Dr", 300)]),
+            ),
+            BenchResult::of(&print, &[("Ir", 500), ("Dr", 100)], None),
+        ];
+        let csv = csv(&results);
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("benchmark,instruction_reads,instruction_l1_misses,"));
+        assert!(header.contains(",ram_accesses,cycles,instruction_reads_delta,"));
+        assert!(header.ends_with(",ram_accesses_delta,cycles_delta,regressed"));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "\"parse, quoted \"\"twice\"\"\",1100,0,0,300,0,0,0,0,0,1400,0,0,1400,\
+                 100,0,0,0,0,0,0,0,0,100,0,0,100,false"
+            )
+        );
+        assert_eq!(
+            lines.next(),
+            Some("print,500,0,0,100,0,0,0,0,0,600,0,0,600,,,,,,,,,,,,,,false")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn writes_a_page_with_bars_of_the_cycles() {
+        let parse = Benchmark::new("parse::<&str>", bench);
+        let print = Benchmark::new("print", bench);
+        let results = [
+            BenchResult::of(&parse, &[("Ir", 1000)], Some(&[("Ir", 500)])),
+            BenchResult::of(&print, &[("Ir", 250)], None),
+        ];
+        let page = html(&results);
+        assert!(page.contains("<p>2 benchmarks."));
+        assert!(page.contains(
+            "<tr><td>parse::&lt;&amp;str&gt;</td><td>1000</td><td>500</td>\
+             <td class=\"up\">+100.000%</td><td>1000</td><td>500</td><td class=\"up\">+100.000%</td>"
+        ));
+        // The bars are relative to the most cycles of any benchmark, old or new
+        assert!(page.contains(r#"<div class="bar new" style="width:100.00%" title="1000"></div>"#));
+        assert!(page.contains(r#"<div class="bar old" style="width:50.00%" title="500"></div>"#));
+        assert!(page.contains(
+            "<tr><td>print</td><td>250</td><td></td><td></td><td>250</td><td></td><td></td>\
+             <td><div class=\"bar new\" style=\"width:25.00%\" title=\"250\"></div></td></tr>"
+        ));
+    }
+}
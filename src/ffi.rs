@@ -0,0 +1,83 @@
+//! Loading `extern "C"` benchmark functions out of shared libraries at runtime.
+
+use crate::Error;
+
+/// A benchmark that calls `symbol` from the shared library at `library`. The symbol must have the
+/// signature `extern "C" fn()`.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExternC {
+    pub library: &'static str,
+    pub symbol: &'static str,
+}
+
+impl ExternC {
+    fn invalid(&self, problem: impl Into<String>) -> Error {
+        Error::settings(format!("the shared library {}", self.library), problem)
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+        use std::{
+            ffi::{CStr, CString},
+            os::raw::{c_char, c_int, c_void},
+        };
+
+        #[cfg_attr(any(target_os = "linux", target_os = "android"), link(name = "dl"))]
+        extern "C" {
+            fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+            fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+            fn dlerror() -> *mut c_char;
+        }
+
+        const RTLD_NOW: c_int = 2;
+
+        fn last_error() -> String {
+            // Safety: dlerror returns either null or a valid C string.
+            let error = unsafe { dlerror() };
+            if error.is_null() {
+                "unknown error".to_owned()
+            } else {
+                unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+            }
+        }
+
+        impl ExternC {
+            /// Opens the library and resolves the symbol. The library is never closed, since the
+            /// child process exits right after the benchmark anyway.
+            pub(crate) fn load(&self) -> Result<extern "C" fn(), Error> {
+                let library = CString::new(self.library)
+                    .map_err(|_| self.invalid("the library path contains a NUL byte"))?;
+                let symbol = CString::new(self.symbol)
+                    .map_err(|_| self.invalid("the symbol name contains a NUL byte"))?;
+                // Safety: both arguments are valid C strings. Loading the library runs its
+                // initializers, which the user asked for by registering it.
+                let handle = unsafe { dlopen(library.as_ptr(), RTLD_NOW) };
+                if handle.is_null() {
+                    return Err(self.invalid(format!("unable to load it: {}", last_error())));
+                }
+                let function = unsafe { dlsym(handle, symbol.as_ptr()) };
+                if function.is_null() {
+                    return Err(self.invalid(format!(
+                        "unable to find {} in it: {}",
+                        self.symbol,
+                        last_error()
+                    )));
+                }
+                // Safety: the benchmark was registered with the promise that the symbol is an
+                // `extern "C" fn()`.
+                Ok(unsafe { std::mem::transmute::<*mut c_void, extern "C" fn()>(function) })
+            }
+        }
+    } else {
+        impl ExternC {
+            pub(crate) fn load(&self) -> Result<extern "C" fn(), Error> {
+                Err(self.invalid(format!(
+                    "unable to load {} from it: shared libraries are only supported on Unix",
+                    self.symbol
+                )))
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Which benchmarks a run selects: those whose name contains one of the filters of the command
+//! line (or, with `--exact`, equals one), matches one of the glob patterns with a `*`, or matches
+//! one of the regular expressions of `--filter-regex`. Without any of them, all benchmarks run.
+//!
+//! The regular expressions are a small subset, enough to pick benchmarks by name: literals, `.`,
+//! `[...]` classes with ranges and `^` negation, `\d`, `\w` and `\s`, the `*`, `+`, `?` and `{n,m}`
+//! repetitions, `(...)` groups, `|` alternatives and the `^` and `$` anchors.
+
+use std::fmt;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Filter {
+    pub(crate) patterns: Vec<String>,
+    /// Whether the patterns without a `*` must equal the name, rather than be part of it.
+    pub(crate) exact: bool,
+    pub(crate) regexes: Vec<Regex>,
+}
+
+impl Filter {
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        if self.patterns.is_empty() && self.regexes.is_empty() {
+            return true;
+        }
+        self.patterns
+            .iter()
+            .any(|pattern| matches_pattern(name, pattern, self.exact))
+            || self.regexes.iter().any(|regex| regex.is_match(name))
+    }
+
+    /// The arguments that select the same benchmarks in another run of the suite.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = self.patterns.clone();
+        if self.exact {
+            args.push("--exact".to_owned());
+        }
+        for regex in &self.regexes {
+            args.push("--filter-regex".to_owned());
+            args.push(regex.source.clone());
+        }
+        args
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regexes = self
+            .regexes
+            .iter()
+            .map(|regex| format!("/{}/", regex.source));
+        let all: Vec<String> = self.patterns.iter().cloned().chain(regexes).collect();
+        f.write_str(&all.join(", "))
+    }
+}
+
+/// Whether `name` is selected by `pattern`: a part of it (all of it when `exact`), or, with `*` in
+/// it, a pattern for the whole name like `hashing::*`.
+fn matches_pattern(name: &str, pattern: &str, exact: bool) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return if exact {
+            name == pattern
+        } else {
+            name.contains(pattern)
+        };
+    };
+    let Some(mut name) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap();
+    for part in parts {
+        match name.find(part) {
+            Some(start) => name = &name[start + part.len()..],
+            None => return false,
+        }
+    }
+    name.ends_with(last)
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Regex {
+    source: String,
+    node: Node,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternatives(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+impl Regex {
+    pub(crate) fn parse(source: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let node = parser.alternatives()?;
+        match parser.peek() {
+            None => Ok(Regex {
+                source: source.to_owned(),
+                node,
+            }),
+            Some(c) => Err(format!("unexpected {:?} at {}", c, parser.pos)),
+        }
+    }
+
+    /// Whether the expression matches anywhere in `text`, unless anchored.
+    pub(crate) fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        (0..=text.len()).any(|start| !ends(&self.node, &text, start).is_empty())
+    }
+}
+
+/// The positions in `text` where a match of `node` starting at `pos` can end.
+fn ends(node: &Node, text: &[char], pos: usize) -> Vec<usize> {
+    let next = text.get(pos).copied();
+    let single = |matches: bool| if matches { vec![pos + 1] } else { Vec::new() };
+    match node {
+        Node::Char(c) => single(next == Some(*c)),
+        Node::Any => single(next.is_some()),
+        Node::Class { negated, ranges } => {
+            single(next.is_some_and(|c| {
+                ranges.iter().any(|&(low, high)| low <= c && c <= high) != *negated
+            }))
+        }
+        Node::Start => {
+            if pos == 0 {
+                vec![pos]
+            } else {
+                Vec::new()
+            }
+        }
+        Node::End => {
+            if pos == text.len() {
+                vec![pos]
+            } else {
+                Vec::new()
+            }
+        }
+        Node::Concat(nodes) => nodes.iter().fold(vec![pos], |positions, node| {
+            let mut next: Vec<usize> = positions
+                .into_iter()
+                .flat_map(|pos| ends(node, text, pos))
+                .collect();
+            next.sort_unstable();
+            next.dedup();
+            next
+        }),
+        Node::Alternatives(nodes) => {
+            let mut all: Vec<usize> = nodes
+                .iter()
+                .flat_map(|node| ends(node, text, pos))
+                .collect();
+            all.sort_unstable();
+            all.dedup();
+            all
+        }
+        Node::Repeat { node, min, max } => {
+            let mut all = Vec::new();
+            let mut current = vec![pos];
+            let mut count = 0;
+            loop {
+                if count >= *min {
+                    all.extend(current.iter().copied());
+                }
+                if max.is_some_and(|max| count >= max) || current.is_empty() {
+                    break;
+                }
+                let mut next: Vec<usize> = current
+                    .iter()
+                    .flat_map(|&pos| ends(node, text, pos))
+                    // Once past the minimum, repeating an empty match gets nowhere
+                    .filter(|next| count < *min || !all.contains(next))
+                    .collect();
+                next.sort_unstable();
+                next.dedup();
+                current = next;
+                count += 1;
+            }
+            all.sort_unstable();
+            all.dedup();
+            all
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn alternatives(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alternatives(alternatives)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => self.counts()?,
+                _ => {
+                    nodes.push(atom);
+                    continue;
+                }
+            };
+            if matches!(atom, Node::Start | Node::End) {
+                return Err(format!("nothing to repeat at {}", start));
+            }
+            if self.pos == start {
+                self.pos += 1;
+            }
+            nodes.push(Node::Repeat {
+                node: Box::new(atom),
+                min,
+                max,
+            });
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    /// A repetition like `{3}`, `{2,}` or `{2,5}`.
+    fn counts(&mut self) -> Result<(usize, Option<usize>), String> {
+        let start = self.pos;
+        let end = (start..self.chars.len())
+            .find(|&i| self.chars[i] == '}')
+            .ok_or_else(|| format!("unclosed {{ at {}", start))?;
+        let counts: String = self.chars[start + 1..end].iter().collect();
+        let count = |count: &str| {
+            count
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid repetition {{{}}} at {}", counts, start))
+        };
+        let (min, max) = match counts.split_once(',') {
+            None => (count(&counts)?, Some(count(&counts)?)),
+            Some((min, "")) => (count(min)?, None),
+            Some((min, max)) => (count(min)?, Some(count(max)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid repetition {{{}}} at {}", counts, start));
+        }
+        self.pos = end + 1;
+        Ok((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let start = self.pos;
+        match self.bump().unwrap() {
+            '(' => {
+                let node = self.alternatives()?;
+                match self.bump() {
+                    Some(')') => Ok(node),
+                    _ => Err(format!("unclosed ( at {}", start)),
+                }
+            }
+            '[' => self.class(start),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '*' | '+' | '?' => Err(format!("nothing to repeat at {}", start)),
+            '\\' => match self.bump() {
+                Some(c) => Ok(
+                    escape(c).map_or(Node::Char(c), |(negated, ranges)| Node::Class {
+                        negated,
+                        ranges,
+                    }),
+                ),
+                None => Err("trailing \\".to_owned()),
+            },
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn class(&mut self, start: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let low = match self.bump() {
+                None => return Err(format!("unclosed [ at {}", start)),
+                Some(']') if !first => break,
+                Some('\\') => match self.bump() {
+                    Some(c) => match escape(c) {
+                        Some((false, class)) => {
+                            ranges.extend(class);
+                            first = false;
+                            continue;
+                        }
+                        Some((true, _)) => {
+                            return Err(format!("negated \\{} inside [] at {}", c, self.pos - 2))
+                        }
+                        None => c,
+                    },
+                    None => return Err("trailing \\".to_owned()),
+                },
+                Some(c) => c,
+            };
+            first = false;
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            if is_range {
+                self.pos += 1;
+                let high = self.bump().unwrap();
+                if high < low {
+                    return Err(format!(
+                        "invalid range {}-{} at {}",
+                        low,
+                        high,
+                        self.pos - 3
+                    ));
+                }
+                ranges.push((low, high));
+            } else {
+                ranges.push((low, low));
+            }
+        }
+        Ok(Node::Class { negated, ranges })
+    }
+}
+
+/// The class of `\d`, `\w`, `\s` and their negations `\D`, `\W` and `\S`.
+fn escape(c: char) -> Option<(bool, Vec<(char, char)>)> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+    Some((c.is_ascii_uppercase(), ranges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(source: &str, text: &str) -> bool {
+        Regex::parse(source).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("fib_[0-9]+", "fib_20"));
+        assert!(!is_match("fib_[0-9]+", "fib_x"));
+        assert!(is_match("[^a-z]", "abc1"));
+        assert!(!is_match("^[^a-z]+$", "abc1"));
+        assert!(is_match(r"^\w+::\d$", "parsing::7"));
+        assert!(is_match(r"\s", "a b"));
+        assert!(!is_match(r"^\S+$", "a b"));
+        assert!(is_match(r"[\d_]x", "_x"));
+        assert!(is_match("[a-]", "-"));
+        assert!(is_match("[]]", "]"));
+        assert!(is_match("a.c", "abc"));
+        assert!(!is_match("a.c", "ac"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("^fib", "fibonacci"));
+        assert!(!is_match("^fib", "bench_fibonacci"));
+        assert!(is_match("long$", "bench_fibonacci_long"));
+        assert!(!is_match("long$", "long_bench"));
+        assert!(is_match("^$", ""));
+        assert!(!is_match("^$", "x"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(is_match("^(parse|print)_json$", "print_json"));
+        assert!(!is_match("^(parse|print)_json$", "emit_json"));
+        assert!(is_match("^json|xml$", "to_xml"));
+        assert!(is_match("a|", "b"));
+    }
+
+    #[test]
+    fn repetition() {
+        assert!(is_match("^ab*c$", "ac"));
+        assert!(is_match("^ab*c$", "abbbc"));
+        assert!(!is_match("^ab+c$", "ac"));
+        assert!(is_match("^colou?r$", "color"));
+        assert!(!is_match("^colou?r$", "colouur"));
+        assert!(is_match(r"^\d{3}$", "123"));
+        assert!(!is_match(r"^\d{3}$", "1234"));
+        assert!(is_match("^a{2,}$", "aaaa"));
+        assert!(!is_match("^a{2,}$", "a"));
+        assert!(is_match("^(ab){1,2}$", "abab"));
+        assert!(!is_match("^(ab){1,2}$", "ababab"));
+        // Repeating what can match nothing still ends
+        assert!(is_match("^(a*)*b$", "aab"));
+        assert!(!is_match("^(a*)*b$", "aac"));
+    }
+
+    #[test]
+    fn bad_patterns() {
+        for (source, error) in [
+            ("(ab", "unclosed ( at 0"),
+            ("ab)", "unexpected ')' at 2"),
+            ("[ab", "unclosed [ at 0"),
+            ("*a", "nothing to repeat at 0"),
+            ("a|+", "nothing to repeat at 2"),
+            ("^*", "nothing to repeat at 1"),
+            ("a{2", "unclosed { at 1"),
+            ("a{x}", "invalid repetition {x} at 1"),
+            ("a{3,2}", "invalid repetition {3,2} at 1"),
+            ("[z-a]", "invalid range z-a at 1"),
+            (r"[\D]", r"negated \D inside [] at 1"),
+            ("a\\", "trailing \\"),
+        ] {
+            assert_eq!(Regex::parse(source).unwrap_err(), error, "{}", source);
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        let filter = |patterns: &[&str], exact| Filter {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            exact,
+            regexes: Vec::new(),
+        };
+        assert!(filter(&[], false).matches("anything"));
+        assert!(filter(&["fib"], false).matches("bench_fibonacci"));
+        assert!(!filter(&["fib"], true).matches("bench_fibonacci"));
+        assert!(filter(&["bench_fibonacci"], true).matches("bench_fibonacci"));
+        assert!(filter(&["hashing::*"], false).matches("hashing::sha256"));
+        assert!(!filter(&["hashing::*"], false).matches("parsing::hashing::sha256"));
+        assert!(filter(&["*::sha*"], true).matches("hashing::sha256"));
+        assert!(!filter(&["a*b*c"], false).matches("acb"));
+    }
+}
@@ -0,0 +1,208 @@
+//! The outputs of `--format`, as the [`Reporter`]s the runner installs ahead of those of the suite.
+//! The pretty and the JSON output report every benchmark as it finishes, the others only once they
+//! have all of the results.
+
+use crate::{
+    binary_size::TextSize,
+    config::{CacheProfile, CYCLE_WEIGHTS},
+    json::{self, RunEvent},
+    policy::Policy,
+    report::{self, Detail},
+    scaling, BenchResult, Config, Error, Reporter, SuiteSummary,
+};
+
+/// Benchmarks that failed are told apart on stderr, which the outputs besides JSON leave alone.
+fn print_failure(name: &str, error: &Error) {
+    eprintln!("iai: {} failed: {}", name, error);
+}
+
+/// The human-readable output: the detailed report of every benchmark, then the subtotals and
+/// totals, and what it says at the end about the benchmarks it left out.
+pub(crate) struct Pretty {
+    detail: Detail,
+    verbose: bool,
+    only_changed: bool,
+    /// Whether the benchmarks are reported as they finish, rather than once they are sorted. Those
+    /// of groups still wait for the subtotals of the groups, which come first.
+    streaming: bool,
+    policy: Policy,
+    cache: Option<CacheProfile>,
+    cycle_weights: Option<[u64; 3]>,
+    skipped_ignored: usize,
+    text_sizes: Vec<TextSize>,
+    hidden: usize,
+    trivial: Vec<String>,
+}
+
+impl Pretty {
+    /// The flags are `--verbose`, `--only-changed` and whether to report the benchmarks as they
+    /// finish.
+    pub(crate) fn new(
+        config: &Config,
+        policy: Policy,
+        detail: Detail,
+        (verbose, only_changed, streaming): (bool, bool, bool),
+        skipped_ignored: usize,
+        text_sizes: Vec<TextSize>,
+    ) -> Pretty {
+        Pretty {
+            detail,
+            verbose,
+            only_changed,
+            streaming,
+            policy,
+            cache: (verbose || config.cache.name != "default").then_some(config.cache),
+            cycle_weights: (verbose || config.cycle_weights != CYCLE_WEIGHTS)
+                .then_some(config.cycle_weights),
+            skipped_ignored,
+            text_sizes,
+            hidden: 0,
+            trivial: Vec::new(),
+        }
+    }
+
+    /// Prints the report of `result`, unless it is hidden by `--only-changed` or too small to be
+    /// worth one.
+    fn bench(&mut self, result: &BenchResult) {
+        let instructions = result.stats.instruction_reads();
+        if self.only_changed && !result.changed(&self.policy) {
+            self.hidden += 1;
+        } else if self.policy.is_trivial(instructions) {
+            self.trivial
+                .push(format!("{} ({})", result.bench.name, instructions));
+        } else {
+            report::print_bench(result, self.detail, self.verbose);
+        }
+    }
+
+    /// Sums up the benchmarks that got no report.
+    fn print_omitted(&self) {
+        if !self.trivial.is_empty() {
+            println!(
+                "Below {} instructions: {}\n",
+                self.policy.min_instructions().unwrap_or_default(),
+                self.trivial.join(", ")
+            );
+        }
+        if self.hidden > 0 {
+            println!("{} unchanged benchmarks hidden\n", self.hidden);
+        }
+        if self.skipped_ignored > 0 {
+            println!(
+                "{} ignored benchmarks skipped, run them with --include-ignored\n",
+                self.skipped_ignored
+            );
+        }
+    }
+}
+
+impl Reporter for Pretty {
+    fn on_suite_start(&mut self, _benchmarks: &[&str]) {
+        if let Some(cache) = &self.cache {
+            report::print_cache(cache);
+        }
+        if let Some(cycle_weights) = self.cycle_weights {
+            report::print_cycle_weights(cycle_weights);
+        }
+    }
+
+    fn on_bench_result(&mut self, result: &BenchResult) {
+        if self.streaming && report::group_of(result.bench.name).is_none() {
+            self.bench(result);
+        }
+    }
+
+    fn on_bench_failed(&mut self, name: &str, error: &Error) {
+        print_failure(name, error);
+    }
+
+    fn on_suite_results(&mut self, results: &[BenchResult], _summary: &SuiteSummary) {
+        // The subtotals need all of the results, so the benchmarks of groups are only reported
+        // once they are known
+        report::print_groups(results, self.detail);
+        for result in results {
+            if !self.streaming || report::group_of(result.bench.name).is_some() {
+                self.bench(result);
+            }
+        }
+        self.print_omitted();
+        scaling::print_scaling(results);
+        report::print_text_sizes(&self.text_sizes);
+        report::print_totals(results, self.detail);
+    }
+}
+
+/// One JSON object per line, see [`json`].
+pub(crate) struct Json {
+    text_sizes: Vec<TextSize>,
+}
+
+impl Json {
+    pub(crate) fn new(text_sizes: Vec<TextSize>) -> Json {
+        Json { text_sizes }
+    }
+}
+
+impl Reporter for Json {
+    fn on_bench_start(&mut self, name: &str) {
+        RunEvent::Run { benchmark: name }.print();
+    }
+
+    fn on_bench_result(&mut self, result: &BenchResult) {
+        result.print_json();
+    }
+
+    fn on_bench_failed(&mut self, name: &str, error: &Error) {
+        RunEvent::Failed {
+            benchmark: name,
+            error: &error.to_string(),
+        }
+        .print();
+    }
+
+    fn on_suite_results(&mut self, results: &[BenchResult], _summary: &SuiteSummary) {
+        report::groups_json(results);
+        scaling::scaling_json(results);
+        report::text_sizes_json(&self.text_sizes);
+        report::totals_json(results);
+    }
+}
+
+/// A GitHub-flavored markdown table, e.g. for PR comments.
+pub(crate) struct Markdown(pub(crate) Detail);
+
+impl Reporter for Markdown {
+    fn on_bench_failed(&mut self, name: &str, error: &Error) {
+        print_failure(name, error);
+    }
+
+    fn on_suite_results(&mut self, results: &[BenchResult], _summary: &SuiteSummary) {
+        print!("{}", report::markdown(results, self.0, false));
+    }
+}
+
+/// A JUnit XML test report, with regressed benchmarks as failed test cases.
+pub(crate) struct Junit;
+
+impl Reporter for Junit {
+    fn on_bench_failed(&mut self, name: &str, error: &Error) {
+        print_failure(name, error);
+    }
+
+    fn on_suite_results(&mut self, results: &[BenchResult], _summary: &SuiteSummary) {
+        print!("{}", report::junit(results));
+    }
+}
+
+/// Bencher's metric format, see [`json::bmf`].
+pub(crate) struct Bmf;
+
+impl Reporter for Bmf {
+    fn on_bench_failed(&mut self, name: &str, error: &Error) {
+        print_failure(name, error);
+    }
+
+    fn on_suite_results(&mut self, results: &[BenchResult], _summary: &SuiteSummary) {
+        println!("{}", json::bmf(results));
+    }
+}
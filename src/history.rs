@@ -0,0 +1,159 @@
+//! The instruction counts of past runs, for telling a change apart from the run-to-run variation
+//! seen so far (e.g. between repeated CI runs on different machines or toolchain patches).
+//!
+//! Every run adds the count of each benchmark to `target/iai/history`, as `<name> <instructions>`
+//! lines, keeping the last [`KEPT`] per benchmark; cache that file between CI runs to build it up.
+//! Once a benchmark has [`MIN_RUNS`] of them, a change is significant when it lies more than three
+//! standard deviations from their mean, and otherwise within the historical noise.
+
+use std::fs;
+
+use crate::{report::BenchResult, write_atomically, Config, Error};
+
+const KEPT: usize = 30;
+const MIN_RUNS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Significance {
+    /// The runs in the history.
+    pub(crate) runs: usize,
+    /// Three standard deviations of the counts in the history, as a percentage of their mean.
+    pub(crate) noise: f64,
+    pub(crate) significant: bool,
+}
+
+pub(crate) struct History {
+    samples: Vec<(String, u64)>,
+}
+
+impl History {
+    pub(crate) fn load(config: &Config) -> History {
+        let contents = fs::read_to_string(config.results_dir().join("history")).unwrap_or_default();
+        let samples = contents
+            .lines()
+            .filter_map(|line| {
+                let (name, instructions) = line.rsplit_once(' ')?;
+                Some((name.to_owned(), instructions.parse().ok()?))
+            })
+            .collect();
+        History { samples }
+    }
+
+    /// How the change of `result` compares with its history, if it changed and has enough of one.
+    pub(crate) fn significance(&self, result: &BenchResult) -> Option<Significance> {
+        let new = result.stats.instruction_reads();
+        if result.old_instructions()? == new {
+            return None;
+        }
+        let counts: Vec<f64> = self
+            .samples
+            .iter()
+            .filter(|(name, _)| name == result.bench.name)
+            .map(|&(_, instructions)| instructions as f64)
+            .collect();
+        if counts.len() < MIN_RUNS {
+            return None;
+        }
+        let mean = counts.iter().sum::<f64>() / counts.len() as f64;
+        let variance = counts
+            .iter()
+            .map(|count| (count - mean).powi(2))
+            .sum::<f64>()
+            / counts.len() as f64;
+        let band = 3.0 * variance.sqrt();
+        Some(Significance {
+            runs: counts.len(),
+            noise: band / mean.max(1.0) * 100.0,
+            significant: (new as f64 - mean).abs() > band,
+        })
+    }
+
+    /// Adds the counts of `results`, dropping the oldest beyond [`KEPT`], and stores the history.
+    pub(crate) fn record(mut self, results: &[BenchResult], config: &Config) -> Result<(), Error> {
+        for result in results {
+            let name = result.bench.name;
+            self.samples
+                .push((name.to_owned(), result.stats.instruction_reads()));
+            let runs = self
+                .samples
+                .iter()
+                .filter(|(known, _)| known == name)
+                .count();
+            for _ in KEPT..runs {
+                let oldest = self.samples.iter().position(|(known, _)| known == name);
+                self.samples.remove(oldest.unwrap());
+            }
+        }
+        let contents: String = self
+            .samples
+            .iter()
+            .map(|(name, instructions)| format!("{} {}\n", name, instructions))
+            .collect();
+        let dir = config.results_dir();
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        let file = dir.join("history");
+        write_atomically(&file, &contents).map_err(|e| Error::io(&file, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Benchmark;
+
+    fn bench() {}
+
+    fn history(counts: &[u64]) -> History {
+        History {
+            samples: counts
+                .iter()
+                .map(|&count| ("parse".to_owned(), count))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn tells_changes_from_the_noise() {
+        let parse = Benchmark::new("parse", bench);
+        let result = |new, old| BenchResult::of(&parse, &[("Ir", new)], Some(&[("Ir", old)]));
+        let history = history(&[1000, 1010, 990, 1000]);
+        let noise = history.significance(&result(1020, 1000)).unwrap();
+        assert_eq!(noise.runs, 4);
+        assert!(!noise.significant);
+        assert!((noise.noise - 3.0 * 50f64.sqrt() / 10.0).abs() < 1e-9);
+        assert!(
+            history
+                .significance(&result(1100, 1000))
+                .unwrap()
+                .significant
+        );
+        // Unchanged benchmarks, and those without a history, aren't judged
+        assert_eq!(history.significance(&result(1000, 1000)), None);
+        let print = Benchmark::new("print", bench);
+        let new = BenchResult::of(&print, &[("Ir", 1100)], Some(&[("Ir", 1000)]));
+        assert_eq!(history.significance(&new), None);
+        assert_eq!(
+            self::history(&[1000, 1010]).significance(&result(1100, 1000)),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_runs_of_every_benchmark() {
+        let config = Config {
+            output_dir: Some(std::env::temp_dir().join("iai-history")),
+            ..Config::default()
+        };
+        let parse = Benchmark::new("parse", bench);
+        let counts: Vec<u64> = (0..KEPT as u64 + 5).collect();
+        history(&counts)
+            .record(&[BenchResult::of(&parse, &[("Ir", 100)], None)], &config)
+            .unwrap();
+        let loaded = History::load(&config);
+        fs::remove_dir_all(config.results_dir()).unwrap();
+        let kept: Vec<u64> = loaded.samples.iter().map(|&(_, count)| count).collect();
+        assert_eq!(kept.len(), KEPT);
+        assert_eq!(kept.first(), Some(&6));
+        assert_eq!(kept.last(), Some(&100));
+    }
+}
@@ -0,0 +1,376 @@
+//! The `--json` output: one JSON object per line, each an event of the run. Every event carries
+//! the version of this layout as `schema` and says what it is as `event`:
+//!
+//! - `run` before a benchmark is measured, and `ran` with all its measurements afterwards
+//! - `group` with the subtotals of each benchmark group, `scaling` with the growth of each
+//!   parameter sweep and finally `totals` for the whole suite
+//!
+//! Fields that don't apply to a run, like the estimated time on a machine with an unknown clock
+//! rate, are `null` rather than left out.
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{
+    backend::Metric,
+    cachegrind::BranchEvents,
+    clock,
+    config::CacheProfile,
+    report::{BenchResult, FunctionChange},
+    CachegrindStats, EstimatedTime, FunctionCost, LayoutSpread,
+};
+
+/// Version of the layout of the events. Bump this whenever a field is removed, renamed or changes
+/// its meaning; adding fields is fine.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum RunEvent<'a> {
+    Run {
+        benchmark: &'a str,
+    },
+    Ran(Box<Ran<'a>>),
+    /// A benchmark that returned `Err`, with the error.
+    Failed {
+        benchmark: &'a str,
+        error: &'a str,
+    },
+    Group {
+        group: &'a str,
+        #[serde(flatten)]
+        summary: Summary,
+    },
+    Scaling {
+        benchmark: &'a str,
+        param: &'a str,
+        /// Parameter values and their instruction counts.
+        points: Vec<(f64, u64)>,
+        growth: &'static str,
+        old_growth: Option<&'static str>,
+    },
+    /// The size of the code in one of the files under test.
+    TextSize {
+        file: &'a str,
+        bytes: u64,
+        old_bytes: Option<u64>,
+    },
+    /// The timings of `--wall-clock-fallback`, in nanoseconds.
+    WallClock {
+        benchmark: &'a str,
+        runs: usize,
+        median_ns: f64,
+        mad_ns: f64,
+    },
+    Totals(Summary),
+}
+
+impl RunEvent<'_> {
+    pub(crate) fn print(&self) {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            schema: u32,
+            #[serde(flatten)]
+            event: &'a RunEvent<'a>,
+        }
+        let line = Line {
+            schema: SCHEMA_VERSION,
+            event: self,
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&line).expect("Failed to serialize JSON output")
+        );
+    }
+}
+
+/// Everything measured for one benchmark.
+#[derive(Serialize)]
+pub(crate) struct Ran<'a> {
+    pub(crate) benchmark: &'a str,
+    /// Numeric values are written as JSON numbers, so that they can be plotted directly.
+    #[serde(serialize_with = "params")]
+    pub(crate) params: Vec<(&'a str, &'a str)>,
+    pub(crate) stats: Stats<'a>,
+    pub(crate) old_stats: Option<Stats<'a>>,
+    /// The entry in the `--baseline-dir` file.
+    pub(crate) baseline: Option<Baseline>,
+    pub(crate) regressed: bool,
+    pub(crate) regression: Option<Regression<'a>>,
+    pub(crate) significance: Option<Significance>,
+    /// The cycles allowed by `#[iai(budget = "...")]`.
+    pub(crate) budget: Option<Budget>,
+    pub(crate) layout: Option<&'a LayoutSpread>,
+    /// The metrics of the other backends, each under its own key (e.g. `heap` and `old_heap`).
+    #[serde(flatten, serialize_with = "measurements")]
+    pub(crate) measurements: Vec<(String, &'a [Metric])>,
+    #[serde(serialize_with = "pairs")]
+    pub(crate) annotations: Vec<(&'a str, &'a str)>,
+    /// The items of `bench_iter`, with the cost per item.
+    pub(crate) items: Option<Items>,
+    pub(crate) threads: Option<u64>,
+    pub(crate) cpus: Option<&'a str>,
+    pub(crate) cache: Cache,
+    /// With `--top-functions`.
+    pub(crate) functions: Option<Vec<Function>>,
+    /// The functions that changed the most, if the instructions did.
+    pub(crate) function_changes: Option<&'a [FunctionChange]>,
+}
+
+/// One of the most expensive functions of a benchmark.
+#[derive(Serialize)]
+pub(crate) struct Function {
+    pub(crate) function: String,
+    pub(crate) file: String,
+    pub(crate) instruction_reads: u64,
+}
+
+impl Function {
+    pub(crate) fn new(cost: &FunctionCost) -> Function {
+        Function {
+            function: cost.function.clone(),
+            file: cost.file.clone(),
+            instruction_reads: cost.instruction_reads(),
+        }
+    }
+}
+
+/// The cachegrind metrics of one run, with the cycles and time estimated from them.
+#[derive(Serialize)]
+pub(crate) struct Stats<'a> {
+    #[serde(flatten)]
+    pub(crate) stats: &'a CachegrindStats,
+    pub(crate) cycles: u64,
+    #[serde(flatten)]
+    pub(crate) time: Time,
+    /// With `--branch-sim`.
+    pub(crate) branches: Option<BranchEvents>,
+}
+
+impl<'a> Stats<'a> {
+    pub(crate) fn new(stats: &'a CachegrindStats, cycle_weights: [u64; 3]) -> Stats<'a> {
+        let cycles = stats.summarize(cycle_weights).cycles();
+        Stats {
+            stats,
+            cycles,
+            time: Time::estimate(cycles),
+            branches: stats.branches(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Time {
+    time_passed: Option<f64>,
+    time_unit: Option<&'static str>,
+}
+
+impl Time {
+    pub(crate) fn estimate(cycles: u64) -> Time {
+        let estimate = EstimatedTime::estimate(cycles);
+        Time {
+            time_passed: estimate.as_ref().map(|time| time.value),
+            time_unit: estimate.map(|time| time.unit),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Baseline {
+    pub(crate) instruction_reads: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Regression<'a> {
+    pub(crate) metric: &'static str,
+    pub(crate) tolerance: String,
+    /// The note of the entry in the accepted-regressions file, if it was expected.
+    pub(crate) accepted: Option<&'a str>,
+}
+
+/// The simulated caches, each as `<size>,<associativity>,<line size>`.
+#[derive(Serialize)]
+pub(crate) struct Cache {
+    pub(crate) profile: &'static str,
+    #[serde(rename = "I1")]
+    pub(crate) i1: &'static str,
+    #[serde(rename = "D1")]
+    pub(crate) d1: &'static str,
+    #[serde(rename = "LL")]
+    pub(crate) ll: &'static str,
+}
+
+impl Cache {
+    pub(crate) fn new(profile: &CacheProfile) -> Cache {
+        let [i1, d1, ll] = profile.geometry();
+        Cache {
+            profile: profile.name,
+            i1,
+            d1,
+            ll,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Items {
+    pub(crate) count: u64,
+    pub(crate) instructions: f64,
+    pub(crate) cycles: f64,
+}
+
+/// How a change compares with the variation of the instruction count in the history.
+#[derive(Serialize)]
+pub(crate) struct Significance {
+    pub(crate) significant: bool,
+    /// Three standard deviations, as a percentage of the mean.
+    pub(crate) noise: f64,
+    pub(crate) runs: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Budget {
+    pub(crate) cycles: u64,
+    pub(crate) exceeded: bool,
+}
+
+/// Totals over a group of benchmarks, or the whole suite.
+#[derive(Serialize)]
+pub(crate) struct Summary {
+    pub(crate) benchmarks: usize,
+    pub(crate) instruction_reads: u64,
+    pub(crate) cycles: u64,
+    pub(crate) old_instruction_reads: Option<u64>,
+    pub(crate) old_cycles: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) time: Time,
+}
+
+impl Serialize for LayoutSpread {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Spread {
+            min: u64,
+            max: u64,
+            spread: f64,
+        }
+        let mut map = serializer.serialize_map(Some(self.metrics.len() + 1))?;
+        map.serialize_entry("runs", &self.runs)?;
+        for &(_, key, min, max) in &self.metrics {
+            let spread = LayoutSpread::spread(min, max);
+            map.serialize_entry(key, &Spread { min, max, spread })?;
+        }
+        map.end()
+    }
+}
+
+/// An object with the keys in the given order.
+fn pairs<S: Serializer, V: Serialize>(
+    pairs: &[(&str, V)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(pairs.len()))?;
+    for (key, value) in pairs {
+        map.serialize_entry(key, value)?;
+    }
+    map.end()
+}
+
+fn params<S: Serializer>(params: &[(&str, &str)], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(params.len()))?;
+    for &(key, value) in params {
+        match value.parse::<serde_json::Number>() {
+            Ok(number) if is_plain_number(value) => map.serialize_entry(key, &number)?,
+            _ => map.serialize_entry(key, value)?,
+        }
+    }
+    map.end()
+}
+
+/// Whether `s` is an integer or decimal, rather than something JSON merely accepts as a number
+/// (like `1e5`) or that only looks like one (like a zero-padded `007`).
+fn is_plain_number(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, "0"));
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    all_digits(int) && all_digits(frac) && (int == "0" || !int.starts_with('0'))
+}
+
+fn measurements<S: Serializer>(
+    measurements: &[(String, &[Metric])],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(measurements.len()))?;
+    for (key, metrics) in measurements {
+        let metrics: Vec<(&str, u64)> = metrics
+            .iter()
+            .map(|metric| (metric.key, metric.value))
+            .collect();
+        map.serialize_entry(key, &Metrics(&metrics))?;
+    }
+    map.end()
+}
+
+struct Metrics<'a>(&'a [(&'a str, u64)]);
+
+impl Serialize for Metrics<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        pairs(self.0, serializer)
+    }
+}
+
+/// The results in Bencher's metric format (BMF), one object per benchmark with a `{"value": ...}`
+/// per measure, for `bencher run --adapter json`. `latency` is the estimated time in nanoseconds,
+/// which is only known with the clock rate.
+pub(crate) fn bmf(results: &[BenchResult]) -> String {
+    let mut benchmarks = serde_json::Map::new();
+    for result in results {
+        let summary = result.summary();
+        let cycles = summary.cycles();
+        let mut measures = vec![
+            ("instructions", result.stats.instruction_reads() as f64),
+            ("l1_accesses", summary.l1_hits as f64),
+            ("l2_accesses", summary.l3_hits as f64),
+            ("ram_accesses", summary.ram_hits as f64),
+            ("estimated_cycles", cycles as f64),
+        ];
+        if let Some(hz) = clock().filter(|&hz| hz > 0) {
+            measures.push(("latency", cycles as f64 / hz as f64 * 1e9));
+        }
+        let measures = measures
+            .into_iter()
+            .map(|(measure, value)| (measure.to_owned(), serde_json::json!({ "value": value })))
+            .collect();
+        benchmarks.insert(
+            result.bench.name.to_owned(),
+            serde_json::Value::Object(measures),
+        );
+    }
+    serde_json::to_string_pretty(&benchmarks).expect("Failed to serialize JSON output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Benchmark;
+
+    fn bench() {}
+
+    #[test]
+    fn reports_the_measures_of_every_benchmark_in_bmf() {
+        let parse = Benchmark::new("parse", bench);
+        let print = Benchmark::new("print", bench);
+        let results = [
+            BenchResult::of(&parse, &[("Ir", 1000), ("Dr", 200), ("D1mr", 10)], None),
+            BenchResult::of(&print, &[("Ir", 300)], None),
+        ];
+        let report: serde_json::Value = serde_json::from_str(&bmf(&results)).unwrap();
+        let parse = &report["parse"];
+        assert_eq!(parse["instructions"]["value"], 1000.0);
+        assert_eq!(parse["l1_accesses"]["value"], 1190.0);
+        assert_eq!(parse["l2_accesses"]["value"], 10.0);
+        assert_eq!(parse["ram_accesses"]["value"], 0.0);
+        assert_eq!(parse["estimated_cycles"]["value"], 1240.0);
+        assert_eq!(report["print"]["estimated_cycles"]["value"], 300.0);
+        assert_eq!(report.as_object().unwrap().len(), 2);
+    }
+}
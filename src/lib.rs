@@ -1,17 +1,90 @@
 use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
     env::args,
     fs::File,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        OnceLock,
+    },
 };
 
 #[cfg(feature = "macro")]
 pub use iai_macro::iai;
 
+mod args;
+mod artifact;
+mod backend;
+mod baseline;
+mod binary_size;
+mod build_profile;
+mod builder;
+mod cachegrind;
+mod capabilities;
+mod client_request;
+mod compare;
+mod config;
+mod dhat;
+mod error;
+mod executor;
+mod export;
+mod ffi;
+mod filter;
+mod formats;
+mod history;
+mod json;
 mod macros;
+mod massif;
+mod matrix;
+mod perf;
+mod policy;
+mod project;
+mod published;
+mod report;
+mod reporter;
+mod rusage;
+mod scaling;
+mod self_test;
+mod session;
+mod template;
+mod wall_clock;
+
+use args::Args;
+pub use artifact::Artifact;
+use backend::{Backend, Measurement, Metric};
+use baseline::BaselineDir;
+use builder::Closure;
+pub use builder::Iai;
+use cachegrind::BranchEvents;
+pub use cachegrind::{
+    parse_cachegrind_functions, parse_cachegrind_output, CachegrindError, CachegrindMetadata,
+    FunctionCost,
+};
+use capabilities::Capabilities;
+pub use client_request::{start_measurement, stop_measurement};
+use compare::Comparison;
+pub use config::Config;
+use config::{PostProcessor, Tool};
+use dhat::HeapStats;
+pub use error::Error;
+pub use executor::block_on;
+#[doc(hidden)]
+pub use ffi::ExternC;
+use history::History;
+use massif::MassifStats;
+use policy::Policy;
+pub use report::BenchResult;
+use report::{Detail, Format, FunctionChange};
+use reporter::Reporters;
+pub use reporter::{Reporter, SuiteSummary};
+use rusage::RusageStats;
+pub use session::run_benches;
+use session::{Extras, Session};
 
 /// A function that is opaque to the optimizer, used to prevent the compiler from
 /// optimizing away computations in a benchmark.
@@ -19,6 +92,185 @@ pub fn black_box<T>(dummy: T) -> T {
     std::hint::black_box(dummy)
 }
 
+/// Like [`black_box`], for a buffer the benchmark built in place: the optimizer has to assume
+/// that its contents are read, so the stores that built it can't be eliminated as dead. Unlike
+/// `black_box(buffer)`, this doesn't move the buffer out of the benchmark.
+pub fn black_box_ref<T: ?Sized>(value: &T) -> &T {
+    let value = std::hint::black_box(value);
+    clobber_memory();
+    value
+}
+
+/// Forces every store the benchmark made to memory that may be visible elsewhere to actually be
+/// performed at this point, and every later load to actually be made. It emits no instructions.
+pub fn clobber_memory() {
+    cfg_if! {
+        if #[cfg(any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64",
+            target_arch = "riscv32",
+            target_arch = "riscv64",
+            target_arch = "loongarch64",
+        ))] {
+            // Safety: the assembly is empty. Without `nomem`, the compiler has to assume that it
+            // reads and writes any memory it could reach, which is the point.
+            unsafe { std::arch::asm!("", options(nostack, preserves_flags)) }
+        } else {
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// Set in benchmark processes that only make the items of [`bench_iter`], or only set up and tear
+/// down the input of [`bench_with_setup`].
+static ITEMS_ONLY: AtomicBool = AtomicBool::new(false);
+/// How many items the running benchmark passed through [`bench_iter`].
+static ITEMS: AtomicU64 = AtomicU64::new(0);
+/// Whether the running benchmark used [`bench_with_setup`].
+static SETUP: AtomicBool = AtomicBool::new(false);
+
+/// Benchmarks `consume` on an iterator of `len` items made by `input`, without measuring the
+/// making of them: the harness runs the benchmark once more with the items only being made, and
+/// subtracts that. The items are still made lazily, as `consume` pulls them, so none of them has
+/// to be kept around. The report also gives the cost per item.
+///
+/// ```no_run
+/// fn sum_of_squares() {
+///     iai::bench_iter(10_000, |i| i as u64, |items| items.map(|x| x * x).sum::<u64>());
+/// }
+/// ```
+pub fn bench_iter<T, R, I: FnMut(usize) -> T>(
+    len: usize,
+    input: I,
+    consume: impl FnOnce(std::iter::Map<std::ops::Range<usize>, I>) -> R,
+) {
+    ITEMS.fetch_add(len as u64, Ordering::Relaxed);
+    let items = (0..len).map(input);
+    if ITEMS_ONLY.load(Ordering::Relaxed) {
+        for item in items {
+            black_box(item);
+        }
+    } else {
+        black_box(consume(items));
+    }
+}
+
+/// Benchmarks `routine` on an input made by `setup`, without measuring the making of it or what
+/// `teardown` does with it afterwards: like with [`bench_iter`], the harness runs the benchmark
+/// once more with only `setup` and `teardown`, and subtracts that.
+///
+/// ```no_run
+/// # fn make_input(len: u32) -> Vec<u32> {
+/// #     (0..len).rev().collect()
+/// # }
+/// fn sort() {
+///     iai::bench_with_setup(|| make_input(10_000), |input| input.sort(), drop);
+/// }
+/// ```
+///
+/// The attribute does the same with `#[iai(setup = make_input)]` on a benchmark taking the input
+/// as `&mut`, and optionally `teardown = close_files`. The teardown also runs when only the setup
+/// does, with the input as `setup` made it: it mustn't rely on `routine` having run, and its cost
+/// is only subtracted exactly if it doesn't depend on what `routine` did.
+pub fn bench_with_setup<T, R>(
+    setup: impl FnOnce() -> T,
+    routine: impl FnOnce(&mut T) -> R,
+    teardown: impl FnOnce(T),
+) {
+    SETUP.store(true, Ordering::Relaxed);
+    let mut input = black_box(setup());
+    if !ITEMS_ONLY.load(Ordering::Relaxed) {
+        black_box(routine(&mut input));
+    }
+    teardown(black_box(input));
+}
+
+/// The name `#[iai]` gives a benchmark at `path`, `module_path!()` followed by the function: the
+/// path without the crate, so that benchmarks at the root of the bench target keep their bare
+/// names. Should not be used directly.
+#[doc(hidden)]
+pub const fn strip_crate(path: &'static str) -> &'static str {
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b':' && bytes[i + 1] == b':' {
+            let (_, rest) = bytes.split_at(i + 2);
+            // SAFETY: `path` is split right after an ASCII `::`, so the rest is still UTF-8
+            return unsafe { std::str::from_utf8_unchecked(rest) };
+        }
+        i += 1;
+    }
+    path
+}
+
+/// The error of a benchmark that returned `Err`, to be reported instead of its measurement.
+static FAILURE: OnceLock<String> = OnceLock::new();
+
+/// What a benchmark returned. `main!` and `#[iai]` call `(&Returned(value)).check()` on it, which
+/// resolves to [`ReturnedResult::check`] for a `Result` and to [`ReturnedValue::check`] for
+/// anything else. Should not be used directly.
+#[doc(hidden)]
+pub struct Returned<T>(pub T);
+
+/// Records the error of a benchmark that returned `Err`, so that it is reported as failed.
+#[doc(hidden)]
+pub trait ReturnedResult {
+    fn check(&self);
+}
+
+impl<T, E: std::fmt::Debug> ReturnedResult for Returned<Result<T, E>> {
+    fn check(&self) {
+        if let Err(e) = &self.0 {
+            let _ = FAILURE.set(format!("{:?}", e));
+        }
+    }
+}
+
+/// Accepts whatever else a benchmark returned.
+#[doc(hidden)]
+pub trait ReturnedValue {
+    fn check(&self);
+}
+
+impl<T> ReturnedValue for &Returned<T> {
+    fn check(&self) {}
+}
+
+/// Name of the instrumentation tool the harness itself is running under, if any. Such tools
+/// inject themselves through the dynamic loader, which is what gives them away. Running cachegrind
+/// inside them would measure the instrumentation rather than the benchmarks.
+fn outer_instrumenter() -> Option<&'static str> {
+    const PRELOADS: [(&str, &str); 2] = [("vgpreload", "valgrind"), ("libheaptrack", "heaptrack")];
+    let preload = ["LD_PRELOAD", "DYLD_INSERT_LIBRARIES"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|v| v.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(":");
+    PRELOADS
+        .iter()
+        .find(|(library, _)| preload.contains(library))
+        .map(|&(_, tool)| tool)
+}
+
+/// Annotations added by the running benchmark through [`annotate`].
+static ANNOTATIONS: std::sync::Mutex<Vec<(String, String)>> = std::sync::Mutex::new(Vec::new());
+
+/// Attaches a free-form `key=value` annotation (e.g. which corpus was loaded) to the results of the
+/// running benchmark. It is shown in the report and included in the JSON output.
+///
+/// Annotating is measured like everything else the benchmark does, so prefer
+/// [`Benchmark::annotations`] for anything that is known up front.
+pub fn annotate(key: &str, value: impl std::fmt::Display) {
+    let value = value.to_string().replace('\n', " ");
+    ANNOTATIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((key.to_owned(), value));
+}
+
 fn check_valgrind() -> bool {
     let result = Command::new("valgrind")
         .arg("--tool=cachegrind")
@@ -43,429 +295,1924 @@ fn check_valgrind() -> bool {
     }
 }
 
-fn get_arch() -> String {
-    let output = Command::new("uname")
-        .arg("-m")
-        .stdout(Stdio::piped())
-        .output()
-        .expect("Failed to run `uname` to determine CPU architecture.");
-
-    String::from_utf8(output.stdout)
-        .expect("`-uname -m` returned invalid unicode.")
-        .trim()
-        .to_owned()
+/// The architecture as `setarch` knows it, i.e. as `uname -m` prints it. The names of the common
+/// architectures are known at compile time; `uname` is only asked about the others (like the
+/// flavours of 32-bit ARM).
+fn get_arch() -> Option<String> {
+    let known = match std::env::consts::ARCH {
+        "x86" => Some("i686"),
+        "powerpc" => Some("ppc"),
+        "powerpc64" if cfg!(target_endian = "little") => Some("ppc64le"),
+        "powerpc64" => Some("ppc64"),
+        arch @ ("x86_64" | "aarch64" | "riscv64" | "s390x" | "loongarch64" | "mips64"
+        | "sparc64") => Some(arch),
+        _ => None,
+    };
+    if let Some(arch) = known {
+        return Some(arch.to_owned());
+    }
+    let output = Command::new("uname").arg("-m").output().ok()?;
+    let arch = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    (output.status.success() && !arch.is_empty()).then_some(arch)
 }
 
+/// The clock rate from `IAI_CLOCK_HZ` or the configuration, once the runner has its configuration.
+static CLOCK_HZ: OnceLock<u64> = OnceLock::new();
+
 /// cpu clock rate in Hz
 fn clock() -> Option<u64> {
-    let f = BufReader::new(File::open("/proc/cpuinfo").ok()?);
-    for line in f.lines() {
-        let Ok(line) = line else {
-            continue;
-        };
-        let Some((key, value)) = line.split_once(':') else {
-            continue;
-        };
-        let (key, value) = (key.trim(), value.trim());
-        if key == "model name" {
-            let (_, clock) = value.split_once('@')?;
-            let clock = clock.trim();
-            macro_rules! freq {
-                ($name:literal, $power:literal) => {
-                    if let Some(clock) = clock.strip_suffix($name) {
-                        return Some(
-                            (clock.parse::<f64>().ok()? * 10f64.powf($power as f64)) as u64,
-                        );
-                    }
+    match CLOCK_HZ.get() {
+        Some(&hz) => Some(hz),
+        None => platform_clock(),
+    }
+}
+
+// The clock rate as the operating system advertises it. macOS only does on Intel, and the BSDs
+// report the TSC or the current frequency of the first CPU.
+cfg_if! {
+    if #[cfg(target_os = "macos")] {
+        fn platform_clock() -> Option<u64> {
+            sysctl("hw.cpufrequency")
+        }
+    } else if #[cfg(any(target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd"))] {
+        fn platform_clock() -> Option<u64> {
+            sysctl("machdep.tsc_freq").or_else(|| Some(sysctl("dev.cpu.0.freq")? * 1_000_000))
+        }
+    } else if #[cfg(target_os = "openbsd")] {
+        fn platform_clock() -> Option<u64> {
+            Some(sysctl("hw.cpuspeed")? * 1_000_000)
+        }
+    } else {
+        fn platform_clock() -> Option<u64> {
+            model_name_clock(BufReader::new(File::open("/proc/cpuinfo").ok()?))
+        }
+
+        /// From the first model name in `cpuinfo`, like `Intel(R) Core(TM) i7-6700K CPU @
+        /// 4.00GHz`.
+        fn model_name_clock(cpuinfo: impl BufRead) -> Option<u64> {
+            for line in cpuinfo.lines() {
+                let Ok(line) = line else {
+                    continue;
+                };
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
                 };
+                let (key, value) = (key.trim(), value.trim());
+                if key == "model name" {
+                    let (_, clock) = value.split_once('@')?;
+                    let clock = clock.trim();
+                    macro_rules! freq {
+                        ($name:literal, $power:literal) => {
+                            if let Some(clock) = clock.strip_suffix($name) {
+                                return Some(
+                                    (clock.parse::<f64>().ok()? * 10f64.powf($power as f64)) as u64,
+                                );
+                            }
+                        };
+                    }
+                    freq!("QHz", 30);
+                    freq!("RHz", 27);
+                    freq!("YHz", 24);
+                    freq!("ZHz", 21);
+                    freq!("EHz", 18);
+                    freq!("PHz", 15);
+                    freq!("THz", 12);
+                    freq!("GHz", 9);
+                    freq!("MHz", 6);
+                    freq!("kHz", 3);
+                    freq!("hHz", 2);
+                    freq!("daHz", 1);
+                    freq!("Hz", 0);
+                }
             }
-            freq!("QHz", 30);
-            freq!("RHz", 27);
-            freq!("YHz", 24);
-            freq!("ZHz", 21);
-            freq!("EHz", 18);
-            freq!("PHz", 15);
-            freq!("THz", 12);
-            freq!("GHz", 9);
-            freq!("MHz", 6);
-            freq!("kHz", 3);
-            freq!("hHz", 2);
-            freq!("daHz", 1);
-            freq!("Hz", 0);
+            None
+        }
+    }
+}
+
+/// A numeric kernel state variable, through the `sysctl` command.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn sysctl(name: &str) -> Option<u64> {
+    let output = Command::new("sysctl").arg("-n").arg(name).output().ok()?;
+    sysctl_value(&String::from_utf8(output.stdout).ok()?)
+}
+
+/// The value `sysctl -n` printed, unless it's zero or not a number.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn sysctl_value(output: &str) -> Option<u64> {
+    output.trim().parse().ok().filter(|&value| value > 0)
+}
+
+/// Wall-clock time a number of cycles would take at a given clock rate, in a unit chosen so that
+/// the value stays readable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EstimatedTime {
+    value: f64,
+    unit: &'static str,
+}
+
+impl EstimatedTime {
+    fn from_cycles(cycles: u64, clock_hz: u64) -> EstimatedTime {
+        let seconds = cycles as f64 / clock_hz as f64;
+        let (scale, unit) = if seconds < 1e-6 {
+            (1e9, "ns")
+        } else if seconds < 1e-3 {
+            (1e6, "μs")
+        } else if seconds < 1.0 {
+            (1e3, "ms")
+        } else {
+            (1.0, "s")
+        };
+        EstimatedTime {
+            value: seconds * scale,
+            unit,
         }
     }
-    None
+
+    /// The estimate for `cycles` on this machine, if its clock rate is known.
+    fn estimate(cycles: u64) -> Option<EstimatedTime> {
+        Self::at_clock(cycles, clock())
+    }
+
+    fn at_clock(cycles: u64, clock_hz: Option<u64>) -> Option<EstimatedTime> {
+        clock_hz
+            .filter(|&hz| hz > 0)
+            .map(|hz| Self::from_cycles(cycles, hz))
+    }
+}
+
+impl std::fmt::Display for EstimatedTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.3}{}", self.value, self.unit)
+    }
 }
 
 fn basic_valgrind() -> Command {
     Command::new("valgrind")
 }
 
-// Invoke Valgrind, disabling ASLR if possible because ASLR could noise up the results a bit
+// Invoke Valgrind, disabling ASLR if possible because ASLR could noise up the results a bit.
+// `aslr_warning` explains why that didn't work on platforms where it can't be done per run.
 cfg_if! {
     if #[cfg(target_os = "linux")] {
-        fn valgrind_without_aslr(arch: &str) -> Command {
+        fn valgrind_without_aslr(arch: Option<&str>) -> Command {
             let mut cmd = Command::new("setarch");
-            cmd.arg(arch)
+            // Without an architecture, setarch (from util-linux 2.33 on) keeps the current one
+            cmd.args(arch)
                 .arg("-R")
                 .arg("valgrind");
             cmd
         }
+
+        fn aslr_warning() -> Option<String> {
+            None
+        }
     } else if #[cfg(target_os = "freebsd")] {
-        fn valgrind_without_aslr(_arch: &str) -> Command {
+        fn valgrind_without_aslr(_arch: Option<&str>) -> Command {
             let mut cmd = Command::new("proccontrol");
             cmd.arg("-m")
                 .arg("aslr")
                 .arg("-s")
-                .arg("disable");
+                .arg("disable")
+                .arg("valgrind");
             cmd
         }
+
+        fn aslr_warning() -> Option<String> {
+            None
+        }
+    } else if #[cfg(target_os = "netbsd")] {
+        fn valgrind_without_aslr(_arch: Option<&str>) -> Command {
+            // NetBSD can only disable ASLR per executable (paxctl) or system-wide (sysctl)
+            basic_valgrind()
+        }
+
+        fn aslr_warning() -> Option<String> {
+            let enabled = Command::new("sysctl")
+                .arg("-n")
+                .arg("security.pax.aslr.enabled")
+                .output()
+                .ok()
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+            match enabled.as_deref() {
+                Some("0") => None,
+                _ => Some(
+                    "ASLR can't be disabled for a single run on NetBSD, so results may be noisy. \
+                     Disable it with `paxctl +a` on the valgrind executables or system-wide with \
+                     `sysctl -w security.pax.aslr.enabled=0`."
+                        .to_owned(),
+                ),
+            }
+        }
+    } else if #[cfg(target_os = "openbsd")] {
+        fn valgrind_without_aslr(_arch: Option<&str>) -> Command {
+            // OpenBSD doesn't allow disabling ASLR at all
+            basic_valgrind()
+        }
+
+        fn aslr_warning() -> Option<String> {
+            Some(
+                "ASLR can't be disabled on OpenBSD, so results may vary slightly between runs."
+                    .to_owned(),
+            )
+        }
     } else {
-        fn valgrind_without_aslr(_arch: &str) -> Command {
+        fn valgrind_without_aslr(_arch: Option<&str>) -> Command {
             // Can't disable ASLR on this platform
             basic_valgrind()
         }
+
+        fn aslr_warning() -> Option<String> {
+            Some(format!(
+                "Iai doesn't know how to disable ASLR on {}, so results may vary slightly \
+                 between runs.",
+                std::env::consts::OS
+            ))
+        }
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file next to it, so that a crash or Ctrl-C in
+/// the middle of writing never leaves a truncated file behind to be compared against later.
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let temporary = PathBuf::from(format!("{}.tmp{}", path.display(), std::process::id()));
+    let result =
+        std::fs::write(&temporary, contents).and_then(|()| std::fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Benchmark names may contain `/` (e.g. for parameters), which must not turn into directories.
+fn file_safe(name: &str) -> String {
+    name.replace(['/', '\\'], "_")
+}
+
+/// Where the latest output of a benchmark is kept. Each tool has its own files, so that switching
+/// tools doesn't compare their results with each other.
+fn result_file(config: &Config, tool: Tool, name: &str) -> PathBuf {
+    config
+        .results_dir()
+        .join(format!("{}.out.{}", tool.name(), file_safe(name)))
+}
+
+/// The `count` functions of benchmark `name` that executed the most instructions, harness
+/// included, from its latest output.
+fn top_functions(config: &Config, name: &str, count: usize) -> Result<Vec<FunctionCost>, Error> {
+    let file = result_file(config, config.tool, name);
+    let mut functions =
+        cachegrind::parse_cachegrind_functions(&file).map_err(|error| Error::Cachegrind {
+            path: file.clone(),
+            error,
+        })?;
+    functions.truncate(count);
+    Ok(functions)
+}
+
+/// How many of the functions that changed the most are reported, unless `--top-functions` says.
+const DEFAULT_FUNCTION_CHANGES: usize = 5;
+
+/// The `count` functions of benchmark `name` whose instructions changed the most since the results
+/// it is compared against.
+fn function_changes(config: &Config, name: &str, count: usize) -> Vec<FunctionChange> {
+    let file = result_file(config, config.tool, name);
+    let parse = |file: &Path| cachegrind::parse_cachegrind_functions(file).unwrap_or_default();
+    let mut old: HashMap<(String, String), u64> = parse(&compare_file(config, &file))
+        .into_iter()
+        .map(|cost| {
+            let instructions = cost.instruction_reads();
+            ((cost.function, cost.file), instructions)
+        })
+        .collect();
+    let mut changes: Vec<FunctionChange> = parse(&file)
+        .into_iter()
+        .map(|cost| {
+            let new = cost.instruction_reads();
+            let old = old.remove(&(cost.function.clone(), cost.file.clone()));
+            FunctionChange {
+                function: cost.function,
+                file: cost.file,
+                old,
+                new: Some(new),
+            }
+        })
+        .collect();
+    // Whatever is left isn't called anymore
+    changes.extend(
+        old.into_iter()
+            .map(|((function, file), old)| FunctionChange {
+                function,
+                file,
+                old: Some(old),
+                new: None,
+            }),
+    );
+    changes.retain(|change| change.delta() != 0);
+    changes.sort_by(|a, b| {
+        b.delta()
+            .unsigned_abs()
+            .cmp(&a.delta().unsigned_abs())
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    changes.truncate(count);
+    changes
+}
+
+/// Hands the output of benchmark `name` to the post-processors of the suite.
+fn post_process(config: &Config, name: &str) -> Result<(), Error> {
+    let file = result_file(config, config.tool, name);
+    for post_processor in &config.post_processors {
+        match post_processor {
+            PostProcessor::Callback(callback) => callback(name, &file),
+            PostProcessor::Command(command) => {
+                let status = Command::new(&command[0])
+                    .args(&command[1..])
+                    .arg(&file)
+                    .env("IAI_BENCHMARK", name)
+                    .status();
+                let problem = match status {
+                    Ok(status) if status.success() => continue,
+                    Ok(status) => status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                return Err(Error::PostProcessor {
+                    command: command[0].clone(),
+                    problem,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What a benchmark process is asked to run.
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    /// Nothing at all, to measure the overhead of starting up and dispatching.
+    Calibration,
+    /// The benchmark with this name. The index is only used when the name is ambiguous, or by
+    /// benchmark processes that don't know about names yet.
+    Bench { index: usize, name: &'static str },
+}
+
+impl Target {
+    fn child_args(self) -> Vec<String> {
+        match self {
+            Target::Calibration => vec!["--iai-run".to_owned(), "-1".to_owned()],
+            Target::Bench { index, name } => vec![
+                "--iai-run".to_owned(),
+                index.to_string(),
+                "--iai-bench".to_owned(),
+                name.to_owned(),
+            ],
+        }
+    }
+}
+
+/// The threading environment of a benchmark, as reported by the benchmark process itself.
+#[derive(Clone, Debug, Default)]
+struct RunMetadata {
+    /// Threads still alive when the benchmark returned, including the main thread. Threads that
+    /// were spawned and joined during the benchmark aren't counted.
+    threads: Option<u64>,
+    /// The CPUs the benchmark was allowed to run on, as a list like `0-3,8`.
+    cpus: Option<String>,
+    /// Annotations added while the benchmark ran.
+    annotations: Vec<(String, String)>,
+    /// The items passed through [`bench_iter`], if it was used.
+    items: Option<u64>,
+    /// Whether [`bench_with_setup`] was used.
+    setup: bool,
+    /// The error of a benchmark that returned `Err`.
+    failure: Option<String>,
+}
+
+impl RunMetadata {
+    const PREFIX: &'static str = "iai-metadata";
+    const ANNOTATION_PREFIX: &'static str = "iai-annotation ";
+    const FAILURE_PREFIX: &'static str = "iai-failure ";
+
+    /// Called by the benchmark process once it's done, including for calibration so that the
+    /// cost is subtracted. Only Linux has the information at hand in `/proc`.
+    fn report() {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map_or("?", str::trim)
+        };
+        eprintln!(
+            "{} threads={} cpus={} items={} setup={}",
+            Self::PREFIX,
+            field("Threads:"),
+            field("Cpus_allowed_list:"),
+            ITEMS.load(Ordering::Relaxed),
+            SETUP.load(Ordering::Relaxed)
+        );
+        let annotations = ANNOTATIONS.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in annotations.iter() {
+            eprintln!("{}{}={}", Self::ANNOTATION_PREFIX, key, value);
+        }
+        for line in FAILURE
+            .get()
+            .into_iter()
+            .flat_map(|failure| failure.lines())
+        {
+            eprintln!("{}{}", Self::FAILURE_PREFIX, line);
+        }
+    }
+
+    fn parse(stderr: &[u8]) -> RunMetadata {
+        let mut metadata = RunMetadata::default();
+        let stderr = String::from_utf8_lossy(stderr);
+        metadata.annotations = stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix(Self::ANNOTATION_PREFIX))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        let failure: Vec<&str> = stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix(Self::FAILURE_PREFIX))
+            .collect();
+        metadata.failure = (!failure.is_empty()).then(|| failure.join("\n"));
+        let line = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix(Self::PREFIX));
+        for (key, value) in line
+            .into_iter()
+            .flat_map(str::split_whitespace)
+            .filter_map(|field| field.split_once('='))
+        {
+            match key {
+                "threads" => metadata.threads = value.parse().ok(),
+                "cpus" if value != "?" => metadata.cpus = Some(value.to_owned()),
+                "items" => metadata.items = value.parse().ok().filter(|&items| items > 0),
+                "setup" => metadata.setup = value == "true",
+                _ => {}
+            }
+        }
+        metadata
     }
 }
 
 fn run_bench(
-    arch: &str,
+    arch: Option<&str>,
     executable: &str,
-    i: isize,
+    target: Target,
     name: &str,
     allow_aslr: bool,
-) -> (CachegrindStats, Option<CachegrindStats>) {
-    let output_file = PathBuf::from(format!("target/iai/cachegrind.out.{}", name));
-    let old_file = output_file.with_file_name(format!("cachegrind.out.{}.old", name));
-    std::fs::create_dir_all(output_file.parent().unwrap()).expect("Failed to create directory");
+    config: &Config,
+    child_args: &[&str],
+) -> Result<(CachegrindStats, Option<CachegrindStats>, RunMetadata), Error> {
+    let cmd = if allow_aslr {
+        basic_valgrind()
+    } else {
+        valgrind_without_aslr(arch)
+    };
+    backend::run(cmd, executable, target, name, config, child_args)
+}
 
+/// Moves the new results in `new_file` to `output_file`, keeping the last ones as `.old`, and
+/// returns what to compare them against: the named baseline given with `--baseline`, or else the
+/// previous run. With `--save-baseline`, the new results are also saved under that name. Results
+/// to compare against that can't be read are warned about and not compared against.
+fn store_results<T>(
+    config: &Config,
+    output_file: &Path,
+    new_file: &Path,
+    parse: impl Fn(&Path) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
     if output_file.exists() {
         // Already run this benchmark once; move last results to .old
-        std::fs::copy(&output_file, &old_file).unwrap();
+        std::fs::rename(output_file, old_file(output_file))
+            .map_err(|e| Error::io(output_file, e))?;
     }
+    std::fs::rename(new_file, output_file).map_err(|e| Error::io(new_file, e))?;
 
-    let mut cmd = if allow_aslr {
-        basic_valgrind()
-    } else {
-        valgrind_without_aslr(arch)
+    let file_name = output_file.file_name().unwrap();
+    let compare_file = compare_file(config, output_file);
+    // Read before saving, so that saving over the baseline that is compared against still
+    // compares against its previous contents
+    let old = match compare_file.exists().then(|| parse(&compare_file)) {
+        Some(Ok(old)) => Some(old),
+        Some(Err(e)) => {
+            eprintln!("Warning: not comparing against {}", e);
+            None
+        }
+        None => None,
     };
-    if let Ok(v) = std::env::var("CACHEGRIND_FLAGS") {
-        cmd.args(v.split(" "));
+    let old = old.filter(|_| {
+        let same = simulated_caches(&compare_file) == simulated_caches(output_file);
+        if !same && !CACHES_CHANGED.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Warning: the results to compare against were measured with other cache sizes \
+                 (see {}); not comparing against them.",
+                compare_file.display()
+            );
+        }
+        same
+    });
+    if let Some(baseline) = &config.save_baseline {
+        let dir = config.named_baseline_dir(baseline);
+        std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        let saved = dir.join(file_name);
+        std::fs::copy(output_file, &saved).map_err(|e| Error::io(&saved, e))?;
     }
-    let output = cmd
-        .arg("--tool=cachegrind")
-        // Set some reasonable cache sizes. The exact sizes matter less than having fixed sizes,
-        // since otherwise cachegrind would take them from the CPU and make benchmark runs
-        // even more incomparable between machines.
-        .arg("--I1=32768,8,64")
-        .arg("--D1=32768,8,64")
-        .arg("--LL=8388608,16,64")
-        .arg("--cache-sim=yes")
-        .arg(format!("--cachegrind-out-file={}", output_file.display()))
-        .arg(executable)
-        .arg("--iai-run")
-        .arg(i.to_string())
-        .output()
-        .expect("Failed to run benchmark in cachegrind");
-    if !output.status.success() {
-        std::io::stderr().write_all(&output.stderr).unwrap();
-        panic!(
-            "Failed to run benchmark in cachegrind. Exit code: {}",
-            output.status
-        );
+    Ok(old)
+}
+
+/// Where the results before those in `output_file` are kept.
+fn old_file(output_file: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.old", output_file.display()))
+}
+
+/// The results that those in `output_file` are compared against: the ones of the named baseline
+/// given with `--baseline`, or else the previous ones.
+fn compare_file(config: &Config, output_file: &Path) -> PathBuf {
+    match &config.compare_baseline {
+        Some(baseline) => config
+            .named_baseline_dir(baseline)
+            .join(output_file.file_name().unwrap()),
+        None => old_file(output_file),
     }
+}
 
-    let new_stats = parse_cachegrind_output(&output_file);
-    let old_stats = if old_file.exists() {
-        Some(parse_cachegrind_output(&old_file))
-    } else {
-        None
+/// Whether results measured with other caches have been found, which is only reported once.
+static CACHES_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// The caches cachegrind (or callgrind) simulated for the results in `file`, as it describes them
+/// at the top of the file; nothing for the other tools.
+fn simulated_caches(file: &Path) -> Vec<String> {
+    let Ok(file) = File::open(file) else {
+        return Vec::new();
     };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .take_while(|line| !line.starts_with("events:"))
+        .filter(|line| line.starts_with("desc: ") && line.contains(" cache:"))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect()
+}
 
-    (new_stats, old_stats)
+/// Number of differently-laid-out runs used by `--layout-sensitivity`, overridable through
+/// `IAI_LAYOUT_RUNS`.
+fn layout_runs() -> usize {
+    std::env::var("IAI_LAYOUT_RUNS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 1)
+        .unwrap_or(5)
 }
 
-fn parse_cachegrind_output(file: &Path) -> CachegrindStats {
-    let mut events_line = None;
-    let mut summary_line = None;
+/// Runs `target` with ASLR enabled and an environment padded by a run-dependent amount,
+/// which shifts the initial stack and the heap so that each run sees a different memory layout.
+fn run_layout_variant(
+    executable: &str,
+    target: Target,
+    name: &str,
+    config: &Config,
+    run: usize,
+) -> Result<CachegrindStats, Error> {
+    let output_file = config.results_dir().join(format!(
+        "layout/{}.out.{}.{}",
+        config.tool.name(),
+        file_safe(name),
+        run
+    ));
+    let dir = output_file.parent().unwrap();
+    std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+
+    let mut cmd = basic_valgrind();
+    cmd.env("IAI_LAYOUT_PADDING", "x".repeat(run * 48));
+    Ok(backend::run_once(cmd, executable, target, config, &[], &output_file)?.0)
+}
 
-    let file_in = File::open(file).expect("Unable to open cachegrind output file");
+/// Min/max of the data-cache metrics of one benchmark over several memory layouts.
+#[derive(Debug)]
+struct LayoutSpread {
+    runs: usize,
+    metrics: Vec<(&'static str, &'static str, u64, u64)>,
+}
+
+impl LayoutSpread {
+    fn measure(
+        executable: &str,
+        target: Target,
+        name: &str,
+        config: &Config,
+        calibrations: &[CachegrindStats],
+    ) -> Result<Self, Error> {
+        let samples = calibrations
+            .iter()
+            .enumerate()
+            .map(|(run, calibration)| {
+                let stats = run_layout_variant(executable, target, name, config, run)?;
+                Ok(stats.subtract(calibration))
+            })
+            .collect::<Result<Vec<CachegrindStats>, Error>>()?;
+
+        let metric = |label, key, get: fn(&CachegrindStats) -> u64| {
+            let min = samples.iter().map(get).min().unwrap_or(0);
+            let max = samples.iter().map(get).max().unwrap_or(0);
+            (label, key, min, max)
+        };
+        #[rustfmt::skip]
+        let metrics = vec![
+            metric("Data Reads", "data_reads", |s| s.data_reads()),
+            metric("D1 Read Misses", "data_l1_read_misses", |s| s.data_l1_read_misses()),
+            metric("LL Read Misses", "data_cache_read_misses", |s| s.data_cache_read_misses()),
+            metric("Data Writes", "data_writes", |s| s.data_writes()),
+            metric("D1 Write Misses", "data_l1_write_misses", |s| s.data_l1_write_misses()),
+            metric("LL Write Misses", "data_cache_write_misses", |s| s.data_cache_write_misses()),
+        ];
+
+        Ok(LayoutSpread {
+            runs: samples.len(),
+            metrics,
+        })
+    }
 
-    for line in BufReader::new(file_in).lines() {
-        let line = line.unwrap();
-        if let Some(line) = line.strip_prefix("events: ") {
-            events_line = Some(line.trim().to_owned());
+    fn spread(min: u64, max: u64) -> f64 {
+        if max == 0 {
+            0.0
+        } else {
+            (max - min) as f64 / max as f64 * 100.0
         }
-        if let Some(line) = line.strip_prefix("summary: ") {
-            summary_line = Some(line.trim().to_owned());
+    }
+
+    fn print(&self) {
+        println!("  Layout sensitivity ({} runs, ASLR enabled):", self.runs);
+        for &(label, _, min, max) in &self.metrics {
+            println!(
+                "    {:<17} {:>12} - {:<12} (spread {:.2}%)",
+                format!("{}:", label),
+                min,
+                max,
+                Self::spread(min, max)
+            );
         }
     }
+}
+
+/// Reads the stats of one of our own cachegrind runs, which always simulate the caches.
+fn read_stats(file: &Path) -> Result<CachegrindStats, Error> {
+    let cachegrind = |error| Error::Cachegrind {
+        path: file.to_owned(),
+        error,
+    };
+    let (stats, _) = CachegrindStats::from_file(file).map_err(cachegrind)?;
+    Ok(stats)
+}
 
-    match (events_line, summary_line) {
-        (Some(events), Some(summary)) => {
-            let events: HashMap<_, _> = events
-                .split_whitespace()
-                .zip(summary.split_whitespace().map(|s| {
-                    s.parse::<u64>()
-                        .expect("Unable to parse summary line from cachegrind output file")
-                }))
-                .collect();
+/// The cachegrind events the report knows, with the names they have in the JSON output.
+const NAMED_EVENTS: [(&str, &str); 9] = [
+    ("Ir", "instruction_reads"),
+    ("I1mr", "instruction_l1_misses"),
+    ("ILmr", "instruction_cache_misses"),
+    ("Dr", "data_reads"),
+    ("D1mr", "data_l1_read_misses"),
+    ("DLmr", "data_cache_read_misses"),
+    ("Dw", "data_writes"),
+    ("D1mw", "data_l1_write_misses"),
+    ("DLmw", "data_cache_write_misses"),
+];
 
-            CachegrindStats {
-                instruction_reads: events["Ir"],
-                instruction_l1_misses: events["I1mr"],
-                instruction_cache_misses: events["ILmr"],
-                data_reads: events["Dr"],
-                data_l1_read_misses: events["D1mr"],
-                data_cache_read_misses: events["DLmr"],
-                data_writes: events["Dw"],
-                data_l1_write_misses: events["D1mw"],
-                data_cache_write_misses: events["DLmw"],
+/// The totals of whichever events cachegrind recorded, by their cachegrind names. Which ones there
+/// are depends on its flags: `Ir` always, the cache events unless the cache simulation was turned
+/// off, the branch events with `--branch-sim` and so on.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(from = "StatsFields")]
+pub struct CachegrindStats {
+    events: HashMap<String, u64>,
+}
+
+/// The JSON of [`CachegrindStats`], as it is read back: the named events may be `null` or missing
+/// and so may `extra`, e.g. in results exported by an older version.
+#[derive(Deserialize)]
+struct StatsFields {
+    instruction_reads: Option<u64>,
+    instruction_l1_misses: Option<u64>,
+    instruction_cache_misses: Option<u64>,
+    data_reads: Option<u64>,
+    data_l1_read_misses: Option<u64>,
+    data_cache_read_misses: Option<u64>,
+    data_writes: Option<u64>,
+    data_l1_write_misses: Option<u64>,
+    data_cache_write_misses: Option<u64>,
+    #[serde(default)]
+    extra: HashMap<String, u64>,
+}
+
+impl From<StatsFields> for CachegrindStats {
+    fn from(fields: StatsFields) -> CachegrindStats {
+        let named = [
+            fields.instruction_reads,
+            fields.instruction_l1_misses,
+            fields.instruction_cache_misses,
+            fields.data_reads,
+            fields.data_l1_read_misses,
+            fields.data_cache_read_misses,
+            fields.data_writes,
+            fields.data_l1_write_misses,
+            fields.data_cache_write_misses,
+        ];
+        let mut events = fields.extra;
+        for ((event, _), count) in NAMED_EVENTS.iter().zip(named) {
+            if let Some(count) = count {
+                events.insert((*event).to_owned(), count);
             }
         }
-        _ => panic!("Unable to parse cachegrind output file"),
+        CachegrindStats { events }
     }
 }
 
-#[derive(Clone, Debug)]
-struct CachegrindStats {
-    instruction_reads: u64,
-    instruction_l1_misses: u64,
-    instruction_cache_misses: u64,
-    data_reads: u64,
-    data_l1_read_misses: u64,
-    data_cache_read_misses: u64,
-    data_writes: u64,
-    data_l1_write_misses: u64,
-    data_cache_write_misses: u64,
+/// The named events under their JSON names (`null` if they weren't recorded), and the others in
+/// `extra`.
+impl Serialize for CachegrindStats {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(NAMED_EVENTS.len() + 1))?;
+        for (event, name) in NAMED_EVENTS {
+            map.serialize_entry(name, &self.events.get(event))?;
+        }
+        let extra: BTreeMap<&str, u64> = self.extra_events().collect();
+        map.serialize_entry("extra", &extra)?;
+        map.end()
+    }
 }
 
-impl std::fmt::Display for CachegrindStats {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self {
-            instruction_reads,
-            instruction_l1_misses,
-            instruction_cache_misses,
-            data_reads,
-            data_l1_read_misses,
-            data_cache_read_misses,
-            data_writes,
-            data_l1_write_misses,
-            data_cache_write_misses,
-        } = self;
-        let cycles = self.summarize().cycles();
-        write!(
-            f,
-            r#"{{"instruction_reads":{instruction_reads},"instruction_l1_misses":{instruction_l1_misses},"instruction_cache_misses":{instruction_cache_misses},"data_reads":{data_reads},"data_l1_read_misses":{data_l1_read_misses},"data_cache_read_misses":{data_cache_read_misses},"data_writes":{data_writes},"data_l1_write_misses":{data_l1_write_misses},"data_cache_write_misses":{data_cache_write_misses},"cycles":{cycles}"#
-        )?;
-        match clock() {
-            Some(c) => {
-                let time = (10000.0 / c as f64) * cycles as f64;
-                write!(f, r#","time_passed":"{time:.3}μs"}}"#)
-            }
-            None => write!(f, "}}"),
-        }
+impl Backend for CachegrindStats {
+    const KEY: &'static str = "stats";
+
+    fn tool(config: &Config) -> &'static str {
+        config.tool.name()
+    }
+
+    fn args(config: &Config) -> Vec<String> {
+        let user_flags = std::env::var("CACHEGRIND_FLAGS").unwrap_or_default();
+        let user_flags = user_flags.split(' ').filter(|flag| !flag.is_empty());
+        // Ours first, so that the user's flags (like `--cache-sim=no`) win
+        config
+            .cache
+            .sim_args
+            .iter()
+            .copied()
+            .chain(["--cache-sim=yes"])
+            .chain(config.branch_sim.then_some("--branch-sim=yes"))
+            .chain(
+                config
+                    .client_requests
+                    .then(|| config.tool.instrumentation_off()),
+            )
+            .chain(config.cachegrind_flags.iter().map(String::as_str))
+            .chain(user_flags)
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn child_args(config: &Config) -> Vec<&'static str> {
+        config
+            .client_requests
+            .then_some(client_request::CHILD_ARG)
+            .into_iter()
+            .collect()
+    }
+
+    fn parse(file: &Path) -> Result<CachegrindStats, Error> {
+        read_stats(file)
+    }
+
+    fn subtract(&self, calibration: &CachegrindStats) -> CachegrindStats {
+        CachegrindStats::subtract(self, calibration)
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        // The report shows the summary derived from these instead
+        vec![
+            Metric::new("instruction_reads", None, self.instruction_reads()),
+            Metric::new("instruction_l1_misses", None, self.instruction_l1_misses()),
+            Metric::new(
+                "instruction_cache_misses",
+                None,
+                self.instruction_cache_misses(),
+            ),
+            Metric::new("data_reads", None, self.data_reads()),
+            Metric::new("data_l1_read_misses", None, self.data_l1_read_misses()),
+            Metric::new(
+                "data_cache_read_misses",
+                None,
+                self.data_cache_read_misses(),
+            ),
+            Metric::new("data_writes", None, self.data_writes()),
+            Metric::new("data_l1_write_misses", None, self.data_l1_write_misses()),
+            Metric::new(
+                "data_cache_write_misses",
+                None,
+                self.data_cache_write_misses(),
+            ),
+        ]
+    }
+}
+
+/// How much a second call of a benchmark costs once the first one warmed up the caches (`--warm`):
+/// a run that calls it twice minus one that calls it once. The metrics are those of the report.
+fn warm_measurement(
+    (twice, old_twice): (&CachegrindStats, Option<&CachegrindStats>),
+    (once, old_once): (&CachegrindStats, Option<&CachegrindStats>),
+    cycle_weights: [u64; 3],
+) -> Measurement {
+    let metrics = |stats: CachegrindStats| {
+        let summary = stats.summarize(cycle_weights);
+        vec![
+            Metric::new(
+                "instruction_reads",
+                Some("Warm Instructions"),
+                stats.instruction_reads(),
+            ),
+            Metric::new("l1_accesses", Some("Warm L1 Accesses"), summary.l1_hits),
+            Metric::new("l2_accesses", Some("Warm L2 Accesses"), summary.l3_hits),
+            Metric::new("ram_accesses", Some("Warm RAM Accesses"), summary.ram_hits),
+            Metric::new("cycles", Some("Warm Cycles"), summary.cycles()),
+        ]
+    };
+    Measurement {
+        key: "warm",
+        metrics: metrics(twice.subtract(once)),
+        old: old_twice
+            .zip(old_once)
+            .map(|(twice, once)| metrics(twice.subtract(once))),
     }
 }
 
 impl CachegrindStats {
+    /// Stats of the given counts, by the cachegrind names of the events.
+    pub fn from_events(events: HashMap<String, u64>) -> CachegrindStats {
+        CachegrindStats { events }
+    }
+
+    /// Reads the totals of the cachegrind (or callgrind) output file at `path`, e.g. one collected
+    /// on another machine, along with what the file says about the run. Fails if `Ir` is missing,
+    /// or some but not all of the cache or branch events are there.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+    ) -> Result<(CachegrindStats, CachegrindMetadata), CachegrindError> {
+        let (events, metadata) = cachegrind::read_file(path)?;
+        cachegrind::check_events(&events)?;
+        Ok((CachegrindStats { events }, metadata))
+    }
+
+    /// All of the recorded events by their cachegrind names, like `Ir` and `D1mr`.
+    pub fn events(&self) -> &HashMap<String, u64> {
+        &self.events
+    }
+
+    /// The count of `event`, or 0 if it wasn't recorded.
+    pub fn event(&self, event: &str) -> u64 {
+        self.events.get(event).copied().unwrap_or(0)
+    }
+
+    pub fn instruction_reads(&self) -> u64 {
+        self.event("Ir")
+    }
+    pub fn instruction_l1_misses(&self) -> u64 {
+        self.event("I1mr")
+    }
+    pub fn instruction_cache_misses(&self) -> u64 {
+        self.event("ILmr")
+    }
+    pub fn data_reads(&self) -> u64 {
+        self.event("Dr")
+    }
+    pub fn data_l1_read_misses(&self) -> u64 {
+        self.event("D1mr")
+    }
+    pub fn data_cache_read_misses(&self) -> u64 {
+        self.event("DLmr")
+    }
+    pub fn data_writes(&self) -> u64 {
+        self.event("Dw")
+    }
+    pub fn data_l1_write_misses(&self) -> u64 {
+        self.event("D1mw")
+    }
+    pub fn data_cache_write_misses(&self) -> u64 {
+        self.event("DLmw")
+    }
+
+    /// Whether the cache simulation was on, without which the L1, L2 and RAM accesses are unknown.
+    pub fn has_cache_events(&self) -> bool {
+        cachegrind::CACHE_EVENTS
+            .iter()
+            .any(|event| self.events.contains_key(*event))
+    }
+
+    /// The events besides the named ones, sorted by name.
+    fn extra_events(&self) -> impl Iterator<Item = (&str, u64)> {
+        let mut extra: Vec<(&str, u64)> = self
+            .events
+            .iter()
+            .filter(|(event, _)| NAMED_EVENTS.iter().all(|(named, _)| named != event))
+            .map(|(event, count)| (event.as_str(), *count))
+            .collect();
+        extra.sort_unstable();
+        extra.into_iter()
+    }
+
+    /// Whether the branch simulation was on (`--branch-sim=yes`).
+    pub fn has_branch_events(&self) -> bool {
+        cachegrind::BRANCH_EVENTS
+            .iter()
+            .any(|event| self.events.contains_key(*event))
+    }
+
+    /// The branch events, if cachegrind was run with `--branch-sim=yes`.
+    pub(crate) fn branches(&self) -> Option<BranchEvents> {
+        let event = |name: &str| self.events.get(name).copied();
+        Some(BranchEvents {
+            conditional: event("Bc")?,
+            conditional_mispredicted: event("Bcm")?,
+            indirect: event("Bi")?,
+            indirect_mispredicted: event("Bim")?,
+        })
+    }
+
     pub fn ram_accesses(&self) -> u64 {
-        self.instruction_cache_misses + self.data_cache_read_misses + self.data_cache_write_misses
+        self.instruction_cache_misses()
+            + self.data_cache_read_misses()
+            + self.data_cache_write_misses()
     }
-    pub fn summarize(&self) -> CachegrindSummary {
+    /// Where the memory accesses hit, with the cycles of an L1 hit, an LL hit and a RAM access
+    /// to estimate the cycles by, like [`Config::cycle_weights`].
+    pub fn summarize(&self, cycle_weights: [u64; 3]) -> CachegrindSummary {
         let ram_hits = self.ram_accesses();
         let l3_accesses =
-            self.instruction_l1_misses + self.data_l1_read_misses + self.data_l1_write_misses;
-        let l3_hits = l3_accesses - ram_hits;
+            self.instruction_l1_misses() + self.data_l1_read_misses() + self.data_l1_write_misses();
+        let l3_hits = l3_accesses.saturating_sub(ram_hits);
 
-        let total_memory_rw = self.instruction_reads + self.data_reads + self.data_writes;
-        let l1_hits = total_memory_rw - (ram_hits + l3_hits);
+        let total_memory_rw = self.instruction_reads() + self.data_reads() + self.data_writes();
+        let l1_hits = total_memory_rw.saturating_sub(ram_hits + l3_hits);
 
         CachegrindSummary {
             l1_hits,
             l3_hits,
             ram_hits,
+            cycle_weights,
         }
     }
 
-    #[rustfmt::skip]
+    /// Every event minus its count in `calibration`, of the events of either.
     pub fn subtract(&self, calibration: &CachegrindStats) -> CachegrindStats {
-        CachegrindStats {
-            instruction_reads: self.instruction_reads.saturating_sub(calibration.instruction_reads),
-            instruction_l1_misses: self.instruction_l1_misses.saturating_sub(calibration.instruction_l1_misses),
-            instruction_cache_misses: self.instruction_cache_misses.saturating_sub(calibration.instruction_cache_misses),
-            data_reads: self.data_reads.saturating_sub(calibration.data_reads),
-            data_l1_read_misses: self.data_l1_read_misses.saturating_sub(calibration.data_l1_read_misses),
-            data_cache_read_misses: self.data_cache_read_misses.saturating_sub(calibration.data_cache_read_misses),
-            data_writes: self.data_writes.saturating_sub(calibration.data_writes),
-            data_l1_write_misses: self.data_l1_write_misses.saturating_sub(calibration.data_l1_write_misses),
-            data_cache_write_misses: self.data_cache_write_misses.saturating_sub(calibration.data_cache_write_misses),
-        }
+        self.combine(calibration, u64::saturating_sub)
+    }
+
+    /// Every event plus its count in `other`, of the events of either, e.g. to total up a group.
+    pub fn add(&self, other: &CachegrindStats) -> CachegrindStats {
+        self.combine(other, u64::saturating_add)
+    }
+
+    fn combine(&self, other: &CachegrindStats, op: fn(u64, u64) -> u64) -> CachegrindStats {
+        let events = self.events.keys().chain(other.events.keys());
+        let events = events
+            .map(|event| (event.clone(), op(self.event(event), other.event(event))))
+            .collect();
+        CachegrindStats { events }
     }
 }
 
-#[derive(Clone, Debug)]
-struct CachegrindSummary {
+/// The memory accesses of a run by where they hit, from which its cycles are estimated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachegrindSummary {
     l1_hits: u64,
     l3_hits: u64,
     ram_hits: u64,
+    /// The cycles of an L1 hit, an LL hit and a RAM access.
+    cycle_weights: [u64; 3],
 }
+
 impl CachegrindSummary {
-    fn cycles(&self) -> u64 {
-        // Uses Itamar Turner-Trauring's formula from https://pythonspeed.com/articles/consistent-benchmarking-in-ci/
-        self.l1_hits + (5 * self.l3_hits) + (35 * self.ram_hits)
+    pub fn l1_hits(&self) -> u64 {
+        self.l1_hits
+    }
+    /// The hits in the last-level cache, which is what cachegrind simulates besides L1.
+    pub fn l3_hits(&self) -> u64 {
+        self.l3_hits
+    }
+    pub fn ram_hits(&self) -> u64 {
+        self.ram_hits
+    }
+
+    /// The weights the hits were summarized with.
+    pub fn cycle_weights(&self) -> [u64; 3] {
+        self.cycle_weights
+    }
+
+    /// The estimated cycles, weighing the hits by the weights they were summarized with.
+    pub fn cycles(&self) -> u64 {
+        let [l1, ll, ram] = self.cycle_weights;
+        l1 * self.l1_hits + ll * self.l3_hits + ram * self.ram_hits
+    }
+
+    /// The hits of both, e.g. to total up a group, with the weights of these.
+    pub fn add(&self, other: &CachegrindSummary) -> CachegrindSummary {
+        CachegrindSummary {
+            l1_hits: self.l1_hits.saturating_add(other.l1_hits),
+            l3_hits: self.l3_hits.saturating_add(other.l3_hits),
+            ram_hits: self.ram_hits.saturating_add(other.ram_hits),
+            cycle_weights: self.cycle_weights,
+        }
+    }
+
+    /// These hits minus those of `other`, or 0 where `other` has more, with the weights of these.
+    pub fn subtract(&self, other: &CachegrindSummary) -> CachegrindSummary {
+        CachegrindSummary {
+            l1_hits: self.l1_hits.saturating_sub(other.l1_hits),
+            l3_hits: self.l3_hits.saturating_sub(other.l3_hits),
+            ram_hits: self.ram_hits.saturating_sub(other.ram_hits),
+            cycle_weights: self.cycle_weights,
+        }
+    }
+}
+
+/// A registered benchmark, as collected by `main!` or the `#[iai]` attribute. Should not be
+/// constructed directly.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug)]
+pub struct Benchmark {
+    pub name: &'static str,
+    pub func: fn(),
+    pub tolerance: Option<&'static str>,
+    pub budget: Option<&'static str>,
+    pub extern_c: Option<ExternC>,
+    pub params: &'static [(&'static str, &'static str)],
+    pub annotations: &'static [(&'static str, &'static str)],
+    pub artifacts: &'static [Artifact],
+    pub ignore: bool,
+    /// The closure of a benchmark registered with [`Iai::bench`], called instead of `func`.
+    pub(crate) closure: Option<Closure>,
+}
+
+impl Benchmark {
+    pub const fn new(name: &'static str, func: fn()) -> Benchmark {
+        Benchmark {
+            name,
+            func,
+            tolerance: None,
+            budget: None,
+            extern_c: None,
+            params: &[],
+            annotations: &[],
+            artifacts: &[],
+            ignore: false,
+            closure: None,
+        }
+    }
+
+    /// A benchmark that calls the `extern "C" fn()` named `symbol` in the shared library at
+    /// `library`. Loading the library is excluded from the measurement.
+    pub const fn extern_c(
+        name: &'static str,
+        library: &'static str,
+        symbol: &'static str,
+    ) -> Benchmark {
+        fn unused() {}
+        Benchmark {
+            extern_c: Some(ExternC { library, symbol }),
+            ..Benchmark::new(name, unused)
+        }
+    }
+
+    /// Overrides the suite-wide regression threshold for this benchmark, e.g. `"2%"` or `"1500"`.
+    pub const fn tolerance(mut self, tolerance: &'static str) -> Benchmark {
+        self.tolerance = Some(tolerance);
+        self
+    }
+
+    /// Fails the run when the estimated cycles of this benchmark exceed what a clock ticks in the
+    /// given time, e.g. `"50us @ 48MHz"`.
+    pub const fn budget(mut self, budget: &'static str) -> Benchmark {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Names and values of the parameters this benchmark was instantiated with, e.g.
+    /// `&[("size", "1000")]`. They are reported as structured fields in the JSON output.
+    pub const fn params(mut self, params: &'static [(&'static str, &'static str)]) -> Benchmark {
+        self.params = params;
+        self
+    }
+
+    /// Free-form notes about this benchmark, e.g. `&[("algorithm", "v2")]`, which are carried
+    /// through to the report and the JSON output. See also [`annotate`].
+    pub const fn annotations(
+        mut self,
+        annotations: &'static [(&'static str, &'static str)],
+    ) -> Benchmark {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Shared inputs this benchmark reads with [`Artifact::get`]. They are built once for all
+    /// benchmarks that declare them, and loading them is excluded from the measurement.
+    pub const fn artifacts(mut self, artifacts: &'static [Artifact]) -> Benchmark {
+        self.artifacts = artifacts;
+        self
+    }
+
+    fn call(&self) {
+        match self.closure {
+            Some(closure) => closure.call(),
+            None => (self.func)(),
+        }
+    }
+
+    /// Skips this benchmark unless the runner is given `--ignored` or `--include-ignored`, like
+    /// `#[ignore]` does for tests.
+    pub const fn ignore(mut self) -> Benchmark {
+        self.ignore = true;
+        self
+    }
+
+    /// Whether setting up this benchmark is expensive enough to be calibrated against a run that
+    /// only does the setup, rather than against the shared calibration.
+    fn has_setup(&self) -> bool {
+        self.extern_c.is_some() || !self.artifacts.is_empty()
+    }
+
+    /// The explicit parameters, followed by any `key=value` segments of a `/`-separated name
+    /// like `parse/size=1000/format=json`.
+    fn parameters(&self) -> Vec<(&'static str, &'static str)> {
+        let from_name = self
+            .name
+            .split('/')
+            .skip(1)
+            .filter_map(|segment| segment.split_once('='));
+        let mut params = self.params.to_vec();
+        for (key, value) in from_name {
+            if !params.iter().any(|(k, _)| *k == key) {
+                params.push((key, value));
+            }
+        }
+        params
+    }
+}
+
+/// Version of the protocol between the harness and the benchmark processes it starts. It is passed
+/// along with the benchmark index, so that a benchmark built against another version of iai
+/// refuses to run instead of running the wrong benchmark.
+const HARNESS_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "/1");
+
+/// Set on the benchmark processes, so that they can tell when a benchmark starts the runner again.
+const BENCHMARK_PROCESS_VAR: &str = "IAI_BENCHMARK_PROCESS";
+
+/// Whether the runner was started in this process.
+static ENTERED: AtomicBool = AtomicBool::new(false);
+
+/// Reports a broken `--iai-run` invocation from inside the benchmark process.
+fn child_error(message: String) -> ! {
+    eprintln!("iai: {}", message);
+    std::process::exit(2)
+}
+
+/// Guards against the runner being started again, with `arg` the first argument of the process.
+fn enter(arg: Option<&str>) {
+    // A benchmark that calls the runner, or runs the bench executable, would otherwise keep
+    // starting benchmark processes that start benchmark processes
+    let nested = std::env::var_os(BENCHMARK_PROCESS_VAR).is_some() && arg != Some("--iai-run");
+    if ENTERED.swap(true, Ordering::Relaxed) || nested {
+        child_error(
+            "a benchmark started the iai runner again, by calling it or by running the bench \
+             executable. Benchmarks can't run benchmarks."
+                .to_owned(),
+        );
     }
 }
 
+/// Applies the environment variables to `config`, and records what the benchmarks were built
+/// with.
+fn configure(config: &mut Config, executable: &str) -> Result<(), Error> {
+    config.cache = config.cache.with_env()?;
+    if let Ok(weights) = std::env::var("IAI_CYCLE_WEIGHTS") {
+        config.cycle_weights = config::parse_cycle_weights(&weights).ok_or_else(|| {
+            Error::Argument(format!(
+                "invalid cycle weights {:?} for IAI_CYCLE_WEIGHTS (expected <l1>,<ll>,<ram>, e.g. \"1,5,35\")",
+                weights
+            ))
+        })?;
+    }
+    if let Some(template) = config.template.clone() {
+        let _ = template::TEMPLATE.set(template);
+    }
+    let clock_hz = std::env::var("IAI_CLOCK_HZ")
+        .ok()
+        .map(|hz| {
+            policy::parse_clock(&hz).ok_or_else(|| {
+                Error::Argument(format!(
+                    "invalid clock rate {:?} for IAI_CLOCK_HZ (expected e.g. \"3.5GHz\" or \"3500000000\")",
+                    hz
+                ))
+            })
+        })
+        .transpose()?;
+    if let Some(hz) = clock_hz.or(config.clock_hz) {
+        let _ = CLOCK_HZ.set(hz);
+    }
+    config.build_profile = build_profile::detect(executable, config.debug_assertions);
+    Ok(())
+}
+
+/// Whether the benchmarks run with ASLR, which is turned off unless the configuration or
+/// `IAI_ALLOW_ASLR` allows it, and warns if it can't be.
+fn allow_aslr(config: &Config) -> bool {
+    let allow_aslr = config.allow_aslr || std::env::var_os("IAI_ALLOW_ASLR").is_some();
+    if !allow_aslr {
+        if let Some(warning) = aslr_warning() {
+            eprintln!("Warning: {} Set IAI_ALLOW_ASLR to silence this.", warning);
+        }
+    }
+    allow_aslr
+}
+
 /// Custom-test-framework runner. Should not be called directly.
 #[doc(hidden)]
-pub fn runner(benches: &[&(&'static str, fn())]) {
+pub fn runner(benches: &[&Benchmark]) {
+    runner_with_config(benches, Config::default())
+}
+
+/// Like [`runner`], with suite-wide defaults from `main!(config = ...; ...)`. Should not be called
+/// directly.
+#[doc(hidden)]
+pub fn runner_with_config(benches: &[&Benchmark], mut config: Config) {
+    config.variant = std::env::var(matrix::VARIANT_VAR).ok();
     let mut args_iter = args();
     let executable = args_iter.next().unwrap();
     let arg = args_iter.next();
+    enter(arg.as_deref());
     if let Some("--iai-run") = arg.as_deref() {
         // In this branch, we're running under cachegrind, so execute the benchmark as quickly as
         // possible and exit
-        let index: isize = args_iter.next().unwrap().parse().unwrap();
+        let index = args_iter.next();
+        let mut harness = None;
+        let mut bench_name = None;
+        let mut load_only = false;
+        let mut warm = false;
+        let mut rusage = false;
+        let mut wall_clock_runs = None;
+        let mut client_requests = false;
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                wall_clock::CHILD_ARG => {
+                    wall_clock_runs = args_iter.next().and_then(|runs| runs.parse().ok())
+                }
+                RusageStats::CHILD_ARG => rusage = true,
+                "--iai-harness" => harness = args_iter.next(),
+                "--iai-bench" => bench_name = args_iter.next(),
+                "--iai-load-only" => load_only = true,
+                "--iai-items-only" => ITEMS_ONLY.store(true, Ordering::Relaxed),
+                "--iai-warm" => warm = true,
+                client_request::CHILD_ARG => client_requests = true,
+                _ => {}
+            }
+        }
+        if harness.as_deref() != Some(HARNESS_VERSION) {
+            child_error(format!(
+                "this benchmark was built with iai harness {}, but was started by harness {}. \
+                 Rebuild the benchmarks.",
+                HARNESS_VERSION,
+                harness.as_deref().unwrap_or("unknown")
+            ));
+        }
+        let index: isize = match index.as_deref().map(str::parse) {
+            Some(Ok(index)) => index,
+            _ => child_error(format!(
+                "invalid benchmark index {:?}",
+                index.unwrap_or_default()
+            )),
+        };
 
         // -1 is used as a signal to do nothing and return. By recording an empty benchmark, we can
         // subtract out the overhead from startup and dispatching to the right benchmark.
         if index == -1 {
+            // The requests themselves cost a few instructions, to be subtracted
+            if client_requests {
+                start_measurement();
+                stop_measurement();
+            }
+            RunMetadata::report();
+            if rusage {
+                RusageStats::report();
+            }
             return;
         }
 
-        let index = index as usize;
+        // Dispatch by name, so that the index can't silently point at another benchmark; the index
+        // disambiguates duplicate names
+        let by_index = usize::try_from(index).ok().and_then(|i| benches.get(i));
+        let bench = match bench_name {
+            Some(name) => by_index
+                .filter(|bench| bench.name == name)
+                .or_else(|| benches.iter().find(|bench| bench.name == name))
+                .unwrap_or_else(|| child_error(format!("no benchmark named {:?}", name))),
+            None => by_index.unwrap_or_else(|| {
+                child_error(format!(
+                    "benchmark index {} is out of range, there are {} benchmarks",
+                    index,
+                    benches.len()
+                ))
+            }),
+        };
 
-        (benches[index].1)();
+        if !bench.artifacts.is_empty() {
+            if let Err(e) = project::load(&mut config) {
+                child_error(e.to_string());
+            }
+            config.build_profile = build_profile::detect(&executable, config.debug_assertions);
+            if let Err(e) = artifact::load(bench, &config) {
+                child_error(format!("unable to load the artifacts: {}", e));
+            }
+        }
+        let function = bench
+            .extern_c
+            .as_ref()
+            .map(ExternC::load)
+            .transpose()
+            .unwrap_or_else(|e| child_error(e.to_string()));
+        if let Some(runs) = wall_clock_runs {
+            match function {
+                Some(function) => {
+                    wall_clock::time(runs, || function());
+                }
+                None => wall_clock::time(runs, || bench.call()),
+            }
+            return;
+        }
+        let calls = match (load_only, warm) {
+            (true, _) => 0,
+            (false, false) => 1,
+            (false, true) => 2,
+        };
+        if client_requests {
+            start_measurement();
+        }
+        for _ in 0..calls {
+            match function {
+                Some(function) => function(),
+                None => bench.call(),
+            }
+        }
+        if client_requests {
+            stop_measurement();
+        }
+        RunMetadata::report();
+        if rusage {
+            RusageStats::report();
+        }
         return;
     }
-    let json = arg.map_or(false, |a| a == "--json");
+    if let Err(e) = run_suite(
+        benches,
+        config,
+        &executable,
+        arg.into_iter().chain(args_iter),
+    ) {
+        eprintln!("iai: {}.", e);
+        std::process::exit(1);
+    }
+}
+
+/// The runner under cargo, with `args` its command line. Whatever keeps the suite from running is
+/// returned, to be reported once.
+fn run_suite(
+    benches: &[&Benchmark],
+    mut config: Config,
+    executable: &str,
+    args: impl IntoIterator<Item = String>,
+) -> Result<(), Error> {
+    let Args {
+        format,
+        list,
+        layout_sensitivity,
+        dhat,
+        massif,
+        rusage,
+        warm,
+        baseline_dir,
+        save_baseline,
+        baseline,
+        compare_with,
+        export_baseline,
+        import_baseline,
+        exports,
+        github_summary,
+        feature_matrix,
+        toolchains,
+        pgo,
+        against_published,
+        wall_clock_fallback,
+        require_valgrind,
+        perf,
+        threshold,
+        threshold_profile,
+        fail_on_regression,
+        accepted_regressions,
+        accept,
+        sort,
+        only_changed,
+        verbose,
+        self_test,
+        min_instructions,
+        top_functions: top_functions_arg,
+        tool,
+        cpu_profile,
+        branch_sim,
+        per_bench_calibration,
+        client_requests,
+        filter,
+        ignored,
+        include_ignored,
+    } = Args::parse(args)?;
+    project::load(&mut config)?;
+    if let Some(profile) = cpu_profile {
+        config.cache = profile;
+    }
+    config.branch_sim |= branch_sim;
+    config.per_bench_calibration |= per_bench_calibration;
+    config.client_requests |= client_requests;
+    config.top_functions = top_functions_arg.or(config.top_functions);
+    if let Some(tool) = tool {
+        config.tool = tool;
+    }
+    configure(&mut config, executable)?;
+    config.save_baseline = save_baseline;
+    config.compare_baseline = baseline;
+    if let Some(name) = threshold_profile {
+        match config
+            .threshold_profiles
+            .iter()
+            .find(|(profile, _)| *profile == name)
+        {
+            Some(&(_, tolerance)) => config.threshold = Some(tolerance),
+            None => {
+                let known: Vec<&str> = config
+                    .threshold_profiles
+                    .iter()
+                    .map(|(profile, _)| profile.as_str())
+                    .collect();
+                return Err(Error::Argument(format!(
+                    "unknown threshold profile {:?} (this suite defines {})",
+                    name,
+                    if known.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        known.join(", ")
+                    }
+                )));
+            }
+        }
+    }
+
+    // Baseline transfer operations only touch stored results, they don't run anything
+    if let Some(path) = export_baseline {
+        return baseline::export(&path, executable, &config, benches);
+    }
+    if let Some(path) = import_baseline {
+        return baseline::import(&path, executable, &config, benches);
+    }
 
     // Otherwise we're running normally, under cargo
-    if !check_valgrind() {
-        return;
+    let selected: Vec<(usize, &Benchmark)> = benches
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, bench)| filter.matches(bench.name))
+        .collect();
+    // Like libtest, ignored benchmarks are listed, but only run when asked to
+    let skipped_ignored = selected
+        .iter()
+        .filter(|(_, bench)| bench.ignore && !(list || ignored || include_ignored))
+        .count();
+    let selected: Vec<(usize, &Benchmark)> = selected
+        .into_iter()
+        .filter(|(_, bench)| match (ignored, include_ignored || list) {
+            (true, _) => bench.ignore,
+            (false, all) => all || !bench.ignore,
+        })
+        .collect();
+    // In the format of libtest's `--list`, which is what tools discovering benchmarks expect
+    if list {
+        for (_, bench) in &selected {
+            println!("{}: bench", bench.name);
+        }
+        return Ok(());
+    }
+    if selected.is_empty() {
+        eprintln!("No benchmarks match {}", filter);
+        return Ok(());
+    }
+    if pgo {
+        return matrix::pgo(executable, &filter.args());
+    }
+    if let Some(version) = against_published {
+        return published::run(executable, &version, &filter.args());
+    }
+    if !feature_matrix.is_empty() || !toolchains.is_empty() {
+        let variants = matrix::variants(&feature_matrix, &toolchains);
+        return matrix::run(executable, &variants, &filter.args());
+    }
+    if compare_with.is_some() && baseline_dir.is_some() {
+        return Err(Error::Argument(
+            "--compare-with and --baseline-dir can't be used together".to_owned(),
+        ));
+    }
+    session::check(&config)?;
+    let comparison = compare_with
+        .map(|path| Comparison::load(&path))
+        .transpose()?;
+    if perf {
+        if warm || layout_sensitivity || dhat || massif || rusage {
+            return Err(Error::Argument(
+                "IAI_BACKEND=perf can't be combined with --warm, --layout-sensitivity or \
+                 --backends"
+                    .to_owned(),
+            ));
+        }
+        perf::check_perf()?;
+    } else if !check_valgrind() {
+        if require_valgrind {
+            // Asked for on the command line, or by IAI_REQUIRE_VALGRIND
+            return Err(Error::ValgrindMissing);
+        }
+        if wall_clock_fallback {
+            artifact::build(&selected, &config)?;
+            let failed = wall_clock::run(executable, &selected, format);
+            if !failed.is_empty() {
+                eprintln!(
+                    "{} benchmark(s) failed: {}",
+                    failed.len(),
+                    failed.join(", ")
+                );
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    } else {
+        check_capabilities(&config, verbose, dhat, massif)?;
     }
 
     let arch = get_arch();
+    let allow_aslr = allow_aslr(&config);
+    if self_test {
+        return self_test::run(arch.as_deref(), executable, allow_aslr, &config);
+    }
+    artifact::build(&selected, &config)?;
 
-    let allow_aslr = std::env::var_os("IAI_ALLOW_ASLR").is_some();
-
-    let (calibration, old_calibration) =
-        run_bench(&arch, &executable, -1, "iai_calibration", allow_aslr);
+    if accept && baseline_dir.is_none() {
+        eprintln!("--accept only has an effect together with --baseline-dir");
+    }
+    let mut baseline_dir = baseline_dir
+        .map(|dir| BaselineDir::load(&dir, executable, &config))
+        .transpose()?;
+    // Command-line arguments and environment variables override the suite's configuration
+    let threshold = threshold.or(config.threshold);
+    let min_instructions = min_instructions.or(config.min_instructions);
+    let mut policy = Policy::new(threshold, fail_on_regression, min_instructions);
+    if let Some(path) = accepted_regressions {
+        policy.load_accepted(&path)?;
+    }
+    // Without the caches only the instructions are known, and `perf` counts the actual cycles
+    let detail = if baseline_dir.is_some() || perf {
+        Detail::InstructionsOnly
+    } else {
+        Detail::Full
+    };
+    let extras = Extras {
+        warm,
+        layout_sensitivity,
+        dhat,
+        massif,
+        rusage,
+        perf,
+    };
+    let session = Session::new(
+        executable,
+        &config,
+        (arch, allow_aslr),
+        extras,
+        policy,
+        comparison,
+    )?;
+    let text_sizes = binary_size::measure(executable, &selected, &config)?;
+    // The output of `--format` comes first, and is told about the run like any other reporter
+    let mut reporters = Reporters::default();
+    match format {
+        Format::Pretty => reporters.push(formats::Pretty::new(
+            &config,
+            session.policy.clone(),
+            detail,
+            // Each benchmark is reported as soon as it is measured, unless they are to be sorted
+            (verbose, only_changed, sort.is_none()),
+            skipped_ignored,
+            text_sizes,
+        )),
+        Format::Json => reporters.push(formats::Json::new(text_sizes)),
+        Format::Markdown => reporters.push(formats::Markdown(detail)),
+        Format::Junit => reporters.push(formats::Junit),
+        Format::Bmf => reporters.push(formats::Bmf),
+    }
+    reporters.extend(&config.reporters);
 
-    for (i, (name, _func)) in benches.iter().enumerate() {
-        if json {
-            println!(r#"{{"event":"run","benchmark":"{name}"}}"#);
-        } else {
-            println!("{}", name);
-        }
-        let (stats, old_stats) = run_bench(&arch, &executable, i as isize, name, allow_aslr);
-        let (stats, old_stats) = (
-            stats.subtract(&calibration),
-            match (&old_stats, &old_calibration) {
-                (Some(old_stats), Some(old_calibration)) => {
-                    Some(old_stats.subtract(old_calibration))
-                }
-                _ => None,
-            },
-        );
-        if json {
-            if let Some(old) = old_stats {
-                println!(
-                    r#"{{"event":"ran","benchmark":"{name}","stats":{stats},"old_stats":{old}}}"#
-                );
-            } else {
-                println!(r#"{{"event":"ran","benchmark":"{name}","stats":{stats}}}"#);
+    let mut results = Vec::with_capacity(benches.len());
+    let mut failed = Vec::new();
+    let names: Vec<&str> = selected.iter().map(|(_, bench)| bench.name).collect();
+    reporters.each(|reporter| reporter.on_suite_start(&names));
+    for &(i, bench) in &selected {
+        let name = bench.name;
+        reporters.each(|reporter| reporter.on_bench_start(name));
+        let mut result = match session.measure(i, bench) {
+            Ok(result) => result,
+            Err(e) => {
+                reporters.each(|reporter| reporter.on_bench_failed(name, &e));
+                failed.push(name);
+                continue;
             }
-            continue;
-        }
-
-        fn signed_short(n: f64) -> String {
-            let n_abs = n.abs();
-
-            if n_abs < 10.0 {
-                format!("{:+.6}", n)
-            } else if n_abs < 100.0 {
-                format!("{:+.5}", n)
-            } else if n_abs < 1000.0 {
-                format!("{:+.4}", n)
-            } else if n_abs < 10000.0 {
-                format!("{:+.3}", n)
-            } else if n_abs < 100000.0 {
-                format!("{:+.2}", n)
-            } else if n_abs < 1000000.0 {
-                format!("{:+.1}", n)
+        };
+        // Instructions-only mode: compare against the committed baseline rather than `.old`
+        if let Some(baseline_dir) = &mut baseline_dir {
+            result.old = None;
+            result.committed = baseline_dir.get(name);
+            if accept {
+                baseline_dir.accept(name, result.stats.instruction_reads());
             } else {
-                format!("{:+.0}", n)
+                baseline_dir.insert_missing(name, result.stats.instruction_reads());
             }
         }
+        session.assess(&mut result);
+        reporters.each(|reporter| reporter.on_bench_result(&result));
+        results.push(result);
+    }
+    let Session {
+        policy, history, ..
+    } = session;
 
-        fn percentage_diff(new: u64, old: u64) -> String {
-            if new == old {
-                return " (No change)".to_owned();
-            }
+    if format != Format::Json {
+        if let Some(sort) = sort {
+            sort.apply(&mut results);
+        }
+    }
+    let summary = report::suite_summary(&results, &policy, failed.len());
+    reporters.each(|reporter| reporter.on_suite_results(&results, &summary));
+    // Only stored once the results are reported, which a failure to store them doesn't prevent
+    if let Some(history) = history {
+        history.record(&results, &config)?;
+    }
+    for export in &exports {
+        export.write(&results, &config)?;
+    }
+    if github_summary {
+        report::github_summary(&results, detail);
+    }
 
-            let new: f64 = new as f64;
-            let old: f64 = old as f64;
+    // Accepting new results means regressions are intended
+    let gated = !(accept && baseline_dir.is_some());
+    let regressions: Vec<&str> = results
+        .iter()
+        .filter(|r| gated && r.regression.as_ref().is_some_and(|r| r.accepted.is_none()))
+        .map(|r| r.bench.name)
+        .collect();
 
-            let diff = (new - old) / old;
-            let pct = diff * 100.0;
+    // Budgets are absolute, so they hold even when accepting new results
+    let over_budget: Vec<&str> = results
+        .iter()
+        .filter(|r| matches!(r.budget(), Some((_, true))))
+        .map(|r| r.bench.name)
+        .collect();
 
-            if pct < 0.0001 {
-                return " (No change)".to_owned();
-            }
+    if let Some(baseline_dir) = baseline_dir {
+        baseline_dir.save()?;
+    }
+
+    eprintln!("{}", report::exit_summary(&results, &policy, failed.len()));
+    if !regressions.is_empty() {
+        eprintln!(
+            "{} benchmark(s) regressed beyond their tolerance: {}",
+            regressions.len(),
+            regressions.join(", ")
+        );
+    }
+    if !over_budget.is_empty() {
+        eprintln!(
+            "{} benchmark(s) exceeded their time budget: {}",
+            over_budget.len(),
+            over_budget.join(", ")
+        );
+    }
+    if !failed.is_empty() {
+        eprintln!(
+            "{} benchmark(s) failed: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    if !regressions.is_empty() || !over_budget.is_empty() || !failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-            format!(" ({:>+6}%)", signed_short(pct))
+/// Checks that the installed valgrind has what the configuration needs, and describes it with
+/// `--verbose`.
+fn check_capabilities(
+    config: &Config,
+    verbose: bool,
+    dhat: bool,
+    massif: bool,
+) -> Result<(), Error> {
+    let capabilities = Capabilities::probe();
+    if verbose {
+        eprintln!("{}", capabilities.describe());
+        if let Some(profile) = &config.build_profile {
+            eprintln!("Built with the {} profile", profile);
         }
+    }
+    let mut checks = Vec::new();
+    if let Some(minimum) = config.min_valgrind {
+        checks.push(capabilities.check_version(minimum));
+    }
+    checks.push(capabilities.check_backend::<CachegrindStats>(config));
+    if dhat {
+        checks.push(capabilities.check_backend::<HeapStats>(config));
+    }
+    if massif {
+        checks.push(capabilities.check_backend::<MassifStats>(config));
+    }
+    match checks.into_iter().find_map(Result::err) {
+        Some(problem) => Err(Error::Unsupported(problem)),
+        None => Ok(()),
+    }
+}
 
-        println!(
-            "  Instructions:     {:>15}{}",
-            stats.instruction_reads,
-            match &old_stats {
-                Some(old) => percentage_diff(stats.instruction_reads, old.instruction_reads),
-                None => "".to_owned(),
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_round_trip_through_json() {
+        let stats = CachegrindStats::from_events(
+            [("Ir", 100), ("Dr", 40), ("Bc", 7)]
+                .iter()
+                .map(|&(event, count)| (event.to_owned(), count))
+                .collect(),
         );
-        let summary = stats.summarize();
-        let old_summary = old_stats.map(|stat| stat.summarize());
-        println!(
-            "  L1 Accesses:      {:>15}{}",
-            summary.l1_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.l1_hits, old.l1_hits),
-                None => "".to_owned(),
-            }
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CachegrindStats>(&json).unwrap(),
+            stats
         );
-        println!(
-            "  L2 Accesses:      {:>15}{}",
-            summary.l3_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.l3_hits, old.l3_hits),
-                None => "".to_owned(),
-            }
+    }
+
+    #[test]
+    fn stats_without_extra_or_unrecorded_events() {
+        let stats: CachegrindStats =
+            serde_json::from_str(r#"{"instruction_reads":100,"data_reads":null}"#).unwrap();
+        assert_eq!(stats.instruction_reads(), 100);
+        assert_eq!(stats.events().len(), 1);
+    }
+
+    #[test]
+    fn estimates_times_in_readable_units() {
+        let time = |cycles, hz| {
+            let time = EstimatedTime::from_cycles(cycles, hz);
+            (time.to_string(), time.unit)
+        };
+        // Each unit until the value would reach a thousand of it
+        assert_eq!(time(999, 1_000_000_000), ("999.000ns".to_owned(), "ns"));
+        assert_eq!(time(1_000, 1_000_000_000), ("1.000μs".to_owned(), "μs"));
+        assert_eq!(time(999_999, 1_000_000_000), ("999.999μs".to_owned(), "μs"));
+        assert_eq!(time(1_000_000, 1_000_000_000), ("1.000ms".to_owned(), "ms"));
+        assert_eq!(
+            time(1_500_000_000, 1_000_000_000),
+            ("1.500s".to_owned(), "s")
         );
-        println!(
-            "  RAM Accesses:     {:>15}{}",
-            summary.ram_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.ram_hits, old.ram_hits),
-                None => "".to_owned(),
-            }
+        // Cycles divided by the clock rate, without any other factor
+        assert_eq!(time(3_500, 3_500_000_000), ("1.000μs".to_owned(), "μs"));
+        assert_eq!(time(48, 48_000_000), ("1.000μs".to_owned(), "μs"));
+        assert_eq!(time(0, 1_000_000_000), ("0.000ns".to_owned(), "ns"));
+    }
+
+    #[test]
+    fn estimates_no_time_without_a_clock() {
+        assert_eq!(EstimatedTime::at_clock(1_000, None), None);
+        assert_eq!(EstimatedTime::at_clock(1_000, Some(0)), None);
+        assert_eq!(
+            EstimatedTime::at_clock(1_000, Some(1_000_000_000)),
+            Some(EstimatedTime::from_cycles(1_000, 1_000_000_000))
         );
-        println!(
-            "  Estimated Cycles: {:>15}{}{}",
-            summary.cycles(),
-            match &old_summary {
-                Some(old) => percentage_diff(summary.cycles(), old.cycles()),
-                None => "".to_owned(),
-            },
-            match clock() {
-                Some(c) => {
-                    format!(" ({:.3}μs)", (10000.0 / c as f64) * summary.cycles() as f64)
-                }
-                None => "".to_owned(),
-            }
+    }
+
+    #[test]
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    fn reads_the_clock_from_the_model_name() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: GenuineIntel\n\
+                       model name\t: Intel(R) Core(TM) i7-6700K CPU @ 4.00GHz\n\
+                       processor\t: 1\n\
+                       model name\t: Intel(R) Core(TM) i7-6700K CPU @ 4.00GHz\n";
+        assert_eq!(model_name_clock(cpuinfo.as_bytes()), Some(4_000_000_000));
+        let clock = |model: &str| model_name_clock(format!("model name : {}\n", model).as_bytes());
+        assert_eq!(
+            clock("Intel(R) Pentium(R) III CPU @ 800MHz"),
+            Some(800_000_000)
         );
-        println!();
+        // Most AMD and ARM processors don't advertise their clock rate in their name
+        assert_eq!(clock("AMD Ryzen 9 5950X 16-Core Processor"), None);
+        assert_eq!(model_name_clock("processor : 0\n".as_bytes()), None);
+    }
+
+    #[test]
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    fn reads_the_clock_from_sysctl() {
+        assert_eq!(sysctl_value("4000000000\n"), Some(4_000_000_000));
+        assert_eq!(sysctl_value("0\n"), None);
+        assert_eq!(sysctl_value(""), None);
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    fn program_and_args(cmd: &Command) -> Vec<&std::ffi::OsStr> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .collect()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn disables_aslr_with_setarch() {
+        let cmd = valgrind_without_aslr(Some("x86_64"));
+        assert_eq!(
+            program_and_args(&cmd),
+            ["setarch", "x86_64", "-R", "valgrind"]
+        );
+        let cmd = valgrind_without_aslr(None);
+        assert_eq!(program_and_args(&cmd), ["setarch", "-R", "valgrind"]);
+        assert_eq!(aslr_warning(), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn disables_aslr_with_proccontrol() {
+        let cmd = valgrind_without_aslr(None);
+        assert_eq!(
+            program_and_args(&cmd),
+            ["proccontrol", "-m", "aslr", "-s", "disable", "valgrind"]
+        );
+        assert_eq!(aslr_warning(), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "netbsd")]
+    fn warns_unless_aslr_is_disabled_system_wide() {
+        assert_eq!(program_and_args(&valgrind_without_aslr(None)), ["valgrind"]);
+        let disabled = Command::new("sysctl")
+            .args(["-n", "security.pax.aslr.enabled"])
+            .output()
+            .is_ok_and(|output| output.stdout.trim_ascii() == b"0");
+        assert_eq!(aslr_warning().is_none(), disabled);
+    }
+
+    #[test]
+    #[cfg(target_os = "openbsd")]
+    fn always_warns_about_aslr() {
+        assert_eq!(program_and_args(&valgrind_without_aslr(None)), ["valgrind"]);
+        assert!(aslr_warning().is_some());
     }
 }
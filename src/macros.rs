@@ -20,7 +20,8 @@
 ///
 /// Since we've disabled the default benchmark harness, we need to add our own:
 ///
-/// ```ignore
+/// ```no_run
+/// # mod suite {
 /// fn bench_method1() {
 /// }
 ///
@@ -28,32 +29,195 @@
 /// }
 ///
 /// iai::main!(bench_method1, bench_method2);
+/// # }
 /// ```
 ///
 /// The `iai::main` macro expands to a `main` function which runs all of the
 /// benchmarks in the given groups.
 ///
+/// Benchmarks can return any type. The returned value goes through [`black_box`](crate::black_box),
+/// so the computation behind it isn't optimized away for being unused. One that returns an `Err`
+/// is reported as failed, with the error, instead of measured; the other benchmarks still run, and
+/// the run fails at the end.
+///
+/// Functions exported from shared libraries can be benchmarked too, which is handy for crates
+/// wrapping a native library. List them after a `;`, grouped by library; each symbol must be an
+/// `extern "C" fn()` and the cost of loading the library is not measured:
+///
+/// ```no_run
+/// # mod suite {
+/// # fn bench_method1() {}
+/// iai::main!(
+///     bench_method1;
+///     extern "C" "target/native/libfoo.so": foo_bench_parse, foo_bench_render;
+/// );
+/// # }
+/// ```
+///
+/// Benchmarks can be put in groups, also after a `;`. Their names start with the group, like
+/// `hashing::bench_sip`, so that `cargo bench -- 'hashing::*'` runs the group alone, and the
+/// summary at the end of the run shows the totals of every group:
+///
+/// ```no_run
+/// # mod suite {
+/// # fn bench_method1() {}
+/// # fn bench_sip() {}
+/// # fn bench_fx() {}
+/// # fn bench_ahash(_len: usize) {}
+/// # fn parse(_size: usize) {}
+/// iai::main!(
+///     bench_method1;
+///     group "hashing": bench_sip, bench_fx, bench_ahash(16, 4096);
+///     group "parsing": parse(10, 1000);
+/// );
+/// # }
+/// ```
+///
+/// Suite-wide defaults can be given in a [`Config`](crate::Config) before the benchmarks.
+/// Command-line arguments and environment variables still override them. The benchmarks can be
+/// introduced with `benches =` to tell them apart:
+///
+/// ```no_run
+/// # mod suite {
+/// # fn bench_method1() {}
+/// # fn bench_method2() {}
+/// iai::main!(
+///     config = iai::Config::new().cache("skylake").threshold("2%");
+///     benches = bench_method1, bench_method2
+/// );
+/// # }
+/// ```
+///
+/// A benchmark taking an input is measured once per input given in parentheses, each as a
+/// benchmark of its own named after the function and the input, like `parse/1000`:
+///
+/// ```no_run
+/// # mod suite {
+/// # struct Document;
+/// # impl Document {
+/// #     fn parse(_input: &str) -> Document {
+/// #         Document
+/// #     }
+/// # }
+/// # fn make_input(size: usize) -> String {
+/// #     "x".repeat(size)
+/// # }
+/// # fn bench_method1() {}
+/// fn parse(size: usize) -> Document {
+///     Document::parse(&make_input(size))
+/// }
+///
+/// iai::main!(bench_method1, parse(10, 1000, 100_000));
+/// # }
+/// ```
+///
 #[macro_export]
 macro_rules! main {
-    ( $( $func_name:ident ),+ $(,)* ) => {
+    ( config = $config:expr ; benches = $( $rest:tt )+ ) => {
+        $crate::main!(@harness $config; $( $rest )+);
+    };
+    ( benches = $( $rest:tt )+ ) => {
+        $crate::main!(@harness $crate::Config::default(); $( $rest )+);
+    };
+    ( config = $config:expr ; $( $rest:tt )+ ) => {
+        $crate::main!(@harness $config; $( $rest )+);
+    };
+    // Without benchmarks before the sections
+    ( @harness $config:expr ; group $group:literal $( $rest:tt )* ) => {
+        $crate::main!(@harness $config; ; group $group $( $rest )*);
+    };
+    ( @harness $config:expr ; $( $func_name:ident $( ( $( $input:expr ),+ ) )? ),* $(,)* $( ; $( $sections:tt )* )? ) => {
         mod iai_wrappers {
             $(
-                pub fn $func_name() {
-                    let _ = $crate::black_box(super::$func_name());
-                }
-            )+
+                $crate::main!(@wrapper $func_name $( ( $( $input ),+ ) )?);
+            )*
         }
 
         fn main() {
 
-            let benchmarks : &[&(&'static str, fn())]= &[
+            let mut benchmarks: Vec<&[$crate::Benchmark]> = vec![
 
                 $(
-                    &(stringify!($func_name), iai_wrappers::$func_name),
-                )+
+                    $crate::main!(@benches [] $func_name $( ( $( $input ),+ ) )?),
+                )*
             ];
+            $( $crate::main!(@sections benchmarks; $( $sections )*); )?
 
-            $crate::runner(benchmarks);
+            let benchmarks: Vec<&$crate::Benchmark> = benchmarks.into_iter().flatten().collect();
+            let config = $config.debug_assertions(cfg!(debug_assertions));
+            $crate::runner_with_config(&benchmarks, config);
         }
-    }
+    };
+    // The sections after the first `;`, one after the other
+    ( @sections $list:ident ; ) => {};
+    ( @sections $list:ident ; ; $( $rest:tt )* ) => {
+        $crate::main!(@sections $list; $( $rest )*);
+    };
+    ( @sections $list:ident ; group $group:literal : $( $func_name:ident $( ( $( $input:expr ),+ ) )? ),+ $(,)* $( ; $( $rest:tt )* )? ) => {
+        $(
+            $list.push($crate::main!(@benches [$group] $func_name $( ( $( $input ),+ ) )?));
+        )+
+        $( $crate::main!(@sections $list; $( $rest )*); )?
+    };
+    ( @sections $list:ident ; extern "C" $library:literal : $( $symbol:ident ),+ $(,)* $( ; $( $rest:tt )* )? ) => {
+        $list.push({
+            const BENCHMARKS: &[$crate::Benchmark] = &[
+                $(
+                    $crate::Benchmark::extern_c(stringify!($symbol), $library, stringify!($symbol)),
+                )+
+            ];
+            BENCHMARKS
+        });
+        $( $crate::main!(@sections $list; $( $rest )*); )?
+    };
+    // The wrapper of a benchmark, which takes care of what it returns
+    ( @wrapper $func_name:ident ) => {
+        pub fn $func_name() {
+            $crate::main!(@check super::$func_name());
+        }
+    };
+    // Benchmarks with inputs are wrapped by closures instead, one per input
+    ( @wrapper $func_name:ident ( $( $input:expr ),+ ) ) => {};
+    ( @benches [] $func_name:ident ) => {{
+        const BENCHMARKS: &[$crate::Benchmark] =
+            &[$crate::Benchmark::new(stringify!($func_name), iai_wrappers::$func_name)];
+        BENCHMARKS
+    }};
+    // Benchmarks of a group are wrapped by closures too, named after the group
+    ( @benches [$group:literal] $func_name:ident ) => {{
+        const BENCHMARKS: &[$crate::Benchmark] = &[$crate::Benchmark::new(
+            concat!($group, "::", stringify!($func_name)),
+            || {
+                $crate::main!(@check $func_name());
+            },
+        )];
+        BENCHMARKS
+    }};
+    ( @benches $prefix:tt $func_name:ident ( $( $input:expr ),+ ) ) => {{
+        const BENCHMARKS: &[$crate::Benchmark] = &[
+            $(
+                $crate::Benchmark::new(
+                    $crate::main!(@name $prefix $func_name, $input),
+                    || {
+                        $crate::main!(@check $func_name($crate::black_box($input)));
+                    },
+                ),
+            )+
+        ];
+        BENCHMARKS
+    }};
+    // Passes what a benchmark returned through `black_box`, and reports an `Err` as a failure
+    ( @check $call:expr ) => {{
+        use $crate::{ReturnedResult as _, ReturnedValue as _};
+        (&$crate::Returned($crate::black_box($call))).check();
+    }};
+    ( @name [] $func_name:ident, $input:expr ) => {
+        concat!(stringify!($func_name), "/", stringify!($input))
+    };
+    ( @name [$group:literal] $func_name:ident, $input:expr ) => {
+        concat!($group, "::", stringify!($func_name), "/", stringify!($input))
+    };
+    ( $( $rest:tt )+ ) => {
+        $crate::main!(@harness $crate::Config::default(); $( $rest )+);
+    };
 }
@@ -0,0 +1,153 @@
+//! Peak memory measurement with massif (`--massif`), including the stack.
+//!
+//! Massif takes snapshots of the heap and stack sizes during the run; the peaks are the largest
+//! values over all of them.
+
+use std::path::Path;
+
+use crate::{
+    backend::{Backend, Metric},
+    Config, Error,
+};
+
+/// Peak memory usage of one benchmark, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct MassifStats {
+    /// Requested heap memory.
+    pub(crate) peak_heap_bytes: u64,
+    /// Allocator bookkeeping and alignment on top of the requested heap memory.
+    pub(crate) peak_heap_extra_bytes: u64,
+    pub(crate) peak_stack_bytes: u64,
+    /// Heap, heap overhead and stack together, which may peak at another time than each of them.
+    pub(crate) peak_total_bytes: u64,
+}
+
+impl Backend for MassifStats {
+    const KEY: &'static str = "massif";
+
+    fn tool(_: &Config) -> &'static str {
+        "massif"
+    }
+
+    fn args(_: &Config) -> Vec<String> {
+        vec!["--stacks=yes".to_owned()]
+    }
+
+    fn parse(file: &Path) -> Result<MassifStats, Error> {
+        let profile = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+        let mut stats = MassifStats::default();
+        let (mut heap, mut extra) = (0, 0);
+        let value = |v: &str| -> Result<u64, Error> {
+            v.trim()
+                .parse()
+                .map_err(|_| Error::output(file, format!("invalid size {:?} in massif output", v)))
+        };
+        // Every snapshot lists mem_heap_B, mem_heap_extra_B and mem_stacks_B, in that order
+        for line in profile.lines() {
+            if let Some(v) = line.strip_prefix("mem_heap_B=") {
+                heap = value(v)?;
+            } else if let Some(v) = line.strip_prefix("mem_heap_extra_B=") {
+                extra = value(v)?;
+            } else if let Some(v) = line.strip_prefix("mem_stacks_B=") {
+                let stack = value(v)?;
+                stats.peak_heap_bytes = stats.peak_heap_bytes.max(heap);
+                stats.peak_heap_extra_bytes = stats.peak_heap_extra_bytes.max(extra);
+                stats.peak_stack_bytes = stats.peak_stack_bytes.max(stack);
+                stats.peak_total_bytes = stats.peak_total_bytes.max(heap + extra + stack);
+            }
+        }
+        Ok(stats)
+    }
+
+    fn subtract(&self, calibration: &MassifStats) -> MassifStats {
+        MassifStats {
+            peak_heap_bytes: self
+                .peak_heap_bytes
+                .saturating_sub(calibration.peak_heap_bytes),
+            peak_heap_extra_bytes: self
+                .peak_heap_extra_bytes
+                .saturating_sub(calibration.peak_heap_extra_bytes),
+            peak_stack_bytes: self
+                .peak_stack_bytes
+                .saturating_sub(calibration.peak_stack_bytes),
+            peak_total_bytes: self
+                .peak_total_bytes
+                .saturating_sub(calibration.peak_total_bytes),
+        }
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        vec![
+            Metric::new("peak_heap_bytes", Some("Massif Heap"), self.peak_heap_bytes),
+            Metric::new("peak_heap_extra_bytes", None, self.peak_heap_extra_bytes),
+            Metric::new(
+                "peak_stack_bytes",
+                Some("Massif Stack"),
+                self.peak_stack_bytes,
+            ),
+            Metric::new(
+                "peak_total_bytes",
+                Some("Massif Total"),
+                self.peak_total_bytes,
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, contents: &str) -> Result<MassifStats, Error> {
+        let path = std::env::temp_dir().join(format!("iai-massif-{}.out", name));
+        std::fs::write(&path, contents).unwrap();
+        let stats = MassifStats::parse(&path);
+        std::fs::remove_file(&path).unwrap();
+        stats
+    }
+
+    #[test]
+    fn takes_the_peaks_over_the_snapshots() {
+        let stats = parse(
+            "snapshots",
+            "desc: --stacks=yes\n\
+             cmd: bench --iai-run 0\n\
+             time_unit: i\n\
+             #-----------\n\
+             snapshot=0\n\
+             #-----------\n\
+             time=0\n\
+             mem_heap_B=0\n\
+             mem_heap_extra_B=0\n\
+             mem_stacks_B=400\n\
+             heap_tree=empty\n\
+             #-----------\n\
+             snapshot=1\n\
+             #-----------\n\
+             time=1000\n\
+             mem_heap_B=4096\n\
+             mem_heap_extra_B=16\n\
+             mem_stacks_B=200\n\
+             heap_tree=peak\n",
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            MassifStats {
+                peak_heap_bytes: 4096,
+                peak_heap_extra_bytes: 16,
+                peak_stack_bytes: 400,
+                // Not the sum of the peaks, which were at different times
+                peak_total_bytes: 4312,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        let error = parse("invalid", "mem_heap_B=lots\n").unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("invalid size \"lots\" in massif output"));
+    }
+}
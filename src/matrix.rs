@@ -0,0 +1,314 @@
+//! Measuring several variants of the suite one after the other: under combinations of cargo
+//! features (`--feature-matrix "default;--features simd;--no-default-features"`), or built by
+//! different toolchains (`--toolchains stable,nightly-2024-06-01`), or with and without
+//! profile-guided optimization (`--pgo`).
+//!
+//! For every variant the bench target is rebuilt and run by `cargo bench`, with `--json`, and the
+//! instruction counts end up in one benchmark × variant table, relative to the first variant. Each
+//! variant keeps its own results (in `target/iai/features/<combination>` or
+//! `target/iai/toolchains/<toolchain>`), so that they aren't compared with each other.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+use crate::{file_safe, Error};
+
+/// Tells the runs of the variants which results directory is theirs.
+pub(crate) const VARIANT_VAR: &str = "IAI_VARIANT";
+
+/// One way to build and run the suite.
+pub(crate) struct Variant {
+    pub(crate) label: String,
+    pub(crate) toolchain: Option<String>,
+    /// Arguments for `cargo bench`.
+    pub(crate) args: Vec<String>,
+    /// Where its results are kept, relative to `target/iai`.
+    pub(crate) dir: String,
+    /// Added to `RUSTFLAGS`.
+    pub(crate) rustflags: Option<String>,
+    /// Instead of cargo's usual target directory.
+    pub(crate) target_dir: Option<PathBuf>,
+}
+
+/// The variants for `--feature-matrix` and `--toolchains`: every feature combination for every
+/// toolchain, with the default of whichever isn't given.
+pub(crate) fn variants(combinations: &[String], toolchains: &[String]) -> Vec<Variant> {
+    let default = ["default".to_owned()];
+    let combinations = if combinations.is_empty() {
+        &default[..]
+    } else {
+        combinations
+    };
+    let toolchains: Vec<Option<&String>> = match toolchains {
+        [] => vec![None],
+        toolchains => toolchains.iter().map(Some).collect(),
+    };
+    let mut variants = Vec::new();
+    for toolchain in &toolchains {
+        for combination in combinations {
+            let args = match combination.as_str() {
+                "default" => Vec::new(),
+                other => other.split_whitespace().map(str::to_owned).collect(),
+            };
+            let features = file_safe(&combination.replace(' ', "_"));
+            let (label, dir) = match toolchain {
+                Some(toolchain) if combinations.len() == 1 => {
+                    (toolchain.to_string(), format!("toolchains/{}", toolchain))
+                }
+                Some(toolchain) => (
+                    format!("+{} {}", toolchain, combination),
+                    format!("toolchains/{}/features/{}", toolchain, features),
+                ),
+                None => (combination.clone(), format!("features/{}", features)),
+            };
+            variants.push(Variant {
+                label,
+                toolchain: toolchain.cloned(),
+                args,
+                dir,
+                rustflags: None,
+                target_dir: None,
+            });
+        }
+    }
+    variants
+}
+
+/// Parses `;`-separated combinations of cargo arguments; `default` stands for no arguments.
+pub(crate) fn parse(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|combination| !combination.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The name of the bench target, from the executable `target/<profile>/deps/<name>-<hash>`.
+pub(crate) fn bench_target(executable: &str) -> Option<&str> {
+    let file = Path::new(executable).file_stem()?.to_str()?;
+    Some(file.rsplit_once('-').map_or(file, |(name, _)| name))
+}
+
+pub(crate) fn run(executable: &str, variants: &[Variant], filters: &[String]) -> Result<(), Error> {
+    let target = target_of(executable)?;
+    let columns: Vec<Vec<(String, u64)>> = variants
+        .iter()
+        .map(|variant| measure(target, variant, filters))
+        .collect::<Result<_, _>>()?;
+    let mut names: Vec<String> = Vec::new();
+    for (name, _) in columns.iter().flatten() {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    let labels: Vec<&str> = variants
+        .iter()
+        .map(|variant| variant.label.as_str())
+        .collect();
+    print(&labels, &names, &columns);
+    Ok(())
+}
+
+/// Measures the suite with profile-guided optimization (`--pgo`): the bench target is built with
+/// instrumentation and run once to collect a profile, which `llvm-profdata` merges, and the
+/// instruction counts of a build optimized with that profile are compared with those of a normal
+/// build. The PGO builds go to `target/iai/pgo/target`, to leave the normal build alone.
+pub(crate) fn pgo(executable: &str, filters: &[String]) -> Result<(), Error> {
+    let target = target_of(executable)?;
+    let dir = std::env::current_dir()
+        .map_err(|e| Error::io(Path::new("."), e))?
+        .join("target/iai/pgo");
+    let profiles = dir.join("profiles");
+    let _ = std::fs::remove_dir_all(&profiles);
+    let pgo_variant = |label: &str, flag: String| Variant {
+        label: label.to_owned(),
+        toolchain: None,
+        args: Vec::new(),
+        dir: format!("pgo/{}", label),
+        rustflags: Some(flag),
+        target_dir: Some(dir.join("target")),
+    };
+
+    measure(
+        target,
+        &pgo_variant(
+            "instrumented",
+            format!("-Cprofile-generate={}", profiles.display()),
+        ),
+        filters,
+    )?;
+    let merged = dir.join("merged.profdata");
+    let status = Command::new(llvm_profdata())
+        .arg("merge")
+        .arg("-o")
+        .arg(&merged)
+        .arg(&profiles)
+        .status();
+    if !status.is_ok_and(|status| status.success()) {
+        return Err(Error::Unsupported(
+            "unable to merge the profiles with llvm-profdata, which has to match the LLVM of \
+             rustc. Install it with `rustup component add llvm-tools`, or point LLVM_PROFDATA at \
+             it"
+            .to_owned(),
+        ));
+    }
+
+    let variants = [
+        variants(&[], &[]).remove(0),
+        pgo_variant("pgo", format!("-Cprofile-use={}", merged.display())),
+    ];
+    run(executable, &variants, filters)
+}
+
+/// `LLVM_PROFDATA`, the one of rustup's `llvm-tools` component, or whichever is on the `PATH`.
+fn llvm_profdata() -> PathBuf {
+    if let Some(path) = std::env::var_os("LLVM_PROFDATA") {
+        return path.into();
+    }
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let print = |what: &str| {
+        let output = Command::new(&rustc).args(["--print", what]).output().ok()?;
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+    };
+    let host = Command::new(&rustc)
+        .arg("-vV")
+        .output()
+        .ok()
+        .and_then(|output| {
+            let info = String::from_utf8(output.stdout).ok()?;
+            Some(
+                info.lines()
+                    .find_map(|l| l.strip_prefix("host: "))?
+                    .to_owned(),
+            )
+        });
+    match (print("sysroot"), host) {
+        (Some(sysroot), Some(host)) => {
+            let path = Path::new(&sysroot)
+                .join("lib/rustlib")
+                .join(host)
+                .join("bin/llvm-profdata");
+            if path.exists() {
+                return path;
+            }
+            "llvm-profdata".into()
+        }
+        _ => "llvm-profdata".into(),
+    }
+}
+
+pub(crate) fn target_of(executable: &str) -> Result<&str, Error> {
+    bench_target(executable).ok_or_else(|| {
+        Error::Argument(format!(
+            "unable to tell the bench target from {}",
+            executable
+        ))
+    })
+}
+
+/// Builds and runs `target` as `variant`, and returns the instruction count of every benchmark.
+fn measure(
+    target: &str,
+    variant: &Variant,
+    filters: &[String],
+) -> Result<Vec<(String, u64)>, Error> {
+    println!("Measuring {} with {}", target, variant.label);
+    let mut cmd = match &variant.toolchain {
+        // Through rustup's proxy, rather than the cargo of the toolchain running this
+        Some(toolchain) => {
+            let mut cmd = Command::new("cargo");
+            cmd.arg(format!("+{}", toolchain));
+            cmd
+        }
+        None => Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into())),
+    };
+    if let Some(flags) = &variant.rustflags {
+        let existing = std::env::var("RUSTFLAGS").unwrap_or_default();
+        cmd.env("RUSTFLAGS", format!("{} {}", existing, flags).trim());
+    }
+    if let Some(dir) = &variant.target_dir {
+        cmd.env("CARGO_TARGET_DIR", dir);
+    }
+    let output = cmd
+        .args(["bench", "--bench", target])
+        .args(&variant.args)
+        .arg("--")
+        .arg("--json")
+        .args(filters)
+        .env(VARIANT_VAR, &variant.dir)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| Error::Spawn {
+            tool: "cargo",
+            error,
+        })?;
+    if !output.status.success() {
+        return Err(Error::Failed(format!(
+            "measuring with {} failed ({})",
+            variant.label, output.status
+        )));
+    }
+    let mut column = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let name = event["benchmark"].as_str();
+        let instructions = event["stats"]["instruction_reads"].as_u64();
+        if let (true, Some(name), Some(instructions)) =
+            (event["event"] == "ran", name, instructions)
+        {
+            column.push((name.to_owned(), instructions));
+        }
+    }
+    Ok(column)
+}
+
+/// Prints the instruction counts, with the change relative to the first variant.
+fn print(labels: &[&str], names: &[String], columns: &[Vec<(String, u64)>]) {
+    let get = |column: &[(String, u64)], name: &str| {
+        column
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|&(_, instructions)| instructions)
+    };
+    let mut rows: Vec<Vec<String>> = vec![std::iter::once("Instructions".to_owned())
+        .chain(labels.iter().map(|label| label.to_string()))
+        .collect()];
+    for name in names {
+        let base = get(&columns[0], name);
+        let mut row = vec![name.clone()];
+        for (i, column) in columns.iter().enumerate() {
+            row.push(match (get(column, name), base) {
+                (Some(value), Some(base)) if i > 0 && base > 0 => {
+                    let pct = (value as f64 - base as f64) / base as f64 * 100.0;
+                    format!("{} ({:+.2}%)", value, pct)
+                }
+                (Some(value), _) => value.to_string(),
+                (None, _) => "-".to_owned(),
+            });
+        }
+        rows.push(row);
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    println!();
+    for row in &rows {
+        let mut line = format!("{:<width$}", row[0], width = widths[0]);
+        for (cell, &width) in row.iter().zip(&widths).skip(1) {
+            line.push_str(&format!("  {:>width$}", cell, width = width));
+        }
+        println!("{}", line.trim_end());
+    }
+}
@@ -0,0 +1,253 @@
+//! Hardware counters instead of cachegrind (`IAI_BACKEND=perf`): every benchmark runs natively
+//! under `perf stat`, which counts the instructions, cycles, branch misses and cache misses of the
+//! CPU itself. That is many times faster than simulating them in valgrind, at the price of some
+//! noise from interrupts and the rest of the machine, and of counts that only compare between runs
+//! on the same kind of CPU.
+//!
+//! The counts are kept in `target/iai/perf.out.<name>` and compared with the previous run's like
+//! those of any other backend, and the instructions are reported and checked for regressions in
+//! place of cachegrind's. They aren't added to the history, whose counts are cachegrind's. This
+//! needs Linux and a `perf` allowed to count user-space events, e.g. with
+//! `kernel.perf_event_paranoid` at 2 or below.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    backend::{self, Backend, Measurement, Metric},
+    Benchmark, CachegrindStats, Config, Error, RunMetadata, Target, BENCHMARK_PROCESS_VAR,
+    HARNESS_VERSION,
+};
+
+const EVENTS: &str = "instructions,cycles,branch-misses,cache-misses";
+
+/// The hardware counters of one benchmark.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PerfStats {
+    pub(crate) instructions: u64,
+    pub(crate) cycles: u64,
+    pub(crate) branch_misses: u64,
+    pub(crate) cache_misses: u64,
+}
+
+impl Backend for PerfStats {
+    const KEY: &'static str = "perf";
+
+    fn tool(_: &Config) -> &'static str {
+        "perf"
+    }
+
+    fn args(_: &Config) -> Vec<String> {
+        vec![
+            "stat".to_owned(),
+            "-x,".to_owned(),
+            "-e".to_owned(),
+            EVENTS.to_owned(),
+        ]
+    }
+
+    /// Runs the benchmark under `perf stat` instead of valgrind, ignoring `cmd`.
+    fn execute(
+        _: Command,
+        executable: &str,
+        target: Target,
+        config: &Config,
+        child_args: &[&str],
+        output_file: &Path,
+    ) -> Result<RunMetadata, Error> {
+        let mut cmd = Command::new("perf");
+        cmd.args(Self::args(config))
+            .arg("-o")
+            .arg(output_file)
+            .arg("--")
+            .arg(executable)
+            .env(BENCHMARK_PROCESS_VAR, "1")
+            .args(target.child_args())
+            .arg("--iai-harness")
+            .arg(HARNESS_VERSION)
+            .args(child_args)
+            .stdout(Stdio::null());
+        let output = backend::run_child("perf", cmd)?;
+        Ok(RunMetadata::parse(&output.stderr))
+    }
+
+    /// Reads the CSV of `perf stat -x,`: the count, its unit and the event on every line.
+    fn parse(file: &Path) -> Result<PerfStats, Error> {
+        let contents = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+        let mut stats = PerfStats::default();
+        for line in contents.lines() {
+            let mut fields = line.split(',');
+            let (Some(count), Some(_unit), Some(event)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            // `<not counted>` and `<not supported>` count as zero
+            let count = count.parse().unwrap_or(0);
+            // Events can carry modifiers (`instructions:u`) or a PMU (`cpu_core/cycles/`), and
+            // hybrid CPUs report each kind of core on a line of its own
+            let counter = match event
+                .split(['/', ':'])
+                .find(|part| EVENTS.split(',').any(|e| e == *part))
+            {
+                Some("instructions") => &mut stats.instructions,
+                Some("cycles") => &mut stats.cycles,
+                Some("branch-misses") => &mut stats.branch_misses,
+                Some("cache-misses") => &mut stats.cache_misses,
+                _ => continue,
+            };
+            *counter += count;
+        }
+        Ok(stats)
+    }
+
+    fn subtract(&self, calibration: &PerfStats) -> PerfStats {
+        PerfStats {
+            instructions: self.instructions.saturating_sub(calibration.instructions),
+            cycles: self.cycles.saturating_sub(calibration.cycles),
+            branch_misses: self.branch_misses.saturating_sub(calibration.branch_misses),
+            cache_misses: self.cache_misses.saturating_sub(calibration.cache_misses),
+        }
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        vec![
+            Metric::new("instructions", Some("Instructions"), self.instructions),
+            Metric::new("cycles", Some("Cycles"), self.cycles),
+            Metric::new("branch_misses", Some("Branch Misses"), self.branch_misses),
+            Metric::new("cache_misses", Some("Cache Misses"), self.cache_misses),
+        ]
+    }
+}
+
+/// Whether `perf` can be run at all.
+pub(crate) fn check_perf() -> Result<(), Error> {
+    let found = Command::new("perf")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if found {
+        Ok(())
+    } else {
+        Err(Error::Unsupported(
+            "IAI_BACKEND=perf needs perf, which isn't installed or can't be run".to_owned(),
+        ))
+    }
+}
+
+/// Measures a benchmark with the hardware counters. The instructions stand in for cachegrind's, so
+/// that they are compared, checked for regressions and reported like those, and the other
+/// counters come along as a measurement of their own.
+pub(crate) fn measure(
+    executable: &str,
+    target: Target,
+    bench: &Benchmark,
+    config: &Config,
+    calibration: &(PerfStats, Option<PerfStats>),
+) -> Result<(CachegrindStats, Option<CachegrindStats>, Measurement), Error> {
+    let mut measurement = backend::measure(executable, target, bench, config, calibration)?;
+    let stats = take_instructions(&mut measurement.metrics);
+    let old = measurement.old.as_mut().map(take_instructions);
+    Ok((stats, old, measurement))
+}
+
+/// Moves the instructions out of `metrics`, as cachegrind's `Ir`.
+fn take_instructions(metrics: &mut Vec<Metric>) -> CachegrindStats {
+    let instructions = metrics
+        .iter()
+        .position(|metric| metric.key == "instructions")
+        .map_or(0, |i| metrics.remove(i).value);
+    CachegrindStats::from_events(HashMap::from([("Ir".to_owned(), instructions)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, contents: &str) -> PerfStats {
+        let path = std::env::temp_dir().join(format!("iai-perf-{}.csv", name));
+        std::fs::write(&path, contents).unwrap();
+        let stats = PerfStats::parse(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        stats
+    }
+
+    #[test]
+    fn reads_the_counters_of_perf_stat() {
+        let stats = parse(
+            "plain",
+            "# started on Mon Jan  1 00:00:00 2024\n\
+             \n\
+             1200,,instructions:u,100.00,,\n\
+             900,,cycles:u,100.00,,\n\
+             12,,branch-misses:u,100.00,,\n\
+             <not counted>,,cache-misses:u,0,100.00,,\n",
+        );
+        assert_eq!(
+            stats,
+            PerfStats {
+                instructions: 1200,
+                cycles: 900,
+                branch_misses: 12,
+                cache_misses: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn sums_the_kinds_of_cores_of_hybrid_cpus() {
+        let stats = parse(
+            "hybrid",
+            "1000,,cpu_core/instructions/u,60.00,,\n\
+             200,,cpu_atom/instructions/u,40.00,,\n\
+             5,,cpu_core/cache-misses/,100.00,,\n\
+             7,,cpu_core/cache-references/,100.00,,\n",
+        );
+        assert_eq!((stats.instructions, stats.cache_misses), (1200, 5));
+        assert_eq!(stats.cycles, 0);
+    }
+
+    #[test]
+    fn subtracts_without_going_below_zero() {
+        let stats = PerfStats {
+            instructions: 100,
+            cycles: 80,
+            branch_misses: 1,
+            cache_misses: 0,
+        };
+        let calibration = PerfStats {
+            instructions: 40,
+            cycles: 90,
+            branch_misses: 1,
+            cache_misses: 3,
+        };
+        assert_eq!(
+            stats.subtract(&calibration),
+            PerfStats {
+                instructions: 60,
+                cycles: 0,
+                branch_misses: 0,
+                cache_misses: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn moves_the_instructions_out_of_the_counters() {
+        let stats = PerfStats {
+            instructions: 100,
+            cycles: 80,
+            branch_misses: 1,
+            cache_misses: 2,
+        };
+        let mut metrics = stats.metrics();
+        assert_eq!(take_instructions(&mut metrics).instruction_reads(), 100);
+        let keys: Vec<&str> = metrics.iter().map(|metric| metric.key).collect();
+        assert_eq!(keys, ["cycles", "branch_misses", "cache_misses"]);
+    }
+}
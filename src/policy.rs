@@ -0,0 +1,583 @@
+//! Decides whether a change in a benchmark's measurements counts as a regression.
+//!
+//! The suite-wide threshold comes from `--threshold`/`IAI_THRESHOLD`; individual benchmarks can
+//! widen (or narrow) it with `#[iai(tolerance = "...")]`. It applies to the instruction count;
+//! other metrics can be gated with `--fail-on-regression cycles=5%`. Regressions that were made on
+//! purpose can be listed in an accepted-regressions file (`--accepted-regressions`), one per line:
+//!
+//! ```text
+//! # name           options                            note
+//! parse_large_json until=2026-12-31 max=5% commit=1a2b3c switched to a validating parser
+//! ```
+//!
+//! `until` is the last day the entry applies, `max` caps how large the accepted regression may
+//! be and `commit` points at the change that introduced it. Everything after the options is a
+//! free-form note that is shown next to the benchmark.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Benchmark, CachegrindStats, Error};
+
+/// How much a metric may grow before it is considered a regression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Tolerance {
+    /// Relative growth, in percent of the old value.
+    Percent(f64),
+    /// Growth in absolute counts.
+    Absolute(u64),
+}
+
+impl Tolerance {
+    /// Parses `"2%"`, `"0.5 %"` or `"1500"`.
+    pub(crate) fn parse(s: &str) -> Option<Tolerance> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(pct) => pct
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|p| p.is_finite() && *p >= 0.0)
+                .map(Tolerance::Percent),
+            None => s.replace('_', "").parse().ok().map(Tolerance::Absolute),
+        }
+    }
+
+    /// Whether `new` differs from `old` by no more than this tolerance, in either direction.
+    pub(crate) fn within(&self, new: u64, old: u64) -> bool {
+        self.allows(new, old) && self.allows(old, new)
+    }
+
+    /// Whether going from `old` to `new` stays within this tolerance.
+    pub(crate) fn allows(&self, new: u64, old: u64) -> bool {
+        if new <= old {
+            return true;
+        }
+        match *self {
+            Tolerance::Percent(pct) => old != 0 && (new - old) as f64 / old as f64 * 100.0 <= pct,
+            Tolerance::Absolute(count) => new - old <= count,
+        }
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tolerance::Percent(pct) => write!(f, "{}%", pct),
+            Tolerance::Absolute(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+/// A metric that can fail the run when it grows, with `--fail-on-regression <metric>=<tolerance>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Metric {
+    Instructions,
+    L1Accesses,
+    L2Accesses,
+    RamAccesses,
+    Cycles,
+    /// Mispredicted conditional and indirect branches, with `--branch-sim`.
+    Mispredictions,
+}
+
+impl Metric {
+    const ALL: [Metric; 6] = [
+        Metric::Instructions,
+        Metric::L1Accesses,
+        Metric::L2Accesses,
+        Metric::RamAccesses,
+        Metric::Cycles,
+        Metric::Mispredictions,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Metric::Instructions => "instructions",
+            Metric::L1Accesses => "l1_accesses",
+            Metric::L2Accesses => "l2_accesses",
+            Metric::RamAccesses => "ram_accesses",
+            Metric::Cycles => "cycles",
+            Metric::Mispredictions => "mispredictions",
+        }
+    }
+
+    pub(crate) fn value(self, stats: &CachegrindStats, cycle_weights: [u64; 3]) -> u64 {
+        let summary = stats.summarize(cycle_weights);
+        match self {
+            Metric::Instructions => stats.instruction_reads(),
+            Metric::L1Accesses => summary.l1_hits,
+            Metric::L2Accesses => summary.l3_hits,
+            Metric::RamAccesses => summary.ram_hits,
+            Metric::Cycles => summary.cycles(),
+            Metric::Mispredictions => stats.branches().map_or(0, |branches| {
+                branches.conditional_mispredicted + branches.indirect_mispredicted
+            }),
+        }
+    }
+
+    /// Parses `<metric>=<tolerance>`, e.g. `cycles=5%`.
+    pub(crate) fn parse_gate(gate: &str) -> Result<(Metric, Tolerance), String> {
+        let (name, tolerance) = gate
+            .split_once('=')
+            .ok_or_else(|| format!("expected <metric>=<tolerance>, got {:?}", gate))?;
+        let metric = Metric::ALL
+            .iter()
+            .copied()
+            .find(|metric| metric.name() == name.trim())
+            .ok_or_else(|| {
+                let known: Vec<&str> = Metric::ALL.iter().map(|metric| metric.name()).collect();
+                format!(
+                    "unknown metric {:?} (expected one of {})",
+                    name,
+                    known.join(", ")
+                )
+            })?;
+        let tolerance = Tolerance::parse(tolerance).ok_or_else(|| {
+            format!(
+                "invalid tolerance {:?} (expected e.g. \"2%\" or \"1500\")",
+                tolerance
+            )
+        })?;
+        Ok((metric, tolerance))
+    }
+}
+
+/// A time budget for a benchmark on a given clock, like `50us @ 48MHz`, from
+/// `#[iai(budget = "...")]`. The estimated cycles of the benchmark must stay within the cycles the
+/// clock ticks in that time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Budget {
+    pub(crate) cycles: u64,
+}
+
+impl Budget {
+    pub(crate) fn parse(s: &str) -> Option<Budget> {
+        let (time, clock) = s.split_once('@')?;
+        let seconds = number_with_unit(
+            time,
+            &[
+                ("ns", 1e-9),
+                ("us", 1e-6),
+                ("μs", 1e-6),
+                ("µs", 1e-6),
+                ("ms", 1e-3),
+                ("s", 1.0),
+            ],
+        )?;
+        let hertz = number_with_unit(clock, HERTZ)?;
+        Some(Budget {
+            cycles: (seconds * hertz) as u64,
+        })
+    }
+
+    /// The budget of `bench`, if it has one. Invalid ones are caught by [`check_attributes`].
+    pub(crate) fn of(bench: &Benchmark) -> Option<Budget> {
+        bench.budget.and_then(Budget::parse)
+    }
+}
+
+/// Checks the `tolerance` and `budget` attributes of `bench`, which are only parsed once they are
+/// needed.
+pub(crate) fn check_attributes(bench: &Benchmark) -> Result<(), Error> {
+    let invalid = |problem| {
+        Err(Error::settings(
+            format!("benchmark {}", bench.name),
+            problem,
+        ))
+    };
+    match (bench.tolerance, bench.budget) {
+        (Some(tolerance), _) if Tolerance::parse(tolerance).is_none() => invalid(format!(
+            "tolerance {:?} isn't a tolerance like \"2%\" or \"1500\"",
+            tolerance
+        )),
+        (_, Some(budget)) if Budget::parse(budget).is_none() => invalid(format!(
+            "budget {:?} isn't a budget like \"50us @ 48MHz\"",
+            budget
+        )),
+        _ => Ok(()),
+    }
+}
+
+const HERTZ: &[(&str, f64)] = &[("khz", 1e3), ("mhz", 1e6), ("ghz", 1e9), ("hz", 1.0)];
+
+/// Parses a clock rate like `3.5GHz`, or a plain number of hertz.
+pub(crate) fn parse_clock(s: &str) -> Option<u64> {
+    let hertz = match s.trim().parse::<u64>() {
+        Ok(hertz) => hertz,
+        Err(_) => number_with_unit(s, HERTZ)? as u64,
+    };
+    (hertz > 0).then_some(hertz)
+}
+
+/// Parses a non-negative number followed by one of `units` (case-insensitive), scaled by the
+/// unit's factor. Longer units must come before the units they end with.
+fn number_with_unit(s: &str, units: &[(&str, f64)]) -> Option<f64> {
+    let s = s.trim().to_lowercase();
+    units.iter().find_map(|&(unit, factor)| {
+        let number: f64 = s.strip_suffix(unit)?.trim().parse().ok()?;
+        (number.is_finite() && number >= 0.0).then_some(number * factor)
+    })
+}
+
+/// A benchmark with a metric that grew beyond its tolerance.
+#[derive(Clone, Debug)]
+pub(crate) struct Regression {
+    pub(crate) metric: Metric,
+    pub(crate) tolerance: Tolerance,
+    /// Set when the regression is listed in the accepted-regressions file.
+    pub(crate) accepted: Option<String>,
+}
+
+#[derive(Clone)]
+struct AcceptedRegression {
+    max: Option<Tolerance>,
+    commit: Option<String>,
+    note: String,
+}
+
+impl AcceptedRegression {
+    fn describe(&self) -> String {
+        match &self.commit {
+            Some(commit) if self.note.is_empty() => commit.clone(),
+            Some(commit) => format!("{}: {}", commit, self.note),
+            None => self.note.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Policy {
+    threshold: Option<Tolerance>,
+    /// Thresholds for metrics other than the instruction count.
+    gates: Vec<(Metric, Tolerance)>,
+    /// Benchmarks below this many instructions are mostly calibration noise.
+    min_instructions: Option<u64>,
+    accepted: HashMap<String, AcceptedRegression>,
+}
+
+impl Policy {
+    /// `gates` are the `--fail-on-regression` thresholds; one for the instruction count replaces
+    /// `threshold`.
+    pub(crate) fn new(
+        threshold: Option<Tolerance>,
+        gates: Vec<(Metric, Tolerance)>,
+        min_instructions: Option<u64>,
+    ) -> Policy {
+        let instructions = gates
+            .iter()
+            .rev()
+            .find(|(metric, _)| *metric == Metric::Instructions)
+            .map(|&(_, tolerance)| tolerance);
+        Policy {
+            threshold: instructions.or(threshold),
+            gates: gates
+                .into_iter()
+                .filter(|(metric, _)| *metric != Metric::Instructions)
+                .collect(),
+            min_instructions,
+            accepted: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn min_instructions(&self) -> Option<u64> {
+        self.min_instructions
+    }
+
+    /// Whether a benchmark with this many instructions is too small to report in detail.
+    pub(crate) fn is_trivial(&self, instructions: u64) -> bool {
+        self.min_instructions.is_some_and(|min| instructions < min)
+    }
+
+    /// Loads the accepted-regressions file at `path`. Expired entries are skipped.
+    pub(crate) fn load_accepted(&mut self, path: &Path) -> Result<(), Error> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let invalid = |problem| Error::settings(path.display(), problem);
+        let today = days_since_epoch();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace().peekable();
+            let name = words.next().unwrap();
+            let mut entry = AcceptedRegression {
+                max: None,
+                commit: None,
+                note: String::new(),
+            };
+            let mut expired = None;
+            while let Some((key, value)) = words.peek().and_then(|w| w.split_once('=')) {
+                match key {
+                    "until" => match parse_date(value) {
+                        Some(until) if until < today => expired = Some(value),
+                        Some(_) => {}
+                        None => return Err(invalid(format!("invalid date {:?}", value))),
+                    },
+                    "max" => {
+                        entry.max = Some(
+                            Tolerance::parse(value)
+                                .ok_or_else(|| invalid(format!("invalid tolerance {:?}", value)))?,
+                        )
+                    }
+                    "commit" => entry.commit = Some(value.to_owned()),
+                    // Not an option, so the note starts here
+                    _ => break,
+                }
+                words.next();
+            }
+            entry.note = words.collect::<Vec<_>>().join(" ");
+            match expired {
+                Some(until) => eprintln!(
+                    "Accepted regression for {} expired on {}, ignoring it.",
+                    name, until
+                ),
+                None => {
+                    self.accepted.insert(name.to_owned(), entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The tolerance that applies to `bench`, if it is gated at all. Invalid ones are caught by
+    /// [`check_attributes`].
+    pub(crate) fn tolerance_for(&self, bench: &Benchmark) -> Option<Tolerance> {
+        bench
+            .tolerance
+            .and_then(Tolerance::parse)
+            .or(self.threshold)
+    }
+
+    /// Whether a change from `old` to `new` is just noise for `bench`. Without any tolerance,
+    /// every change counts.
+    pub(crate) fn is_noise(&self, bench: &Benchmark, new: u64, old: u64) -> bool {
+        match self.tolerance_for(bench) {
+            Some(tolerance) => tolerance.within(new, old),
+            None => new == old,
+        }
+    }
+
+    /// Checks whether the instruction count of `bench` regressed beyond its tolerance.
+    pub(crate) fn check(&self, bench: &Benchmark, new: u64, old: u64) -> Option<Regression> {
+        let tolerance = self.tolerance_for(bench)?;
+        self.check_metric(bench, Metric::Instructions, tolerance, new, old)
+    }
+
+    /// Checks the metrics gated with `--fail-on-regression`, besides the instruction count.
+    pub(crate) fn check_gates(
+        &self,
+        bench: &Benchmark,
+        new: &CachegrindStats,
+        old: &CachegrindStats,
+        cycle_weights: [u64; 3],
+    ) -> Option<Regression> {
+        // Cache behaviour of a trivial benchmark is dominated by the calibration noise
+        if self.is_trivial(new.instruction_reads()) && self.is_trivial(old.instruction_reads()) {
+            return None;
+        }
+        self.gates.iter().find_map(|&(metric, tolerance)| {
+            self.check_metric(
+                bench,
+                metric,
+                tolerance,
+                metric.value(new, cycle_weights),
+                metric.value(old, cycle_weights),
+            )
+        })
+    }
+
+    fn check_metric(
+        &self,
+        bench: &Benchmark,
+        metric: Metric,
+        tolerance: Tolerance,
+        new: u64,
+        old: u64,
+    ) -> Option<Regression> {
+        if tolerance.allows(new, old) {
+            return None;
+        }
+        // A few instructions more on a trivial benchmark is a huge percentage, but still noise
+        if metric == Metric::Instructions
+            && matches!(tolerance, Tolerance::Percent(_))
+            && self.is_trivial(new)
+            && self.is_trivial(old)
+        {
+            return None;
+        }
+        let accepted = self
+            .accepted
+            .get(bench.name)
+            .filter(|entry| entry.max.is_none_or(|max| max.allows(new, old)))
+            .map(AcceptedRegression::describe);
+        Some(Regression {
+            metric,
+            tolerance,
+            accepted,
+        })
+    }
+}
+
+fn days_since_epoch() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| (d.as_secs() / 86400) as i64)
+}
+
+/// Parses a `YYYY-MM-DD` date into days since the Unix epoch.
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Howard Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bench() {}
+
+    #[test]
+    fn parses_tolerances() {
+        assert_eq!(Tolerance::parse("2%"), Some(Tolerance::Percent(2.0)));
+        assert_eq!(Tolerance::parse(" 0.5 % "), Some(Tolerance::Percent(0.5)));
+        assert_eq!(Tolerance::parse("1_500"), Some(Tolerance::Absolute(1500)));
+        for invalid in ["", "%", "-1%", "inf%", "2 percent", "-5"] {
+            assert_eq!(Tolerance::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn tolerances_allow_growth_up_to_them() {
+        let percent = Tolerance::Percent(2.0);
+        assert!(percent.allows(102, 100));
+        assert!(!percent.allows(103, 100));
+        assert!(percent.allows(50, 100));
+        assert!(!percent.within(50, 100));
+        // Nothing is within a percentage of nothing
+        assert!(!percent.allows(1, 0));
+        let absolute = Tolerance::Absolute(10);
+        assert!(absolute.allows(110, 100));
+        assert!(!absolute.allows(111, 100));
+        assert!(absolute.within(90, 100));
+    }
+
+    #[test]
+    fn parses_budgets() {
+        let cycles = |s| Budget::parse(s).map(|budget| budget.cycles);
+        assert_eq!(cycles("50us @ 48MHz"), Some(2400));
+        assert_eq!(cycles("1ms@1GHz"), Some(1_000_000));
+        assert_eq!(cycles("2 s @ 10 hz"), Some(20));
+        assert_eq!(cycles("100ns @ 3.5GHz"), Some(350));
+        assert_eq!(cycles("10µs @ 1MHz"), Some(10));
+        for invalid in ["50us", "@ 48MHz", "50 @ 48MHz", "50us @ 48", "-1us @ 1MHz"] {
+            assert_eq!(cycles(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_clock_rates() {
+        assert_eq!(parse_clock("3.5GHz"), Some(3_500_000_000));
+        assert_eq!(parse_clock(" 48 mhz "), Some(48_000_000));
+        assert_eq!(parse_clock("1.5kHz"), Some(1500));
+        assert_eq!(parse_clock("2400000000"), Some(2_400_000_000));
+        for invalid in ["", "0", "0GHz", "-1GHz", "fast", "3.5 GHz per core"] {
+            assert_eq!(parse_clock(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn checks_the_attributes_of_benchmarks() {
+        let mut benchmark = Benchmark::new("parse", bench);
+        assert!(check_attributes(&benchmark).is_ok());
+        benchmark.tolerance = Some("2%");
+        benchmark.budget = Some("1ms @ 1GHz");
+        assert!(check_attributes(&benchmark).is_ok());
+        benchmark.budget = Some("1ms");
+        assert_eq!(
+            check_attributes(&benchmark).unwrap_err().to_string(),
+            "invalid settings in benchmark parse: budget \"1ms\" isn't a budget like \"50us @ \
+             48MHz\""
+        );
+        benchmark.tolerance = Some("two");
+        assert_eq!(
+            check_attributes(&benchmark).unwrap_err().to_string(),
+            "invalid settings in benchmark parse: tolerance \"two\" isn't a tolerance like \"2%\" \
+             or \"1500\""
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(11017));
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+        assert_eq!(parse_date("1969-12-31"), Some(-1));
+        for invalid in [
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-32",
+            "2024-01",
+            "tomorrow",
+        ] {
+            assert_eq!(parse_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    fn load(name: &str, contents: &str) -> Result<Policy, Error> {
+        let path = std::env::temp_dir().join(format!("iai-accepted-{}.txt", name));
+        fs::write(&path, contents).unwrap();
+        let mut policy = Policy::new(Some(Tolerance::Percent(1.0)), Vec::new(), None);
+        let loaded = policy.load_accepted(&path);
+        fs::remove_file(&path).unwrap();
+        loaded.map(|()| policy)
+    }
+
+    #[test]
+    fn accepts_listed_regressions() {
+        let policy = load(
+            "valid",
+            "# accepted while the parser is rewritten\n\
+             parse max=10% commit=abc123 slower lexer\n\
+             print until=1999-01-01 expired long ago\n",
+        )
+        .unwrap();
+        let parse = Benchmark::new("parse", bench);
+        let accepted = policy.check(&parse, 105, 100).unwrap().accepted;
+        assert_eq!(accepted.as_deref(), Some("abc123: slower lexer"));
+        // Beyond its own maximum, the regression is no longer accepted
+        assert_eq!(policy.check(&parse, 120, 100).unwrap().accepted, None);
+        let print = Benchmark::new("print", bench);
+        assert_eq!(policy.check(&print, 105, 100).unwrap().accepted, None);
+
+        assert_eq!(
+            load("bad-date", "parse until=soon\n")
+                .err()
+                .unwrap()
+                .to_string(),
+            format!(
+                "invalid settings in {}: invalid date \"soon\"",
+                std::env::temp_dir()
+                    .join("iai-accepted-bad-date.txt")
+                    .display()
+            )
+        );
+        assert!(load("bad-max", "parse max=lots\n").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Project-level settings, from `iai.toml` next to `Cargo.toml` or else from the
+//! `[package.metadata.iai]` table of `Cargo.toml` itself:
+//!
+//! ```toml
+//! cache = "zen3"              # or a table of I1, D1 and LL, as cachegrind's flags
+//! cycle-weights = { l1 = 1, ll = 5, ram = 35 }  # or IAI_CYCLE_WEIGHTS=1,5,35
+//! output-dir = "target/iai"
+//! threshold = "2%"
+//! threshold-profiles = { ci = "1%", local = "10%" }
+//! min-instructions = 1000
+//! tool = "callgrind"
+//! cachegrind-flags = ["--branch-sim=yes"]
+//! allow-aslr = true
+//! branch-sim = true           # also count mispredicted branches
+//! per-bench-calibration = true  # calibrate every benchmark against a run of its own
+//! client-requests = true     # only count the call of every benchmark (valgrind 3.22+)
+//! top-functions = 5           # report the most expensive functions of every benchmark
+//! clock = "3.5GHz"          # for estimating times, instead of /proc/cpuinfo
+//! post-process = ["./cost-model", "--json"]  # run with the output of every benchmark
+//!
+//! [template]                  # the layout of the report, see `template.rs`
+//! labels = { instructions = "Instr" }
+//! ```
+//!
+//! They take precedence over `main!(config = ...; ...)`, and environment variables and
+//! command-line arguments take precedence over them; `CACHEGRIND_FLAGS` is added to
+//! `cachegrind-flags`.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{cache_profile, cache_profile_names, PostProcessor, Tool, CACHE_LEVELS},
+    policy::{parse_clock, Tolerance},
+    template::{Template, DEFAULT_LINE},
+    Config, Error,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Settings {
+    cache: Option<Cache>,
+    cycle_weights: Option<CycleWeights>,
+    output_dir: Option<PathBuf>,
+    threshold: Option<String>,
+    #[serde(default)]
+    threshold_profiles: BTreeMap<String, String>,
+    min_instructions: Option<u64>,
+    tool: Option<String>,
+    #[serde(default)]
+    cachegrind_flags: Vec<String>,
+    allow_aslr: Option<bool>,
+    branch_sim: Option<bool>,
+    per_bench_calibration: Option<bool>,
+    client_requests: Option<bool>,
+    top_functions: Option<usize>,
+    clock: Option<String>,
+    #[serde(default)]
+    post_process: Vec<String>,
+    template: Option<TemplateSettings>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TemplateSettings {
+    line: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    thousands_separator: String,
+    decimal_separator: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Cache {
+    Profile(String),
+    #[serde(rename_all = "UPPERCASE")]
+    Sizes {
+        i1: String,
+        d1: String,
+        ll: String,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CycleWeights {
+    l1: u64,
+    ll: u64,
+    ram: u64,
+}
+
+/// Applies the settings of the project being benchmarked to `config`, if it has any.
+pub(crate) fn load(config: &mut Config) -> Result<(), Error> {
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    load_from(&dir, config)
+}
+
+fn load_from(dir: &Path, config: &mut Config) -> Result<(), Error> {
+    let file = dir.join("iai.toml");
+    let mut source = file.display().to_string();
+    let settings = match std::fs::read_to_string(&file) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| invalid(&source, &e.to_string()))?,
+        Err(_) => {
+            let manifest = dir.join("Cargo.toml");
+            let Ok(contents) = std::fs::read_to_string(&manifest) else {
+                return Ok(());
+            };
+            let Ok(manifest) = toml::from_str::<toml::Table>(&contents) else {
+                return Ok(());
+            };
+            let Some(table) = manifest
+                .get("package")
+                .and_then(|package| package.get("metadata"))
+                .and_then(|metadata| metadata.get("iai"))
+            else {
+                return Ok(());
+            };
+            source = "[package.metadata.iai]".to_owned();
+            table
+                .clone()
+                .try_into()
+                .map_err(|e: toml::de::Error| invalid(&source, &e.to_string()))?
+        }
+    };
+    apply(settings, &source, config)
+}
+
+fn apply(settings: Settings, source: &str, config: &mut Config) -> Result<(), Error> {
+    let tolerance = |key: &str, value: &str| {
+        Tolerance::parse(value).ok_or_else(|| {
+            invalid(
+                source,
+                &format!(
+                    "{}: {:?} isn't a tolerance like \"2%\" or \"1500\"",
+                    key, value
+                ),
+            )
+        })
+    };
+    match settings.cache {
+        Some(Cache::Profile(name)) => {
+            config.cache = cache_profile(&name).ok_or_else(|| {
+                invalid(
+                    source,
+                    &format!(
+                        "cache: unknown profile {:?} (expected one of {})",
+                        name,
+                        cache_profile_names()
+                    ),
+                )
+            })?
+        }
+        Some(Cache::Sizes { i1, d1, ll }) => {
+            for (level, geometry) in CACHE_LEVELS.iter().zip([i1, d1, ll]) {
+                config.cache = config.cache.with_level(level, &geometry).ok_or_else(|| {
+                    invalid(
+                        source,
+                        &format!(
+                            "cache: {} should be <size>,<associativity>,<line size>",
+                            level
+                        ),
+                    )
+                })?;
+            }
+        }
+        None => {}
+    }
+    if let Some(CycleWeights { l1, ll, ram }) = settings.cycle_weights {
+        config.cycle_weights = [l1, ll, ram];
+    }
+    if let Some(dir) = settings.output_dir {
+        config.output_dir = Some(dir);
+    }
+    if let Some(threshold) = settings.threshold {
+        config.threshold = Some(tolerance("threshold", &threshold)?);
+    }
+    for (name, threshold) in settings.threshold_profiles {
+        let threshold = tolerance("threshold-profiles", &threshold)?;
+        config
+            .threshold_profiles
+            .retain(|(existing, _)| *existing != name);
+        config.threshold_profiles.push((name, threshold));
+    }
+    if let Some(instructions) = settings.min_instructions {
+        config.min_instructions = Some(instructions);
+    }
+    if let Some(tool) = settings.tool {
+        config.tool = Tool::parse(&tool).ok_or_else(|| {
+            invalid(
+                source,
+                &format!(
+                    "tool: unknown tool {:?} (expected cachegrind or callgrind)",
+                    tool
+                ),
+            )
+        })?;
+    }
+    config.cachegrind_flags.extend(settings.cachegrind_flags);
+    if let Some(allow) = settings.allow_aslr {
+        config.allow_aslr = allow;
+    }
+    if let Some(enabled) = settings.branch_sim {
+        config.branch_sim = enabled;
+    }
+    if let Some(enabled) = settings.per_bench_calibration {
+        config.per_bench_calibration = enabled;
+    }
+    if let Some(enabled) = settings.client_requests {
+        config.client_requests = enabled;
+    }
+    if let Some(count) = settings.top_functions {
+        config.top_functions = Some(count);
+    }
+    if !settings.post_process.is_empty() {
+        let command = PostProcessor::Command(settings.post_process);
+        config.post_processors.push(command);
+    }
+    if let Some(template) = settings.template {
+        let template = Template::new(
+            template.line.as_deref().unwrap_or(DEFAULT_LINE),
+            template.labels,
+            &template.thousands_separator,
+            template.decimal_separator.as_deref().unwrap_or("."),
+        );
+        config.template = Some(template.map_err(|e| invalid(source, &format!("template: {}", e)))?);
+    }
+    if let Some(clock) = settings.clock {
+        config.clock_hz = Some(parse_clock(&clock).ok_or_else(|| {
+            invalid(
+                source,
+                &format!("clock: {:?} isn't a clock rate like \"3.5GHz\"", clock),
+            )
+        })?);
+    }
+    Ok(())
+}
+
+fn invalid(source: &str, problem: &str) -> Error {
+    Error::settings(source, problem.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, file: &str, contents: &str) -> Result<Config, Error> {
+        let dir = std::env::temp_dir().join(format!("iai-project-{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(file), contents).unwrap();
+        let mut config = Config::default();
+        let loaded = load_from(&dir, &mut config);
+        std::fs::remove_dir_all(&dir).unwrap();
+        loaded.map(|()| config)
+    }
+
+    #[test]
+    fn applies_the_settings_of_iai_toml() {
+        let config = project(
+            "toml",
+            "iai.toml",
+            r#"
+            cycle-weights = { l1 = 1, ll = 10, ram = 100 }
+            threshold = "2%"
+            threshold-profiles = { ci = "1500" }
+            tool = "callgrind"
+            cachegrind-flags = ["--branch-sim=yes"]
+            clock = "3.5GHz"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.cycle_weights, [1, 10, 100]);
+        assert_eq!(config.threshold, Some(Tolerance::Percent(2.0)));
+        assert_eq!(
+            config.threshold_profiles,
+            [("ci".to_owned(), Tolerance::Absolute(1500))]
+        );
+        assert_eq!(config.tool, Tool::Callgrind);
+        assert_eq!(config.cachegrind_flags, ["--branch-sim=yes"]);
+        assert_eq!(config.clock_hz, Some(3_500_000_000));
+    }
+
+    #[test]
+    fn falls_back_to_the_package_metadata() {
+        let manifest =
+            "[package]\nname = \"parser\"\n\n[package.metadata.iai]\nmin-instructions = 1000\n";
+        let config = project("metadata", "Cargo.toml", manifest).unwrap();
+        assert_eq!(config.min_instructions, Some(1000));
+        let config = project("none", "Cargo.toml", "[package]\nname = \"parser\"\n").unwrap();
+        assert_eq!(config.min_instructions, None);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let error = |name, contents| project(name, "iai.toml", contents).unwrap_err().to_string();
+        assert!(error("threshold", "threshold = \"lots\"").contains("isn't a tolerance"));
+        assert!(error("tool", "tool = \"massif\"").contains("unknown tool \"massif\""));
+        assert!(error("unknown", "colour = true").contains("unknown field `colour`"));
+        let error = project(
+            "metadata-error",
+            "Cargo.toml",
+            "[package.metadata.iai]\nclock = \"fast\"\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("invalid settings in [package.metadata.iai]: clock:"));
+    }
+}
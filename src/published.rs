@@ -0,0 +1,172 @@
+//! Measuring the suite against a version of the crate published on crates.io
+//! (`--against-published <version>`, or `latest`), for seeing what a release would change.
+//!
+//! A shim package in `target/iai/published` depends on the published crate under the name of the
+//! local one, along with the dependencies of the local one, and builds the same bench target from
+//! the same source. The benchmark bodies are thus compiled against either version, and the table
+//! shows the local instruction counts relative to the published ones.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use toml::{Table, Value};
+
+use crate::{
+    matrix::{self, Variant},
+    Error,
+};
+
+pub(crate) fn run(executable: &str, version: &str, filters: &[String]) -> Result<(), Error> {
+    let root = match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(root) => PathBuf::from(root),
+        None => std::env::current_dir().map_err(|e| Error::io(Path::new("."), e))?,
+    };
+    let manifest_path = root.join("Cargo.toml");
+    let contents =
+        std::fs::read_to_string(&manifest_path).map_err(|e| Error::io(&manifest_path, e))?;
+    let manifest: Table = toml::from_str(&contents)
+        .map_err(|e| Error::settings(manifest_path.display(), e.to_string().trim()))?;
+    let Some(name) = manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(Value::as_str)
+    else {
+        return Err(Error::settings(
+            manifest_path.display(),
+            "the benchmarks don't belong to a package",
+        ));
+    };
+    let version = match version {
+        "latest" => latest_version(name)?,
+        version => version.to_owned(),
+    };
+    let target = matrix::target_of(executable)?;
+
+    let shim = root.join("target/iai/published");
+    std::fs::create_dir_all(&shim).map_err(|e| Error::io(&shim, e))?;
+    let shim_manifest = shim.join("Cargo.toml");
+    let contents = toml::to_string(&self::shim_manifest(
+        &root, &manifest, name, &version, target,
+    ))
+    .map_err(|e| Error::output(&shim_manifest, e.to_string()))?;
+    std::fs::write(&shim_manifest, contents).map_err(|e| Error::io(&shim_manifest, e))?;
+    // The settings are found next to the manifest of the package being benchmarked
+    let settings = root.join("iai.toml");
+    if settings.exists() {
+        std::fs::copy(&settings, shim.join("iai.toml")).map_err(|e| Error::io(&settings, e))?;
+    }
+
+    let mut published = matrix::variants(&[], &[]).remove(0);
+    published.label = format!("{} {}", name, version);
+    published.dir = format!("published/{}", version);
+    published.args = vec![
+        "--manifest-path".to_owned(),
+        shim.join("Cargo.toml").display().to_string(),
+    ];
+    let mut local = matrix::variants(&[], &[]).remove(0);
+    local.label = "local".to_owned();
+    let variants: [Variant; 2] = [published, local];
+    matrix::run(executable, &variants, filters)
+}
+
+/// The package that builds `target` against version `version` of the crate.
+fn shim_manifest(root: &Path, manifest: &Table, name: &str, version: &str, target: &str) -> Table {
+    let package = &manifest["package"];
+    let lib = manifest
+        .get("lib")
+        .and_then(|lib| lib.get("name"))
+        .and_then(Value::as_str)
+        .map_or_else(|| name.replace('-', "_"), str::to_owned);
+    let source = manifest
+        .get("bench")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .find(|bench| bench.get("name").and_then(Value::as_str) == Some(target))
+        .and_then(|bench| bench.get("path"))
+        .and_then(Value::as_str)
+        .map(|path| root.join(path))
+        .unwrap_or_else(|| {
+            let file = root.join("benches").join(format!("{}.rs", target));
+            if file.exists() {
+                file
+            } else {
+                root.join("benches").join(target).join("main.rs")
+            }
+        });
+
+    // Benchmarks can use the dependencies of the crate as well as its dev-dependencies
+    let mut dependencies = Table::new();
+    for kind in ["dependencies", "dev-dependencies"] {
+        if let Some(Value::Table(table)) = manifest.get(kind) {
+            for (dependency, spec) in table {
+                dependencies.insert(dependency.clone(), absolute_path(root, spec.clone()));
+            }
+        }
+    }
+    let mut published = Table::new();
+    published.insert("package".to_owned(), name.into());
+    published.insert("version".to_owned(), format!("={}", version).into());
+    dependencies.insert(lib, published.into());
+
+    let mut shim_package = Table::new();
+    shim_package.insert("name".to_owned(), format!("{}-published", name).into());
+    shim_package.insert("version".to_owned(), "0.0.0".into());
+    let edition = package.get("edition").and_then(Value::as_str);
+    shim_package.insert("edition".to_owned(), edition.unwrap_or("2015").into());
+    shim_package.insert("publish".to_owned(), false.into());
+    if let Some(metadata) = package.get("metadata").and_then(|m| m.get("iai")) {
+        let mut iai = Table::new();
+        iai.insert("iai".to_owned(), metadata.clone());
+        shim_package.insert("metadata".to_owned(), iai.into());
+    }
+    let mut bench = Table::new();
+    bench.insert("name".to_owned(), target.into());
+    bench.insert("path".to_owned(), source.display().to_string().into());
+    bench.insert("harness".to_owned(), false.into());
+
+    let mut shim = Table::new();
+    shim.insert("package".to_owned(), shim_package.into());
+    // Keeps it out of the workspace of the local crate
+    shim.insert("workspace".to_owned(), Table::new().into());
+    shim.insert("dependencies".to_owned(), dependencies.into());
+    shim.insert("bench".to_owned(), vec![Value::from(bench)].into());
+    shim
+}
+
+/// `spec` with a relative `path` made relative to `root` instead of the shim.
+fn absolute_path(root: &Path, mut spec: Value) -> Value {
+    if let Some(Value::String(path)) = spec.get_mut("path") {
+        *path = root.join(&*path).display().to_string();
+    }
+    spec
+}
+
+/// The newest version of `name` on crates.io, as told by `cargo search`.
+fn latest_version(name: &str) -> Result<String, Error> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .args(["search", name, "--limit", "1"])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| Error::Spawn {
+            tool: "cargo",
+            error,
+        })?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    listing
+        .lines()
+        .find_map(|line| {
+            let (found, version) = line.split_once(" = \"")?;
+            (found == name).then(|| version.split('"').next().map(str::to_owned))?
+        })
+        .ok_or_else(|| {
+            Error::Argument(format!(
+                "unable to find {} on crates.io; pass the version to compare with instead of \
+                 `latest`",
+                name
+            ))
+        })
+}
@@ -0,0 +1,1102 @@
+//! Turns measurements into the human-readable, markdown and JSON output of the runner.
+
+use std::{io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    backend::Measurement,
+    binary_size::TextSize,
+    clock,
+    config::CacheProfile,
+    history::Significance,
+    json::{self, Ran, RunEvent, Stats, Summary, Time},
+    policy::{Budget, Policy, Regression},
+    reporter::SuiteSummary,
+    template::Template,
+    Benchmark, CachegrindStats, CachegrindSummary, EstimatedTime, FunctionCost, LayoutSpread,
+    RunMetadata,
+};
+
+/// Everything measured for a single benchmark in this run, as [`run_benches`](crate::run_benches)
+/// returns it.
+#[derive(Debug)]
+pub struct BenchResult<'a> {
+    pub(crate) bench: &'a Benchmark,
+    pub(crate) stats: CachegrindStats,
+    /// The previous run, when comparing against `target/iai`.
+    pub(crate) old: Option<CachegrindStats>,
+    /// The committed instruction count, when comparing against `--baseline-dir`.
+    pub(crate) committed: Option<u64>,
+    pub(crate) layout: Option<LayoutSpread>,
+    /// What the backends besides cachegrind measured, like heap usage with `--dhat`.
+    pub(crate) measurements: Vec<Measurement>,
+    pub(crate) metadata: RunMetadata,
+    pub(crate) regression: Option<Regression>,
+    /// How the change in instructions compares with the variation in the history.
+    pub(crate) significance: Option<Significance>,
+    /// The simulated caches it was measured with.
+    pub(crate) cache: CacheProfile,
+    /// The cycles of an L1 hit, an LL hit and a RAM access it is estimated with.
+    pub(crate) cycle_weights: [u64; 3],
+    /// The functions that executed the most instructions, with `--top-functions`.
+    pub(crate) functions: Vec<FunctionCost>,
+    /// The functions that changed the most, if the benchmark did.
+    pub(crate) function_changes: Vec<FunctionChange>,
+}
+
+/// The instructions a function executed itself in the results compared against and in these, or
+/// nothing in those where it wasn't called.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FunctionChange {
+    pub(crate) function: String,
+    pub(crate) file: String,
+    pub(crate) old: Option<u64>,
+    pub(crate) new: Option<u64>,
+}
+
+impl FunctionChange {
+    pub(crate) fn delta(&self) -> i64 {
+        self.new.unwrap_or(0) as i64 - self.old.unwrap_or(0) as i64
+    }
+}
+
+impl BenchResult<'_> {
+    pub fn name(&self) -> &str {
+        self.bench.name
+    }
+
+    /// The events of the benchmark, with the calibration subtracted.
+    pub fn stats(&self) -> &CachegrindStats {
+        &self.stats
+    }
+
+    /// The events of the results it was compared against, if there are any.
+    pub fn old_stats(&self) -> Option<&CachegrindStats> {
+        self.old.as_ref()
+    }
+
+    /// Whether it regressed beyond its tolerance.
+    pub fn regressed(&self) -> bool {
+        self.regression.is_some()
+    }
+
+    /// The functions that executed the most instructions, with `--top-functions`.
+    pub fn top_functions(&self) -> &[FunctionCost] {
+        &self.functions
+    }
+
+    pub(crate) fn summary(&self) -> CachegrindSummary {
+        self.stats.summarize(self.cycle_weights)
+    }
+
+    pub(crate) fn old_summary(&self) -> Option<CachegrindSummary> {
+        self.old
+            .as_ref()
+            .map(|old| old.summarize(self.cycle_weights))
+    }
+
+    pub(crate) fn old_instructions(&self) -> Option<u64> {
+        self.old
+            .as_ref()
+            .map(|old| old.instruction_reads())
+            .or(self.committed)
+    }
+
+    /// Whether the instruction count or the estimated cycles moved by more than the noise
+    /// threshold. Benchmarks without anything to compare against haven't changed.
+    pub(crate) fn changed(&self, policy: &Policy) -> bool {
+        let instructions = self
+            .old_instructions()
+            .is_some_and(|old| !policy.is_noise(self.bench, self.stats.instruction_reads(), old));
+        let cycles = self
+            .old_summary()
+            .is_some_and(|old| !policy.is_noise(self.bench, self.summary().cycles(), old.cycles()));
+        instructions || cycles
+    }
+
+    /// The annotations given at registration, followed by the ones added while running.
+    fn annotations(&self) -> Vec<(&str, &str)> {
+        let registered = self.bench.annotations.iter().copied();
+        let runtime = self
+            .metadata
+            .annotations
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()));
+        registered.chain(runtime).collect()
+    }
+
+    /// Relative change of the instruction count, if there is anything to compare against.
+    fn instructions_delta(&self) -> Option<f64> {
+        let old = self.old_instructions()?;
+        let new = self.stats.instruction_reads();
+        Some(if old == 0 {
+            if new == 0 {
+                0.0
+            } else {
+                f64::INFINITY
+            }
+        } else {
+            (new as f64 - old as f64) / old as f64
+        })
+    }
+
+    /// The budget of the benchmark, and whether its estimated cycles exceeded it.
+    pub(crate) fn budget(&self) -> Option<(Budget, bool)> {
+        let budget = Budget::of(self.bench)?;
+        Some((budget, self.summary().cycles() > budget.cycles))
+    }
+
+    pub(crate) fn print_json(&self) {
+        let mut measurements = Vec::new();
+        for measurement in &self.measurements {
+            measurements.push((measurement.key.to_owned(), &measurement.metrics[..]));
+            if let Some(old) = &measurement.old {
+                measurements.push((format!("old_{}", measurement.key), &old[..]));
+            }
+        }
+        RunEvent::Ran(Box::new(Ran {
+            benchmark: self.bench.name,
+            params: self.bench.parameters(),
+            stats: Stats::new(&self.stats, self.cycle_weights),
+            old_stats: self
+                .old
+                .as_ref()
+                .map(|old| Stats::new(old, self.cycle_weights)),
+            baseline: self
+                .committed
+                .map(|instruction_reads| json::Baseline { instruction_reads }),
+            regressed: self.regression.is_some(),
+            regression: self.regression.as_ref().map(|regression| json::Regression {
+                metric: regression.metric.name(),
+                tolerance: regression.tolerance.to_string(),
+                accepted: regression.accepted.as_deref(),
+            }),
+            significance: self.significance.map(|significance| json::Significance {
+                significant: significance.significant,
+                noise: significance.noise,
+                runs: significance.runs,
+            }),
+            budget: self.budget().map(|(budget, exceeded)| json::Budget {
+                cycles: budget.cycles,
+                exceeded,
+            }),
+            layout: self.layout.as_ref(),
+            measurements,
+            annotations: self.annotations(),
+            items: self.metadata.items.map(|items| json::Items {
+                count: items,
+                instructions: self.stats.instruction_reads() as f64 / items as f64,
+                cycles: self.summary().cycles() as f64 / items as f64,
+            }),
+            threads: self.metadata.threads,
+            cpus: self.metadata.cpus.as_deref(),
+            cache: json::Cache::new(&self.cache),
+            functions: (!self.functions.is_empty())
+                .then(|| self.functions.iter().map(json::Function::new).collect()),
+            function_changes: (!self.function_changes.is_empty())
+                .then_some(&self.function_changes[..]),
+        }))
+        .print();
+    }
+}
+
+#[cfg(test)]
+impl<'a> BenchResult<'a> {
+    /// A result of `bench` with the `events` of this run and of the previous one, for tests.
+    pub(crate) fn of(
+        bench: &'a Benchmark,
+        events: &[(&str, u64)],
+        old: Option<&[(&str, u64)]>,
+    ) -> BenchResult<'a> {
+        let stats = |events: &[(&str, u64)]| {
+            CachegrindStats::from_events(
+                events
+                    .iter()
+                    .map(|&(event, count)| (event.to_owned(), count))
+                    .collect(),
+            )
+        };
+        let config = crate::Config::default();
+        BenchResult {
+            bench,
+            stats: stats(events),
+            old: old.map(stats),
+            committed: None,
+            layout: None,
+            measurements: Vec::new(),
+            metadata: RunMetadata::default(),
+            regression: None,
+            significance: None,
+            cache: config.cache,
+            cycle_weights: config.cycle_weights,
+            functions: Vec::new(),
+            function_changes: Vec::new(),
+        }
+    }
+}
+
+/// Order of the benchmarks in the human-readable report, chosen with
+/// `--sort name|cycles|instructions|delta[:asc|:desc]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Sort {
+    key: SortKey,
+    descending: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Name,
+    Cycles,
+    Instructions,
+    Delta,
+}
+
+impl Sort {
+    pub(crate) fn parse(s: &str) -> Option<Sort> {
+        let (key, descending) = match s.rsplit_once(':') {
+            Some((key, "asc")) => (key, false),
+            Some((key, "desc")) => (key, true),
+            Some(_) => return None,
+            None => (s, false),
+        };
+        let key = match key {
+            "name" => SortKey::Name,
+            "cycles" => SortKey::Cycles,
+            "instructions" => SortKey::Instructions,
+            "delta" => SortKey::Delta,
+            _ => return None,
+        };
+        Some(Sort { key, descending })
+    }
+
+    /// Sorts `results` in place. The sort is stable, so ties stay in registration order, and
+    /// benchmarks without a delta always go last when sorting by delta.
+    pub(crate) fn apply(&self, results: &mut [BenchResult]) {
+        use std::cmp::Ordering;
+        let descending = self.descending;
+        let directed = |ordering: Ordering| {
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+        match self.key {
+            SortKey::Name => results.sort_by(|a, b| directed(a.bench.name.cmp(b.bench.name))),
+            SortKey::Cycles => {
+                results.sort_by(|a, b| directed(a.summary().cycles().cmp(&b.summary().cycles())))
+            }
+            SortKey::Instructions => results.sort_by(|a, b| {
+                directed(
+                    a.stats
+                        .instruction_reads()
+                        .cmp(&b.stats.instruction_reads()),
+                )
+            }),
+            SortKey::Delta => {
+                results.sort_by(
+                    |a, b| match (a.instructions_delta(), b.instructions_delta()) {
+                        (Some(a), Some(b)) => directed(a.total_cmp(&b)),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    },
+                )
+            }
+        }
+    }
+}
+
+/// How the results are reported on stdout, chosen with
+/// `--format pretty|json|markdown|junit|bmf` (or `--json`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Format {
+    #[default]
+    Pretty,
+    /// One JSON object per line, see [`json`].
+    Json,
+    /// A GitHub-flavored markdown table, e.g. for PR comments.
+    Markdown,
+    /// A JUnit XML test report, with regressed benchmarks as failed test cases.
+    Junit,
+    /// Bencher's metric format, see [`json::bmf`].
+    Bmf,
+}
+
+impl Format {
+    pub(crate) fn parse(s: &str) -> Option<Format> {
+        match s {
+            // libtest's terse format is requested by tools that list benchmarks
+            "pretty" | "terse" => Some(Format::Pretty),
+            "json" => Some(Format::Json),
+            "markdown" => Some(Format::Markdown),
+            "junit" => Some(Format::Junit),
+            "bmf" => Some(Format::Bmf),
+            _ => None,
+        }
+    }
+}
+
+/// Whether only instruction counts are reported, as with `--baseline-dir`.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Detail {
+    Full,
+    InstructionsOnly,
+}
+
+fn signed_short(n: f64) -> String {
+    let n_abs = n.abs();
+
+    if n_abs < 10.0 {
+        format!("{:+.6}", n)
+    } else if n_abs < 100.0 {
+        format!("{:+.5}", n)
+    } else if n_abs < 1000.0 {
+        format!("{:+.4}", n)
+    } else if n_abs < 10000.0 {
+        format!("{:+.3}", n)
+    } else if n_abs < 100000.0 {
+        format!("{:+.2}", n)
+    } else if n_abs < 1000000.0 {
+        format!("{:+.1}", n)
+    } else {
+        format!("{:+.0}", n)
+    }
+}
+
+const NO_CHANGE: &str = " (No change)";
+
+/// Changes of at least this many percent get an arrow in the report, so that they stand out in a
+/// long suite.
+const LARGE_CHANGE: f64 = 5.0;
+
+fn percentage_diff(new: u64, old: u64) -> String {
+    if new == old {
+        return NO_CHANGE.to_owned();
+    }
+
+    let new: f64 = new as f64;
+    let old: f64 = old as f64;
+
+    let diff = (new - old) / old;
+    let pct = diff * 100.0;
+
+    if pct.abs() < 0.0001 {
+        return NO_CHANGE.to_owned();
+    }
+
+    format!(" ({:>+6}%)", signed_short(pct))
+}
+
+/// Whether the report is colored: only on a terminal, and unless `NO_COLOR` is set.
+fn use_color() -> bool {
+    use std::io::IsTerminal;
+    static COLOR: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *COLOR.get_or_init(|| {
+        std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+            && std::io::stdout().is_terminal()
+    })
+}
+
+/// Paints `text` with the ANSI color `code`, if the report is colored.
+fn paint(text: &str, code: &str) -> String {
+    if use_color() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Like [`percentage_diff`] for the human-readable report: increases in red, decreases in green,
+/// and with an arrow when they are large.
+fn highlighted_diff(new: u64, old: u64) -> String {
+    let diff = percentage_diff(new, old);
+    if diff == NO_CHANGE {
+        return diff;
+    }
+    let pct = (new as f64 - old as f64) / old as f64 * 100.0;
+    let (color, arrow) = if new > old {
+        ("31", " ▲")
+    } else {
+        ("32", " ▼")
+    };
+    let arrow = if pct.abs() >= LARGE_CHANGE { arrow } else { "" };
+    paint(&format!("{}{}", diff, arrow), color)
+}
+
+fn print_regression(regression: &Option<Regression>) {
+    match regression {
+        Some(Regression {
+            metric,
+            tolerance,
+            accepted: Some(note),
+        }) => println!(
+            "  Accepted regression: {} grew by more than {} ({})",
+            metric.name(),
+            tolerance,
+            note
+        ),
+        Some(Regression {
+            metric, tolerance, ..
+        }) => println!(
+            "  {}",
+            paint(
+                &format!(
+                    "REGRESSED: {} grew by more than {}",
+                    metric.name(),
+                    tolerance
+                ),
+                "1;31"
+            )
+        ),
+        None => {}
+    }
+}
+
+fn estimated_time(cycles: u64) -> String {
+    match EstimatedTime::estimate(cycles) {
+        Some(time) => format!(" ({})", time),
+        None => "".to_owned(),
+    }
+}
+
+/// One metric with the change since `old`, laid out by the [`Template`] of the project.
+fn metric_line(key: &str, label: &str, value: u64, old: Option<u64>, extra: &str) -> String {
+    let change = old.map_or_else(String::new, |old| highlighted_diff(value, old));
+    Template::current().line(key, label, value, old, &change, extra)
+}
+
+/// The labelled metrics of a backend, with the change since the old measurement.
+pub(crate) fn print_measurement(measurement: &Measurement) {
+    for (i, metric) in measurement.metrics.iter().enumerate() {
+        if let Some(label) = metric.label {
+            let key = format!("{}.{}", measurement.key, metric.key);
+            let old = measurement.old.as_ref().map(|old| old[i].value);
+            println!("{}", metric_line(&key, label, metric.value, old, ""));
+        }
+    }
+}
+
+/// Prints the detailed report of one benchmark.
+pub(crate) fn print_bench(result: &BenchResult, detail: Detail, verbose: bool) {
+    let stats = &result.stats;
+    println!("{}", result.bench.name);
+    let annotations = result.annotations();
+    if !annotations.is_empty() {
+        let annotations: Vec<String> = annotations
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        println!("  ({})", annotations.join(", "));
+    }
+    let old_instructions = result.old_instructions();
+    let no_baseline = old_instructions.is_none() && detail == Detail::InstructionsOnly;
+    println!(
+        "{}",
+        metric_line(
+            "instructions",
+            "Instructions",
+            stats.instruction_reads(),
+            old_instructions,
+            if no_baseline { " (No baseline)" } else { "" }
+        )
+    );
+    print_regression(&result.regression);
+    if let Some(significance) = result.significance {
+        println!(
+            "  History:          {} (±{:.3}% over {} runs)",
+            if significance.significant {
+                "significant"
+            } else {
+                "within historical noise"
+            },
+            significance.noise,
+            significance.runs
+        );
+    }
+    if detail == Detail::Full {
+        let summary = result.summary();
+        let old_summary = result.old_summary();
+        let metrics = [
+            (
+                "l1_accesses",
+                "L1 Accesses",
+                summary.l1_hits,
+                old_summary.as_ref().map(|old| old.l1_hits),
+            ),
+            (
+                "l2_accesses",
+                "L2 Accesses",
+                summary.l3_hits,
+                old_summary.as_ref().map(|old| old.l3_hits),
+            ),
+            (
+                "ram_accesses",
+                "RAM Accesses",
+                summary.ram_hits,
+                old_summary.as_ref().map(|old| old.ram_hits),
+            ),
+        ];
+        // Unknown without the cache simulation, e.g. with `CACHEGRIND_FLAGS=--cache-sim=no`
+        if stats.has_cache_events() {
+            for (key, label, value, old) in metrics {
+                println!("{}", metric_line(key, label, value, old, ""));
+            }
+        }
+        println!(
+            "{}",
+            metric_line(
+                "estimated_cycles",
+                "Estimated Cycles",
+                summary.cycles(),
+                old_summary.as_ref().map(CachegrindSummary::cycles),
+                &estimated_time(summary.cycles())
+            )
+        );
+        if let Some(branches) = stats.branches() {
+            let old = result.old.as_ref().and_then(CachegrindStats::branches);
+            let mispredictions = [
+                (
+                    "conditional_mispredicted",
+                    "Cond. Mispredicts",
+                    branches.conditional_mispredicted,
+                    old.map(|old| old.conditional_mispredicted),
+                ),
+                (
+                    "indirect_mispredicted",
+                    "Ind. Mispredicts",
+                    branches.indirect_mispredicted,
+                    old.map(|old| old.indirect_mispredicted),
+                ),
+            ];
+            for (key, label, value, old) in mispredictions {
+                println!("{}", metric_line(key, label, value, old, ""));
+            }
+        }
+    }
+    if let Some(items) = result.metadata.items {
+        let per_item = |value: u64| value as f64 / items as f64;
+        println!(
+            "  Per Item:         {:>15.2} instructions, {:.2} estimated cycles ({} items)",
+            per_item(stats.instruction_reads()),
+            per_item(result.summary().cycles()),
+            items
+        );
+    }
+    if let Some((budget, true)) = result.budget() {
+        let message = format!(
+            "OVER BUDGET: more than the {} cycles of {}",
+            budget.cycles,
+            result.bench.budget.unwrap_or_default().trim()
+        );
+        println!("  {}", paint(&message, "1;31"));
+    }
+    for measurement in &result.measurements {
+        print_measurement(measurement);
+    }
+    // Several threads share the simulated caches, so their interleaving shows up in the metrics
+    if let Some(threads) = result.metadata.threads.filter(|&t| t > 1) {
+        println!("{}", metric_line("threads", "Threads", threads, None, ""));
+    }
+    if verbose {
+        if let Some(cpus) = &result.metadata.cpus {
+            println!("  Allowed CPUs:     {:>15}", cpus);
+        }
+        for (event, count) in stats.extra_events() {
+            let old = result.old.as_ref().and_then(|old| old.events.get(event));
+            println!("{}", metric_line(event, event, count, old.copied(), ""));
+        }
+    }
+    print_functions(&result.functions);
+    print_function_changes(&result.function_changes);
+    if let Some(layout) = &result.layout {
+        layout.print();
+    }
+    println!();
+}
+
+/// Where a change in instructions comes from: the functions that gained or lost the most.
+fn print_function_changes(changes: &[FunctionChange]) {
+    if changes.is_empty() {
+        return;
+    }
+    println!("  Changed Functions (instructions):");
+    for change in changes {
+        let note = match (change.old, change.new) {
+            (None, _) => " [new]",
+            (_, None) => " [gone]",
+            _ => "",
+        };
+        println!(
+            "    {:>+15}  {} ({}){}",
+            change.delta(),
+            change.function,
+            change.file,
+            note
+        );
+    }
+}
+
+/// The functions of `--top-functions`, with the instructions each executed itself.
+fn print_functions(functions: &[FunctionCost]) {
+    if functions.is_empty() {
+        return;
+    }
+    println!("  Top Functions (instructions, harness included):");
+    for function in functions {
+        println!(
+            "    {:>15}  {} ({})",
+            function.instruction_reads(),
+            function.function,
+            function.file
+        );
+    }
+}
+
+/// The group of a benchmark is everything before the last `::` of its name.
+pub(crate) fn group_of(name: &str) -> Option<&str> {
+    name.rsplit_once("::").map(|(group, _)| group)
+}
+
+/// Summed instructions and cycles of several benchmarks. The old totals are only known if every
+/// one of the benchmarks has an old measurement.
+struct Totals {
+    count: usize,
+    instructions: u64,
+    cycles: u64,
+    old_instructions: Option<u64>,
+    old_cycles: Option<u64>,
+}
+
+impl Totals {
+    fn new() -> Totals {
+        Totals {
+            count: 0,
+            instructions: 0,
+            cycles: 0,
+            old_instructions: Some(0),
+            old_cycles: Some(0),
+        }
+    }
+
+    fn add(&mut self, result: &BenchResult) {
+        self.count += 1;
+        self.instructions += result.stats.instruction_reads();
+        self.cycles += result.summary().cycles();
+        self.old_instructions = self
+            .old_instructions
+            .zip(result.old_instructions())
+            .map(|(a, b)| a + b);
+        self.old_cycles = self
+            .old_cycles
+            .zip(result.old_summary())
+            .map(|(a, old)| a + old.cycles());
+    }
+
+    fn print(&self, indent: &str, detail: Detail) {
+        let instructions = metric_line(
+            "instructions",
+            "Instructions",
+            self.instructions,
+            self.old_instructions,
+            "",
+        );
+        println!("{}{}", indent, instructions);
+        if detail == Detail::Full {
+            let cycles = metric_line(
+                "estimated_cycles",
+                "Estimated Cycles",
+                self.cycles,
+                self.old_cycles,
+                &estimated_time(self.cycles),
+            );
+            println!("{}{}", indent, cycles);
+        }
+    }
+
+    fn summary(&self) -> Summary {
+        Summary {
+            benchmarks: self.count,
+            instruction_reads: self.instructions,
+            cycles: self.cycles,
+            old_instruction_reads: self.old_instructions,
+            old_cycles: self.old_cycles,
+            time: Time::estimate(self.cycles),
+        }
+    }
+}
+
+/// Sums up the results per group, in order of first appearance.
+fn group_totals<'a>(results: &'a [BenchResult]) -> Vec<(&'a str, Totals)> {
+    let mut groups: Vec<(&str, Totals)> = Vec::new();
+    for result in results {
+        let Some(group) = group_of(result.bench.name) else {
+            continue;
+        };
+        let index = match groups.iter().position(|(name, _)| *name == group) {
+            Some(index) => index,
+            None => {
+                groups.push((group, Totals::new()));
+                groups.len() - 1
+            }
+        };
+        groups[index].1.add(result);
+    }
+    groups
+}
+
+pub(crate) fn print_groups(results: &[BenchResult], detail: Detail) {
+    let groups = group_totals(results);
+    if groups.is_empty() {
+        return;
+    }
+    println!("Group subtotals");
+    for (group, totals) in groups {
+        println!("  {} ({} benchmarks)", group, totals.count);
+        totals.print("  ", detail);
+    }
+    println!();
+}
+
+pub(crate) fn groups_json(results: &[BenchResult]) {
+    for (group, totals) in group_totals(results) {
+        RunEvent::Group {
+            group,
+            summary: totals.summary(),
+        }
+        .print();
+    }
+}
+
+fn suite_totals(results: &[BenchResult]) -> Totals {
+    let mut totals = Totals::new();
+    for result in results {
+        totals.add(result);
+    }
+    totals
+}
+
+pub(crate) fn print_totals(results: &[BenchResult], detail: Detail) {
+    let totals = suite_totals(results);
+    println!("Total ({} benchmarks)", totals.count);
+    totals.print("", detail);
+    println!();
+}
+
+/// The results as a markdown table, with a row per benchmark and a last one for the totals.
+/// With `markers`, changes are also flagged with ▲ or ▼, to stand out in a long table.
+pub(crate) fn markdown(results: &[BenchResult], detail: Detail, markers: bool) -> String {
+    let cell = |new: u64, old: Option<u64>| match old {
+        Some(old) => {
+            let diff = percentage_diff(new, old);
+            let marker = match new.cmp(&old) {
+                _ if !markers || diff == NO_CHANGE => "",
+                std::cmp::Ordering::Greater => " ▲",
+                std::cmp::Ordering::Less => " ▼",
+                std::cmp::Ordering::Equal => "",
+            };
+            format!("{}{}{}", new, marker, diff)
+        }
+        None => new.to_string(),
+    };
+    let mut table = match detail {
+        Detail::Full => "| Benchmark | Instructions | L1 Accesses | L2 Accesses | RAM Accesses \
+            | Estimated Cycles |\n|:--|--:|--:|--:|--:|--:|\n"
+            .to_owned(),
+        Detail::InstructionsOnly => "| Benchmark | Instructions |\n|:--|--:|\n".to_owned(),
+    };
+    for result in results {
+        let mut row = format!("| `{}`", result.bench.name.replace('|', "\\|"));
+        if let Some(regression) = &result.regression {
+            let status = match regression.accepted {
+                Some(_) => "accepted regression",
+                None => "**regressed**",
+            };
+            row.push_str(&format!(" ({})", status));
+        }
+        let instructions = result.stats.instruction_reads();
+        row.push_str(&format!(
+            " | {}",
+            cell(instructions, result.old_instructions())
+        ));
+        if detail == Detail::Full {
+            let summary = result.summary();
+            let old = result.old_summary();
+            let cells = [
+                cell(summary.l1_hits, old.as_ref().map(|old| old.l1_hits)),
+                cell(summary.l3_hits, old.as_ref().map(|old| old.l3_hits)),
+                cell(summary.ram_hits, old.as_ref().map(|old| old.ram_hits)),
+                cell(summary.cycles(), old.as_ref().map(|old| old.cycles())),
+            ];
+            for cell in &cells {
+                row.push_str(&format!(" | {}", cell));
+            }
+        }
+        table.push_str(&row);
+        table.push_str(" |\n");
+    }
+    let totals = suite_totals(results);
+    table.push_str(&format!(
+        "| **Total ({} benchmarks)** | {}",
+        totals.count,
+        cell(totals.instructions, totals.old_instructions)
+    ));
+    if detail == Detail::Full {
+        table.push_str(&format!(
+            " | | | | {}",
+            cell(totals.cycles, totals.old_cycles)
+        ));
+    }
+    table.push_str(" |\n");
+    table
+}
+
+/// The results as a JUnit XML report, for the test panels of CI servers. Every benchmark is a test
+/// case that fails when it regressed beyond its tolerance (unless that was accepted) or exceeded
+/// its budget; the estimated time, when the clock rate is known, is its duration.
+pub(crate) fn junit(results: &[BenchResult]) -> String {
+    let mut cases = String::new();
+    let mut failures = 0;
+    for result in results {
+        let cycles = result.summary().cycles();
+        let seconds = clock()
+            .filter(|&hz| hz > 0)
+            .map_or(0.0, |hz| cycles as f64 / hz as f64);
+        let mut failure = match &result.regression {
+            Some(regression) if regression.accepted.is_none() => Some(format!(
+                "{} grew by more than {}",
+                regression.metric.name(),
+                regression.tolerance
+            )),
+            _ => None,
+        };
+        if let Some((budget, true)) = result.budget() {
+            failure.get_or_insert(format!(
+                "cycles exceeded the budget of {} cycles",
+                budget.cycles
+            ));
+        }
+        let name = result.bench.name;
+        cases.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.9}\">\n",
+            xml_escape(name),
+            xml_escape(group_of(name).unwrap_or("iai")),
+            seconds
+        ));
+        if let Some(failure) = failure {
+            failures += 1;
+            cases.push_str(&format!(
+                "      <failure message=\"{}\"/>\n",
+                xml_escape(&failure)
+            ));
+        }
+        let old = result.old_instructions().map_or_else(String::new, |old| {
+            percentage_diff(result.stats.instruction_reads(), old)
+        });
+        cases.push_str(&format!(
+            "      <system-out>Instructions: {}{}, Estimated Cycles: {}</system-out>\n",
+            result.stats.instruction_reads(),
+            xml_escape(&old),
+            cycles
+        ));
+        cases.push_str("    </testcase>\n");
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n  <testsuite name=\"iai\" \
+         tests=\"{}\" failures=\"{}\">\n{}  </testsuite>\n</testsuites>\n",
+        results.len(),
+        failures,
+        cases
+    )
+}
+
+/// Escapes `s` for XML (and HTML) text and attribute values.
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Appends the results to the job summary of a GitHub Actions step, if running in one.
+pub(crate) fn github_summary(results: &[BenchResult], detail: Detail) {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+        return;
+    };
+    let summary = format!(
+        "### iai benchmarks\n\n{}\n",
+        markdown(results, detail, true)
+    );
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(summary.as_bytes()));
+    if let Err(e) = result {
+        eprintln!(
+            "Warning: unable to write the job summary to {}: {}",
+            Path::new(&path).display(),
+            e
+        );
+    }
+}
+
+pub(crate) fn print_cache(cache: &CacheProfile) {
+    let [i1, d1, ll] = cache.geometry();
+    println!(
+        "Simulated caches ({}): I1 {}, D1 {}, LL {} (size, associativity, line size)\n",
+        cache.name, i1, d1, ll
+    );
+}
+
+pub(crate) fn print_cycle_weights([l1, ll, ram]: [u64; 3]) {
+    println!(
+        "Estimated cycles: {} per L1 hit, {} per LL hit, {} per RAM access\n",
+        l1, ll, ram
+    );
+}
+
+pub(crate) fn print_text_sizes(sizes: &[TextSize]) {
+    if sizes.is_empty() {
+        return;
+    }
+    println!("Text size (bytes)");
+    for size in sizes {
+        let line = metric_line(&size.file, &size.file, size.bytes, size.old, "");
+        println!("{}", line);
+    }
+    println!();
+}
+
+pub(crate) fn text_sizes_json(sizes: &[TextSize]) {
+    for size in sizes {
+        RunEvent::TextSize {
+            file: &size.file,
+            bytes: size.bytes,
+            old_bytes: size.old,
+        }
+        .print();
+    }
+}
+
+/// The single line printed to stderr at the end of every run, e.g.
+/// `iai: 42 benches, 3 regressed, 5 improved, 1 failed`.
+pub(crate) fn exit_summary(results: &[BenchResult], policy: &Policy, failed: usize) -> String {
+    let (regressed, improved) = changes(results, policy);
+    format!(
+        "iai: {} benches, {} regressed, {} improved, {} failed",
+        results.len() + failed,
+        regressed,
+        improved,
+        failed
+    )
+}
+
+/// The summary of the run for the [`Reporter`](crate::Reporter)s.
+pub(crate) fn suite_summary(
+    results: &[BenchResult],
+    policy: &Policy,
+    failed: usize,
+) -> SuiteSummary {
+    let (regressed, improved) = changes(results, policy);
+    let totals = suite_totals(results);
+    SuiteSummary {
+        benchmarks: results.len() + failed,
+        regressed,
+        improved,
+        failed,
+        instruction_reads: totals.instructions,
+        estimated_cycles: totals.cycles,
+        old_instruction_reads: totals.old_instructions,
+        old_estimated_cycles: totals.old_cycles,
+    }
+}
+
+/// How many of the benchmarks regressed and how many improved.
+fn changes(results: &[BenchResult], policy: &Policy) -> (usize, usize) {
+    let (mut regressed, mut improved) = (0, 0);
+    for result in results {
+        let new = result.stats.instruction_reads();
+        match result.old_instructions() {
+            Some(old) if !policy.is_noise(result.bench, new, old) => {
+                if new > old {
+                    regressed += 1;
+                } else {
+                    improved += 1;
+                }
+            }
+            // Gated by another metric than the instruction count
+            _ if result.regression.is_some() => regressed += 1,
+            _ => {}
+        }
+    }
+    (regressed, improved)
+}
+
+pub(crate) fn totals_json(results: &[BenchResult]) {
+    RunEvent::Totals(suite_totals(results).summary()).print();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Metric, Tolerance};
+
+    fn bench() {}
+
+    #[test]
+    fn tabulates_the_results_in_markdown() {
+        let parse = Benchmark::new("parse|split", bench);
+        let print = Benchmark::new("print", bench);
+        let mut regressed = BenchResult::of(&parse, &[("Ir", 1100)], Some(&[("Ir", 1000)]));
+        regressed.regression = Some(Regression {
+            metric: Metric::Instructions,
+            tolerance: Tolerance::Percent(2.0),
+            accepted: None,
+        });
+        let results = [
+            regressed,
+            BenchResult::of(&print, &[("Ir", 500)], Some(&[("Ir", 500)])),
+        ];
+        assert_eq!(
+            markdown(&results, Detail::InstructionsOnly, true),
+            "| Benchmark | Instructions |\n\
+             |:--|--:|\n\
+             | `parse\\|split` (**regressed**) | 1100 ▲ (+10.00000%) |\n\
+             | `print` | 500 (No change) |\n\
+             | **Total (2 benchmarks)** | 1600 ▲ (+6.666667%) |\n"
+        );
+        let table = markdown(&results[1..], Detail::Full, false);
+        let mut lines = table.lines();
+        assert!(lines.next().unwrap().ends_with("| Estimated Cycles |"));
+        assert_eq!(lines.nth(1).unwrap().matches(" | ").count(), 5);
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("| **Total (1 benchmarks)** | 500 (No change) | | | | "));
+    }
+
+    #[test]
+    fn reports_regressions_as_junit_failures() {
+        let parse = Benchmark::new("parse::<&str>", bench);
+        let print = Benchmark::new("print", bench);
+        let regression = |accepted| Regression {
+            metric: Metric::Instructions,
+            tolerance: Tolerance::Percent(2.0),
+            accepted,
+        };
+        let mut regressed = BenchResult::of(&parse, &[("Ir", 1100)], Some(&[("Ir", 1000)]));
+        regressed.regression = Some(regression(None));
+        let mut accepted = BenchResult::of(&print, &[("Ir", 500)], None);
+        accepted.regression = Some(regression(Some("slower, but correct".to_owned())));
+        let report = junit(&[regressed, accepted]);
+        assert!(report.contains("<testsuite name=\"iai\" tests=\"2\" failures=\"1\">"));
+        assert!(report.contains("<testcase name=\"parse::&lt;&amp;str&gt;\" classname=\"parse\" "));
+        assert!(report.contains("<failure message=\"instructions grew by more than 2%\"/>"));
+        assert!(report.contains("<system-out>Instructions: 1100 (+10.00000%), Estimated Cycles: "));
+        assert!(report.contains("<testcase name=\"print\" classname=\"iai\" "));
+        assert_eq!(report.matches("<failure").count(), 1);
+    }
+}
@@ -0,0 +1,116 @@
+//! Reporters of one's own, which are told about the run as it goes along: to feed the results to a
+//! dashboard, say, or to print them in a format iai doesn't know. They report besides the format
+//! chosen with `--format`, which is a reporter too, and are installed with
+//! [`Config::reporter`](crate::Config::reporter) or [`Iai::reporter`](crate::Iai::reporter):
+//!
+//! ```no_run
+//! # mod suite {
+//! # fn upload(_name: &str, _instructions: u64) {}
+//! # fn bench_method1() {}
+//! # fn bench_method2() {}
+//! struct Dashboard;
+//!
+//! impl iai::Reporter for Dashboard {
+//!     fn on_bench_complete(
+//!         &mut self,
+//!         name: &str,
+//!         stats: &iai::CachegrindStats,
+//!         _old: Option<&iai::CachegrindStats>,
+//!     ) {
+//!         upload(name, stats.instruction_reads());
+//!     }
+//! }
+//!
+//! iai::main!(
+//!     config = iai::Config::new().reporter(Dashboard);
+//!     benches = bench_method1, bench_method2
+//! );
+//! # }
+//! ```
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{BenchResult, CachegrindStats, Error};
+
+/// Receives the results of a run. Every method does nothing unless implemented, besides those
+/// that hand out the whole results, which pass on the events by default.
+pub trait Reporter {
+    /// Called before the first benchmark runs, with the names of those that will.
+    fn on_suite_start(&mut self, _benchmarks: &[&str]) {}
+
+    /// Called before a benchmark is measured.
+    fn on_bench_start(&mut self, _name: &str) {}
+
+    /// Called once a benchmark has been measured, with the calibration subtracted, and with the
+    /// results it is compared against, if there are any.
+    fn on_bench_complete(
+        &mut self,
+        _name: &str,
+        _stats: &CachegrindStats,
+        _old: Option<&CachegrindStats>,
+    ) {
+    }
+
+    /// Called once a benchmark has been measured and checked for regressions, with everything
+    /// known about it. Calls [`on_bench_complete`](Reporter::on_bench_complete) unless
+    /// implemented.
+    fn on_bench_result(&mut self, result: &BenchResult) {
+        self.on_bench_complete(result.name(), result.stats(), result.old_stats());
+    }
+
+    /// Called when a benchmark couldn't be measured, instead of
+    /// [`on_bench_complete`](Reporter::on_bench_complete).
+    fn on_bench_failed(&mut self, _name: &str, _error: &Error) {}
+
+    /// Called once all the benchmarks ran.
+    fn on_suite_end(&mut self, _summary: &SuiteSummary) {}
+
+    /// Called once all the benchmarks ran, with the results of those that didn't fail. Calls
+    /// [`on_suite_end`](Reporter::on_suite_end) unless implemented.
+    fn on_suite_results(&mut self, _results: &[BenchResult], summary: &SuiteSummary) {
+        self.on_suite_end(summary);
+    }
+}
+
+/// The outcome of a whole run, as summed up at its end.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SuiteSummary {
+    /// The benchmarks that ran, including those that failed.
+    pub benchmarks: usize,
+    pub regressed: usize,
+    pub improved: usize,
+    /// The benchmarks that returned `Err`.
+    pub failed: usize,
+    /// The instructions of all benchmarks that didn't fail.
+    pub instruction_reads: u64,
+    pub estimated_cycles: u64,
+    /// The same totals of the results compared against, if every benchmark has them.
+    pub old_instruction_reads: Option<u64>,
+    pub old_estimated_cycles: Option<u64>,
+}
+
+/// The reporters of a configuration, shared by its clones.
+#[derive(Clone, Default)]
+pub(crate) struct Reporters(Vec<Rc<RefCell<dyn Reporter>>>);
+
+impl fmt::Debug for Reporters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} reporters", self.0.len())
+    }
+}
+
+impl Reporters {
+    pub(crate) fn push(&mut self, reporter: impl Reporter + 'static) {
+        self.0.push(Rc::new(RefCell::new(reporter)));
+    }
+
+    pub(crate) fn extend(&mut self, reporters: &Reporters) {
+        self.0.extend(reporters.0.iter().cloned());
+    }
+
+    pub(crate) fn each(&self, mut report: impl FnMut(&mut dyn Reporter)) {
+        for reporter in &self.0 {
+            report(&mut *reporter.borrow_mut());
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Resource usage of the benchmark process as the kernel sees it (`--rusage`): its peak resident
+//! set, page faults and context switches.
+//!
+//! Unlike the other backends this one doesn't involve valgrind, whose own memory and scheduling
+//! would drown out the benchmark's; the benchmark runs natively and reports the numbers itself
+//! from `/proc`, so they are only available on Linux.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    backend::{self, Backend, Metric},
+    Config, Error, RunMetadata, Target, BENCHMARK_PROCESS_VAR, HARNESS_VERSION,
+};
+
+/// Resource usage of one benchmark.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RusageStats {
+    pub(crate) max_rss_kb: u64,
+    pub(crate) minor_faults: u64,
+    pub(crate) major_faults: u64,
+    pub(crate) voluntary_switches: u64,
+    pub(crate) involuntary_switches: u64,
+}
+
+impl RusageStats {
+    const PREFIX: &'static str = "iai-rusage";
+    /// Asks the benchmark process to [`report`](RusageStats::report) once it's done.
+    pub(crate) const CHILD_ARG: &'static str = "--iai-rusage";
+
+    /// Called by the benchmark process once it's done.
+    pub(crate) fn report() {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next())
+                .unwrap_or("0")
+                .to_owned()
+        };
+        // The fields after the command name, which may itself contain spaces
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+        let stat: Vec<&str> = stat
+            .rsplit_once(") ")
+            .map_or(Vec::new(), |(_, fields)| fields.split(' ').collect());
+        let stat_field = |index: usize| stat.get(index).copied().unwrap_or("0");
+        eprintln!(
+            "{} max_rss_kb={} minor_faults={} major_faults={} voluntary_switches={} \
+             involuntary_switches={}",
+            Self::PREFIX,
+            field("VmHWM:"),
+            stat_field(7),
+            stat_field(9),
+            field("voluntary_ctxt_switches:"),
+            field("nonvoluntary_ctxt_switches:"),
+        );
+    }
+}
+
+impl Backend for RusageStats {
+    const KEY: &'static str = "rusage";
+
+    fn tool(_: &Config) -> &'static str {
+        "rusage"
+    }
+
+    fn args(_: &Config) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Runs the benchmark without valgrind, ignoring `cmd`, and stores what it reported in
+    /// `output_file` as `key=value` lines.
+    fn execute(
+        _: Command,
+        executable: &str,
+        target: Target,
+        _: &Config,
+        child_args: &[&str],
+        output_file: &Path,
+    ) -> Result<RunMetadata, Error> {
+        let mut cmd = Command::new(executable);
+        cmd.env(BENCHMARK_PROCESS_VAR, "1")
+            .args(target.child_args())
+            .arg("--iai-harness")
+            .arg(HARNESS_VERSION)
+            .args(child_args)
+            .arg(Self::CHILD_ARG)
+            .stdout(Stdio::null());
+        let output = backend::run_child("rusage", cmd)?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let fields = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix(Self::PREFIX))
+            .ok_or_else(|| {
+                Error::output(
+                    output_file,
+                    "the benchmark didn't report its resource usage",
+                )
+            })?;
+        let contents: String = fields
+            .split_whitespace()
+            .map(|field| format!("{}\n", field))
+            .collect();
+        std::fs::write(output_file, contents).map_err(|e| Error::io(output_file, e))?;
+        Ok(RunMetadata::parse(&output.stderr))
+    }
+
+    fn parse(file: &Path) -> Result<RusageStats, Error> {
+        let contents = std::fs::read_to_string(file).map_err(|e| Error::io(file, e))?;
+        let mut stats = RusageStats::default();
+        for line in contents.lines() {
+            let (key, value) = line.split_once('=').unwrap_or((line, ""));
+            let value = value
+                .parse()
+                .map_err(|_| Error::output(file, format!("invalid line {:?}", line)))?;
+            match key {
+                "max_rss_kb" => stats.max_rss_kb = value,
+                "minor_faults" => stats.minor_faults = value,
+                "major_faults" => stats.major_faults = value,
+                "voluntary_switches" => stats.voluntary_switches = value,
+                "involuntary_switches" => stats.involuntary_switches = value,
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+
+    fn subtract(&self, calibration: &RusageStats) -> RusageStats {
+        RusageStats {
+            max_rss_kb: self.max_rss_kb.saturating_sub(calibration.max_rss_kb),
+            minor_faults: self.minor_faults.saturating_sub(calibration.minor_faults),
+            major_faults: self.major_faults.saturating_sub(calibration.major_faults),
+            voluntary_switches: self
+                .voluntary_switches
+                .saturating_sub(calibration.voluntary_switches),
+            involuntary_switches: self
+                .involuntary_switches
+                .saturating_sub(calibration.involuntary_switches),
+        }
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        vec![
+            Metric::new("max_rss_kb", Some("Peak RSS (KiB)"), self.max_rss_kb),
+            Metric::new("minor_faults", Some("Page Faults"), self.minor_faults),
+            Metric::new("major_faults", Some("Major Faults"), self.major_faults),
+            Metric::new("voluntary_switches", None, self.voluntary_switches),
+            Metric::new("involuntary_switches", None, self.involuntary_switches),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, contents: &str) -> Result<RusageStats, Error> {
+        let path = std::env::temp_dir().join(format!("iai-rusage-{}.out", name));
+        std::fs::write(&path, contents).unwrap();
+        let stats = RusageStats::parse(&path);
+        std::fs::remove_file(&path).unwrap();
+        stats
+    }
+
+    #[test]
+    fn reads_what_the_benchmark_reported() {
+        let stats = parse(
+            "report",
+            "max_rss_kb=2048\nminor_faults=120\nmajor_faults=1\nvoluntary_switches=3\n\
+             involuntary_switches=0\nnew_counter=7\n",
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            RusageStats {
+                max_rss_kb: 2048,
+                minor_faults: 120,
+                major_faults: 1,
+                voluntary_switches: 3,
+                involuntary_switches: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let error = parse("invalid", "max_rss_kb=2048\nminor_faults\n").unwrap_err();
+        assert!(error.to_string().ends_with("invalid line \"minor_faults\""));
+    }
+}
@@ -0,0 +1,258 @@
+//! Scaling curves for benchmarks that sweep a numeric parameter, e.g. `parse/size=10`,
+//! `parse/size=1000` and `parse/size=100000`.
+//!
+//! For every such sweep the instruction counts are fitted against a few common complexity
+//! classes, and the best fit is reported. If the previous run fit a cheaper class, the sweep is
+//! flagged, since that usually means an accidental complexity regression rather than a constant
+//! factor change.
+
+use crate::{json::RunEvent, report::BenchResult};
+
+/// Sweeps need at least this many points before a growth rate means anything.
+const MIN_POINTS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Growth {
+    Constant,
+    Logarithmic,
+    Linear,
+    Linearithmic,
+    Quadratic,
+    Cubic,
+}
+
+impl Growth {
+    const ALL: [Growth; 6] = [
+        Growth::Constant,
+        Growth::Logarithmic,
+        Growth::Linear,
+        Growth::Linearithmic,
+        Growth::Quadratic,
+        Growth::Cubic,
+    ];
+
+    fn model(self, n: f64) -> f64 {
+        let log = n.max(2.0).log2();
+        match self {
+            Growth::Constant => 1.0,
+            Growth::Logarithmic => log,
+            Growth::Linear => n,
+            Growth::Linearithmic => n * log,
+            Growth::Quadratic => n * n,
+            Growth::Cubic => n * n * n,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Growth::Constant => "constant",
+            Growth::Logarithmic => "log n",
+            Growth::Linear => "linear",
+            Growth::Linearithmic => "n log n",
+            Growth::Quadratic => "quadratic",
+            Growth::Cubic => "cubic",
+        }
+    }
+
+    /// The class whose curve, scaled to the data, has the smallest relative error.
+    fn fit(points: &[(f64, u64)]) -> Growth {
+        let error = |growth: Growth| {
+            let ratios: Vec<f64> = points
+                .iter()
+                .map(|&(n, cost)| cost as f64 / growth.model(n))
+                .collect();
+            let scale = ratios.iter().sum::<f64>() / ratios.len() as f64;
+            if scale == 0.0 {
+                return 0.0;
+            }
+            ratios
+                .iter()
+                .map(|r| (r / scale - 1.0).powi(2))
+                .sum::<f64>()
+        };
+        Growth::ALL
+            .iter()
+            .copied()
+            .min_by(|a, b| error(*a).total_cmp(&error(*b)))
+            .unwrap()
+    }
+}
+
+struct Sweep {
+    base: String,
+    param: &'static str,
+    /// Parameter value, new instruction count and old instruction count.
+    points: Vec<(f64, u64, Option<u64>)>,
+}
+
+impl Sweep {
+    fn growth(&self) -> Growth {
+        let points: Vec<(f64, u64)> = self.points.iter().map(|&(n, new, _)| (n, new)).collect();
+        Growth::fit(&points)
+    }
+
+    fn old_growth(&self) -> Option<Growth> {
+        let points: Option<Vec<(f64, u64)>> = self
+            .points
+            .iter()
+            .map(|&(n, _, old)| Some((n, old?)))
+            .collect();
+        points.map(|points| Growth::fit(&points))
+    }
+}
+
+/// The name of `result` with the segment holding parameter `key` replaced by `key=*`.
+fn base_name(result: &BenchResult, key: &str) -> String {
+    result
+        .bench
+        .name
+        .split('/')
+        .map(|segment| match segment.split_once('=') {
+            Some((k, _)) if k == key => format!("{}=*", k),
+            _ => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn sweeps(results: &[BenchResult]) -> Vec<Sweep> {
+    let mut sweeps: Vec<Sweep> = Vec::new();
+    for result in results {
+        for (key, value) in result.bench.parameters() {
+            let Ok(n) = value.parse::<f64>() else {
+                continue;
+            };
+            if !n.is_finite() || n <= 0.0 {
+                continue;
+            }
+            let base = base_name(result, key);
+            let point = (
+                n,
+                result.stats.instruction_reads(),
+                result.old_instructions(),
+            );
+            match sweeps.iter_mut().find(|s| s.base == base && s.param == key) {
+                Some(sweep) => sweep.points.push(point),
+                None => sweeps.push(Sweep {
+                    base,
+                    param: key,
+                    points: vec![point],
+                }),
+            }
+        }
+    }
+    sweeps.retain(|sweep| sweep.points.len() >= MIN_POINTS);
+    for sweep in &mut sweeps {
+        sweep.points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    sweeps
+}
+
+pub(crate) fn print_scaling(results: &[BenchResult]) {
+    for sweep in sweeps(results) {
+        println!("Scaling of {} over {}", sweep.base, sweep.param);
+        println!(
+            "  {:>12}  {:>15}  {:>12}",
+            sweep.param, "Instructions", "Per unit"
+        );
+        for &(n, instructions, _) in &sweep.points {
+            println!(
+                "  {:>12}  {:>15}  {:>12.2}",
+                n,
+                instructions,
+                instructions as f64 / n
+            );
+        }
+        let growth = sweep.growth();
+        match sweep.old_growth() {
+            Some(old) if old < growth => println!(
+                "  Apparent growth: {} (GREW from {})",
+                growth.name(),
+                old.name()
+            ),
+            Some(old) if old != growth => {
+                println!("  Apparent growth: {} (was {})", growth.name(), old.name())
+            }
+            _ => println!("  Apparent growth: {}", growth.name()),
+        }
+        println!();
+    }
+}
+
+pub(crate) fn scaling_json(results: &[BenchResult]) {
+    for sweep in sweeps(results) {
+        RunEvent::Scaling {
+            benchmark: &sweep.base,
+            param: sweep.param,
+            points: sweep
+                .points
+                .iter()
+                .map(|&(n, instructions, _)| (n, instructions))
+                .collect(),
+            growth: sweep.growth().name(),
+            old_growth: sweep.old_growth().map(Growth::name),
+        }
+        .print();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Benchmark;
+
+    fn bench() {}
+
+    fn points(cost: impl Fn(f64) -> f64) -> Vec<(f64, u64)> {
+        [10.0, 100.0, 1000.0, 10000.0]
+            .iter()
+            .map(|&n| (n, cost(n) as u64))
+            .collect()
+    }
+
+    #[test]
+    fn fits_the_class_of_the_costs() {
+        assert_eq!(Growth::fit(&points(|_| 500.0)), Growth::Constant);
+        assert_eq!(
+            Growth::fit(&points(|n| 40.0 * n.log2())),
+            Growth::Logarithmic
+        );
+        assert_eq!(Growth::fit(&points(|n| 5.0 * n + 20.0)), Growth::Linear);
+        assert_eq!(
+            Growth::fit(&points(|n| 3.0 * n * n.log2())),
+            Growth::Linearithmic
+        );
+        assert_eq!(Growth::fit(&points(|n| n * n)), Growth::Quadratic);
+        assert_eq!(Growth::fit(&points(|n| n * n * n)), Growth::Cubic);
+        // Nothing measured is as constant as it gets
+        assert_eq!(Growth::fit(&points(|_| 0.0)), Growth::Constant);
+    }
+
+    #[test]
+    fn finds_sweeps_over_numeric_parameters() {
+        let benches = [
+            Benchmark::new("parse/size=10", bench),
+            Benchmark::new("parse/size=100", bench),
+            Benchmark::new("parse/size=1000", bench),
+            Benchmark::new("parse/size=huge", bench),
+            Benchmark::new("print/size=10", bench),
+            Benchmark::new("print/size=100", bench),
+        ];
+        let results: Vec<BenchResult> = benches
+            .iter()
+            .map(|bench| {
+                let n: u64 = bench.name.rsplit_once('=').unwrap().1.parse().unwrap_or(1);
+                BenchResult::of(bench, &[("Ir", n * n)], Some(&[("Ir", 8 * n)]))
+            })
+            .collect();
+        // `print` has too few points, and `huge` isn't a number
+        let sweeps = sweeps(&results);
+        assert_eq!(sweeps.len(), 1);
+        let sweep = &sweeps[0];
+        assert_eq!((sweep.base.as_str(), sweep.param), ("parse/size=*", "size"));
+        let sizes: Vec<f64> = sweep.points.iter().map(|&(n, _, _)| n).collect();
+        assert_eq!(sizes, [10.0, 100.0, 1000.0]);
+        assert_eq!(sweep.growth(), Growth::Quadratic);
+        assert_eq!(sweep.old_growth(), Some(Growth::Linear));
+    }
+}
@@ -0,0 +1,104 @@
+//! Measures the overhead of the harness itself (`--self-test`), so that growth of what is
+//! subtracted from every benchmark doesn't go unnoticed between iai versions.
+//!
+//! The instruction count of the calibration run is what ends up subtracted, so it is recorded per
+//! iai version in `target/iai/self-test.history` and checked against the entry of the previous
+//! version. The time it takes to start a benchmark under valgrind and to parse its output is only
+//! reported, since wall-clock times are too noisy to fail on.
+
+use std::{fs, time::Instant};
+
+use crate::{
+    backend, basic_valgrind, read_stats, valgrind_without_aslr, write_atomically, CachegrindStats,
+    Config, Error, Target,
+};
+
+/// How much the calibration may grow, in percent, before the self-test fails.
+const TOLERANCE: f64 = 5.0;
+
+pub(crate) fn run(
+    arch: Option<&str>,
+    executable: &str,
+    allow_aslr: bool,
+    config: &Config,
+) -> Result<(), Error> {
+    let dir = config.results_dir();
+    fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+    let output_file = dir.join("self-test.out");
+    let cmd = if allow_aslr {
+        basic_valgrind()
+    } else {
+        valgrind_without_aslr(arch)
+    };
+
+    let start = Instant::now();
+    let (calibration, _) = backend::run_once::<CachegrindStats>(
+        cmd,
+        executable,
+        Target::Calibration,
+        config,
+        &[],
+        &output_file,
+    )?;
+    let spawn = start.elapsed();
+    let start = Instant::now();
+    read_stats(&output_file)?;
+    let parse = start.elapsed();
+    let instructions = calibration.instruction_reads();
+
+    let history_file = dir.join("self-test.history");
+    let mut history: Vec<(String, u64)> = fs::read_to_string(&history_file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (version, count) = line.split_once(' ')?;
+            Some((version.to_owned(), count.trim().parse().ok()?))
+        })
+        .collect();
+    let version = env!("CARGO_PKG_VERSION");
+    let previous = history
+        .iter()
+        .rev()
+        .find(|(recorded, _)| recorded != version)
+        .cloned();
+
+    println!("iai {} self-test", version);
+    let change = previous.as_ref().map(|(recorded, count)| {
+        let percent = (instructions as f64 - *count as f64) / (*count).max(1) as f64 * 100.0;
+        (recorded, percent)
+    });
+    match &change {
+        Some((recorded, percent)) => println!(
+            "  Calibration:       {:>10} instructions ({:+.2}% since iai {})",
+            instructions, percent, recorded
+        ),
+        None => println!("  Calibration:       {:>10} instructions", instructions),
+    }
+    println!(
+        "  Spawn:             {:>10.1} ms",
+        spawn.as_secs_f64() * 1000.0
+    );
+    println!(
+        "  Parse:             {:>10.3} ms",
+        parse.as_secs_f64() * 1000.0
+    );
+
+    history.retain(|(recorded, _)| recorded != version);
+    history.push((version.to_owned(), instructions));
+    let contents: String = history
+        .iter()
+        .map(|(recorded, count)| format!("{} {}\n", recorded, count))
+        .collect();
+    write_atomically(&history_file, &contents).map_err(|e| Error::io(&history_file, e))?;
+
+    if let Some((recorded, percent)) = change {
+        if percent > TOLERANCE {
+            eprintln!(
+                "iai: the harness overhead grew by {:.2}% since iai {}, more than the allowed {}%",
+                percent, recorded, TOLERANCE
+            );
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
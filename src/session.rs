@@ -0,0 +1,453 @@
+//! The measurement of a suite, shared by the runner of `main!` and by [`run_benches`] for tools
+//! that embed iai and want the results rather than a report.
+
+use crate::{
+    artifact, backend, check_valgrind, configure, function_changes, get_arch,
+    perf::{self, PerfStats},
+    policy, post_process, project, report, run_bench, run_layout_variant, runner_with_config,
+    top_functions, warm_measurement, BenchResult, Benchmark, CachegrindStats, Capabilities,
+    Comparison, Config, Error, HeapStats, History, LayoutSpread, MassifStats, Measurement, Policy,
+    RunMetadata, RusageStats, Target, DEFAULT_FUNCTION_CHANGES,
+};
+
+/// Checks what has to hold before anything is measured.
+pub(crate) fn check(config: &Config) -> Result<(), Error> {
+    if let Some(required) = &config.required_profile {
+        if config.build_profile.as_ref() != Some(required) {
+            return Err(Error::WrongProfile {
+                required: required.clone(),
+                built: config.build_profile.clone(),
+            });
+        }
+    }
+    if let Some(baseline) = &config.compare_baseline {
+        let dir = config.named_baseline_dir(baseline);
+        if !dir.is_dir() {
+            return Err(Error::MissingBaseline {
+                name: baseline.clone(),
+                dir,
+            });
+        }
+    }
+    match crate::outer_instrumenter() {
+        Some(tool) => Err(Error::Instrumented(tool)),
+        None => Ok(()),
+    }
+}
+
+/// The measurements besides cachegrind's that the command line asks for.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Extras {
+    pub(crate) warm: bool,
+    pub(crate) layout_sensitivity: bool,
+    pub(crate) dhat: bool,
+    pub(crate) massif: bool,
+    pub(crate) rusage: bool,
+    /// Count with `perf stat` instead of cachegrind (`IAI_BACKEND=perf`), without any of the
+    /// others.
+    pub(crate) perf: bool,
+}
+
+/// The runs that what the runtime does before reaching a benchmark is measured with.
+enum Calibration {
+    Cachegrind(CachegrindStats, Option<CachegrindStats>),
+    Perf((PerfStats, Option<PerfStats>)),
+}
+
+/// Everything the benchmarks of a run are measured with, calibrated once for all of them.
+pub(crate) struct Session<'c> {
+    executable: &'c str,
+    config: &'c Config,
+    arch: Option<String>,
+    allow_aslr: bool,
+    warm: bool,
+    calibration: Calibration,
+    /// The calibrations of `--layout-sensitivity`.
+    layout_calibrations: Option<Vec<CachegrindStats>>,
+    heap_calibration: Option<(HeapStats, Option<HeapStats>)>,
+    massif_calibration: Option<(MassifStats, Option<MassifStats>)>,
+    rusage_calibration: Option<(RusageStats, Option<RusageStats>)>,
+    pub(crate) policy: Policy,
+    /// The results of `--compare-with`, compared against instead of the previous ones.
+    pub(crate) comparison: Option<Comparison>,
+    /// The history of the instructions, unless they are counted by `perf`.
+    pub(crate) history: Option<History>,
+}
+
+impl<'c> Session<'c> {
+    /// Measures the calibration runs, without which nothing can be measured.
+    pub(crate) fn new(
+        executable: &'c str,
+        config: &'c Config,
+        (arch, allow_aslr): (Option<String>, bool),
+        extras: Extras,
+        policy: Policy,
+        comparison: Option<Comparison>,
+    ) -> Result<Session<'c>, Error> {
+        if extras.perf {
+            return Ok(Session {
+                executable,
+                config,
+                arch,
+                allow_aslr,
+                warm: false,
+                calibration: Calibration::Perf(backend::calibrate(executable, config)?),
+                layout_calibrations: None,
+                heap_calibration: None,
+                massif_calibration: None,
+                rusage_calibration: None,
+                policy,
+                comparison,
+                history: None,
+            });
+        }
+        let (calibration, old_calibration, _) = run_bench(
+            arch.as_deref(),
+            executable,
+            Target::Calibration,
+            "iai_calibration",
+            allow_aslr,
+            config,
+            &[],
+        )?;
+        let layout_calibrations = extras
+            .layout_sensitivity
+            .then(|| {
+                (0..crate::layout_runs())
+                    .map(|run| {
+                        run_layout_variant(
+                            executable,
+                            Target::Calibration,
+                            "iai_calibration",
+                            config,
+                            run,
+                        )
+                    })
+                    .collect()
+            })
+            .transpose()?;
+        Ok(Session {
+            executable,
+            config,
+            arch,
+            allow_aslr,
+            warm: extras.warm,
+            calibration: Calibration::Cachegrind(calibration, old_calibration),
+            layout_calibrations,
+            heap_calibration: extras
+                .dhat
+                .then(|| backend::calibrate(executable, config))
+                .transpose()?,
+            massif_calibration: extras
+                .massif
+                .then(|| backend::calibrate(executable, config))
+                .transpose()?,
+            rusage_calibration: extras
+                .rusage
+                .then(|| backend::calibrate(executable, config))
+                .transpose()?,
+            policy,
+            comparison,
+            history: Some(History::load(config)),
+        })
+    }
+
+    fn run(
+        &self,
+        target: Target,
+        name: &str,
+        child_args: &[&str],
+    ) -> Result<(CachegrindStats, Option<CachegrindStats>), Error> {
+        let (stats, old, _) = run_bench(
+            self.arch.as_deref(),
+            self.executable,
+            target,
+            name,
+            self.allow_aslr,
+            self.config,
+            child_args,
+        )?;
+        Ok((stats, old))
+    }
+
+    /// Measures the benchmark at `index`, or returns why it failed. What it is compared against
+    /// is the previous run or `--compare-with`; it isn't yet checked for regressions.
+    pub(crate) fn measure<'b>(
+        &self,
+        index: usize,
+        bench: &'b Benchmark,
+    ) -> Result<BenchResult<'b>, Error> {
+        let config = self.config;
+        let name = bench.name;
+        policy::check_attributes(bench)?;
+        let target = Target::Bench { index, name };
+        let calibration = match &self.calibration {
+            Calibration::Cachegrind(calibration, old_calibration) => (calibration, old_calibration),
+            Calibration::Perf(calibration) => return self.measure_perf(target, bench, calibration),
+        };
+        let (stats, old_stats, metadata) = run_bench(
+            self.arch.as_deref(),
+            self.executable,
+            target,
+            name,
+            self.allow_aslr,
+            config,
+            &[],
+        )?;
+        post_process(config, name)?;
+        let functions = match config.top_functions {
+            Some(count) => top_functions(config, name, count)?,
+            None => Vec::new(),
+        };
+        // Loading a shared library or artifacts is far more expensive than the usual dispatch
+        // overhead, so such benchmarks are calibrated against a run that loads them but skips the
+        // call, as are all of them with `--per-bench-calibration`. Benchmarks of `bench_iter` are
+        // against one that only makes the items, after any such setup, and those of
+        // `bench_with_setup` against one that only sets up and tears down the input
+        let setup_run = if metadata.items.is_some() {
+            Some(("items", "--iai-items-only"))
+        } else if metadata.setup {
+            Some(("setup", "--iai-items-only"))
+        } else if bench.has_setup() || config.per_bench_calibration {
+            Some(("load", "--iai-load-only"))
+        } else {
+            None
+        };
+        let setup_calibration = setup_run
+            .map(|(suffix, arg)| self.run(target, &format!("{}.{}", name, suffix), &[arg]))
+            .transpose()?;
+        let (calibration, old_calibration) = setup_calibration
+            .as_ref()
+            .map_or(calibration, |(calibration, old)| (calibration, old));
+        let warm = if self.warm {
+            let (twice, old_twice) =
+                self.run(target, &format!("{}.warm", name), &["--iai-warm"])?;
+            Some(warm_measurement(
+                (&twice, old_twice.as_ref()),
+                (&stats, old_stats.as_ref()),
+                config.cycle_weights,
+            ))
+        } else {
+            None
+        };
+        let stats = stats.subtract(calibration);
+        let layout = self
+            .layout_calibrations
+            .as_ref()
+            .map(|calibrations| {
+                LayoutSpread::measure(self.executable, target, name, config, calibrations)
+            })
+            .transpose()?;
+
+        let mut measurements: Vec<Measurement> = warm.into_iter().collect();
+        if let Some(calibration) = &self.heap_calibration {
+            measurements.push(backend::measure(
+                self.executable,
+                target,
+                bench,
+                config,
+                calibration,
+            )?);
+        }
+        if let Some(calibration) = &self.massif_calibration {
+            measurements.push(backend::measure(
+                self.executable,
+                target,
+                bench,
+                config,
+                calibration,
+            )?);
+        }
+        if let Some(calibration) = &self.rusage_calibration {
+            measurements.push(backend::measure(
+                self.executable,
+                target,
+                bench,
+                config,
+                calibration,
+            )?);
+        }
+
+        Ok(BenchResult {
+            bench,
+            stats,
+            old: match (&self.comparison, &old_stats, old_calibration) {
+                (Some(comparison), _, _) => comparison.get(name).cloned(),
+                (None, Some(old_stats), Some(old_calibration)) => {
+                    Some(old_stats.subtract(old_calibration))
+                }
+                _ => None,
+            },
+            committed: None,
+            layout,
+            measurements,
+            metadata,
+            regression: None,
+            significance: None,
+            cache: config.cache,
+            cycle_weights: config.cycle_weights,
+            functions,
+            function_changes: Vec::new(),
+        })
+    }
+
+    /// Measures a benchmark with the hardware counters, see [`perf`].
+    fn measure_perf<'b>(
+        &self,
+        target: Target,
+        bench: &'b Benchmark,
+        calibration: &(PerfStats, Option<PerfStats>),
+    ) -> Result<BenchResult<'b>, Error> {
+        let config = self.config;
+        let (stats, old, counters) =
+            perf::measure(self.executable, target, bench, config, calibration)?;
+        Ok(BenchResult {
+            bench,
+            stats,
+            old: match &self.comparison {
+                Some(comparison) => comparison.get(bench.name).cloned(),
+                None => old,
+            },
+            committed: None,
+            layout: None,
+            measurements: vec![counters],
+            metadata: RunMetadata::default(),
+            regression: None,
+            significance: None,
+            cache: config.cache,
+            cycle_weights: config.cycle_weights,
+            functions: Vec::new(),
+            function_changes: Vec::new(),
+        })
+    }
+
+    /// Checks `result` for regressions against what it is compared with, and finds the functions
+    /// that changed the most if it did.
+    pub(crate) fn assess(&self, result: &mut BenchResult) {
+        let bench = result.bench;
+        result.regression = result
+            .old
+            .as_ref()
+            .map(|old| old.instruction_reads())
+            .or(result.committed)
+            .and_then(|old| {
+                self.policy
+                    .check(bench, result.stats.instruction_reads(), old)
+            })
+            .or_else(|| {
+                let old = result.old.as_ref()?;
+                self.policy
+                    .check_gates(bench, &result.stats, old, self.config.cycle_weights)
+            });
+        result.significance = self
+            .history
+            .as_ref()
+            .and_then(|history| history.significance(result));
+        // Results from `--compare-with` don't come with the functions, nor do those of `perf`
+        if self.comparison.is_none()
+            && matches!(self.calibration, Calibration::Cachegrind(..))
+            && result.old.is_some()
+            && result.changed(&self.policy)
+        {
+            let count = self
+                .config
+                .top_functions
+                .unwrap_or(DEFAULT_FUNCTION_CHANGES);
+            result.function_changes = function_changes(self.config, bench.name, count);
+        }
+    }
+}
+
+/// Measures `benches` like the runner of `main!` does and returns the results, for tools that
+/// embed iai and do their own analysis. Nothing is printed besides warnings, and the command line
+/// isn't looked at: all of the configuration comes from `config` and `iai.toml`. Ignored
+/// benchmarks are skipped, and those that fail (by returning `Err`, crashing, or leaving output
+/// that can't be read) are reported on stderr and left out of the results. The
+/// [`Reporter`](crate::Reporter)s of `config` are told about the run as usual.
+///
+/// The benchmarks are measured in processes started from the running executable, which call this
+/// again with the same benchmarks; in those, it runs the one benchmark and exits.
+///
+/// ```no_run
+/// # fn bench_parse() {}
+/// # fn upload(_name: &str, _instructions: u64) {}
+/// fn main() {
+///     let benches = [&iai::Benchmark::new("parse", bench_parse)];
+///     let results = iai::run_benches(&benches, &iai::Config::new()).unwrap();
+///     for result in &results {
+///         upload(result.name(), result.stats().instruction_reads());
+///     }
+/// }
+/// ```
+pub fn run_benches<'b>(
+    benches: &[&'b Benchmark],
+    config: &Config,
+) -> Result<Vec<BenchResult<'b>>, Error> {
+    let mut args = std::env::args();
+    let executable = args.next().unwrap();
+    if args.next().as_deref() == Some("--iai-run") {
+        // A benchmark process: what runs after the benchmark would be measured along with it
+        runner_with_config(benches, config.clone());
+        std::process::exit(0);
+    }
+    crate::enter(None);
+    let mut config = config.clone();
+    project::load(&mut config)?;
+    configure(&mut config, &executable)?;
+    check(&config)?;
+    if !check_valgrind() {
+        return Err(Error::ValgrindMissing);
+    }
+    let capabilities = Capabilities::probe();
+    if let Some(minimum) = config.min_valgrind {
+        capabilities
+            .check_version(minimum)
+            .map_err(Error::Unsupported)?;
+    }
+    capabilities
+        .check_backend::<CachegrindStats>(&config)
+        .map_err(Error::Unsupported)?;
+
+    let selected: Vec<(usize, &Benchmark)> = benches
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, bench)| !bench.ignore)
+        .collect();
+    artifact::build(&selected, &config)?;
+    let policy = Policy::new(config.threshold, Vec::new(), config.min_instructions);
+    let session = Session::new(
+        &executable,
+        &config,
+        (get_arch(), crate::allow_aslr(&config)),
+        Extras::default(),
+        policy,
+        None,
+    )?;
+    let names: Vec<&str> = selected.iter().map(|(_, bench)| bench.name).collect();
+    let reporters = &config.reporters;
+    reporters.each(|reporter| reporter.on_suite_start(&names));
+    let mut results = Vec::with_capacity(selected.len());
+    let mut failed = 0;
+    for &(i, bench) in &selected {
+        reporters.each(|reporter| reporter.on_bench_start(bench.name));
+        match session.measure(i, bench) {
+            Ok(mut result) => {
+                session.assess(&mut result);
+                reporters.each(|reporter| reporter.on_bench_result(&result));
+                results.push(result);
+            }
+            Err(error) => {
+                eprintln!("iai: {} failed: {}", bench.name, error);
+                reporters.each(|reporter| reporter.on_bench_failed(bench.name, &error));
+                failed += 1;
+            }
+        }
+    }
+    let summary = report::suite_summary(&results, &session.policy, failed);
+    reporters.each(|reporter| reporter.on_suite_results(&results, &summary));
+    if let Some(history) = session.history {
+        history.record(&results, &config)?;
+    }
+    Ok(results)
+}
@@ -0,0 +1,277 @@
+//! The layout of the metric lines of the human-readable report, for matching in-house report
+//! conventions. It is set in the `[template]` table of the project settings:
+//!
+//! ```toml
+//! [template]
+//! line = "  {label}:{col:20}{value:>15}{change}{extra}"  # the default
+//! labels = { instructions = "Instruktionen", estimated_cycles = "Geschätzte Zyklen" }
+//! thousands-separator = "."
+//! decimal-separator = ","
+//! ```
+//!
+//! The line can contain `{label}`, `{metric}` (the key of the metric), `{value}`, `{old}` (the
+//! previous value, or nothing), `{change}` (the percentage since then) and `{extra}` (like the
+//! estimated time), each optionally padded to a width as `{value:>15}` or `{label:<20}`, and
+//! `{col:N}`, which pads the line so far to N columns. Labels are looked up by the key of the
+//! metric: `instructions`, `l1_accesses`, `l2_accesses`, `ram_accesses`, `estimated_cycles`,
+//! `conditional_mispredicted`, `indirect_mispredicted`, `threads`, `<backend>.<metric>` for the
+//! other backends (e.g. `perf.cycles`), the event names of `--verbose` (e.g. `Bcm`) and the files
+//! of the text sizes.
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+/// The template of the run, set once the project settings are known.
+pub(crate) static TEMPLATE: OnceLock<Template> = OnceLock::new();
+
+pub(crate) const DEFAULT_LINE: &str = "  {label}:{col:20}{value:>15}{change}{extra}";
+
+#[derive(Clone, Debug)]
+pub(crate) struct Template {
+    line: Vec<Piece>,
+    labels: BTreeMap<String, String>,
+    thousands_separator: String,
+    decimal_separator: String,
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Text(String),
+    Field {
+        field: Field,
+        /// Whether to pad on the left, and to which width.
+        padding: Option<(bool, usize)>,
+    },
+    Column(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Label,
+    Metric,
+    Value,
+    Old,
+    Change,
+    Extra,
+}
+
+impl Default for Template {
+    fn default() -> Template {
+        Template::new(DEFAULT_LINE, BTreeMap::new(), "", ".").unwrap()
+    }
+}
+
+impl Template {
+    pub(crate) fn new(
+        line: &str,
+        labels: BTreeMap<String, String>,
+        thousands_separator: &str,
+        decimal_separator: &str,
+    ) -> Result<Template, String> {
+        Ok(Template {
+            line: parse(line)?,
+            labels,
+            thousands_separator: thousands_separator.to_owned(),
+            decimal_separator: decimal_separator.to_owned(),
+        })
+    }
+
+    /// The template of this run, or the default one.
+    pub(crate) fn current() -> &'static Template {
+        TEMPLATE.get_or_init(Template::default)
+    }
+
+    /// One metric of the report. `change` is the percentage since `old`, already highlighted.
+    pub(crate) fn line(
+        &self,
+        key: &str,
+        label: &str,
+        value: u64,
+        old: Option<u64>,
+        change: &str,
+        extra: &str,
+    ) -> String {
+        let mut line = String::new();
+        for piece in &self.line {
+            let (field, padding) = match piece {
+                Piece::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Piece::Column(column) => {
+                    let width = visible_width(&line);
+                    line.extend(std::iter::repeat_n(' ', column.saturating_sub(width)));
+                    continue;
+                }
+                Piece::Field { field, padding } => (field, padding),
+            };
+            let text = match field {
+                Field::Label => self
+                    .labels
+                    .get(key)
+                    .map_or(label, String::as_str)
+                    .to_owned(),
+                Field::Metric => key.to_owned(),
+                Field::Value => self.number(value),
+                Field::Old => old.map(|old| self.number(old)).unwrap_or_default(),
+                Field::Change => self.decimals(change),
+                Field::Extra => self.decimals(extra),
+            };
+            let fill = padding.map_or(0, |(_, width)| width.saturating_sub(visible_width(&text)));
+            let fill = " ".repeat(fill);
+            match padding {
+                Some((true, _)) => line.extend([fill, text]),
+                _ => line.extend([text, fill]),
+            }
+        }
+        line
+    }
+
+    fn number(&self, n: u64) -> String {
+        let digits = n.to_string();
+        if self.thousands_separator.is_empty() {
+            return digits;
+        }
+        let mut grouped = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push_str(&self.thousands_separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+
+    fn decimals(&self, text: &str) -> String {
+        text.replace('.', &self.decimal_separator)
+    }
+}
+
+fn parse(line: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            pieces.push(Piece::Text(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed {{ in {:?}", line))?
+            + start;
+        let placeholder = &rest[start + 1..end];
+        let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let width = |digits: &str| {
+            digits
+                .parse::<usize>()
+                .map_err(|_| format!("invalid width in {{{}}}", placeholder))
+        };
+        if name == "col" {
+            pieces.push(Piece::Column(width(spec)?));
+        } else {
+            let field = match name {
+                "label" => Field::Label,
+                "metric" => Field::Metric,
+                "value" => Field::Value,
+                "old" => Field::Old,
+                "change" => Field::Change,
+                "extra" => Field::Extra,
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder {{{}}} (expected label, metric, value, old, change, \
+                         extra or col)",
+                        name
+                    ))
+                }
+            };
+            let padding = match spec.chars().next() {
+                None => None,
+                Some('>') => Some((true, width(&spec[1..])?)),
+                Some('<') => Some((false, width(&spec[1..])?)),
+                Some(_) => Some((false, width(spec)?)),
+            };
+            pieces.push(Piece::Field { field, padding });
+        }
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_owned()));
+    }
+    Ok(pieces)
+}
+
+/// The width of `text` on a terminal, without its color codes.
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_up_the_default_line() {
+        let template = Template::default();
+        assert_eq!(
+            template.line(
+                "instructions",
+                "Instructions",
+                1234,
+                Some(1000),
+                " (+23.4%)",
+                ""
+            ),
+            format!("  Instructions:{}1234 (+23.4%)", " ".repeat(5 + 11))
+        );
+        // Color codes take no room
+        let template = Template::new("{change:>6}|", BTreeMap::new(), "", ".").unwrap();
+        assert_eq!(
+            template.line("threads", "Threads", 2, None, "\x1b[1;31m+5%\x1b[0m", ""),
+            "   \x1b[1;31m+5%\x1b[0m|"
+        );
+    }
+
+    #[test]
+    fn relabels_and_separates_numbers() {
+        let labels = BTreeMap::from([("instructions".to_owned(), "Instruktionen".to_owned())]);
+        let template = Template::new(
+            "{label:<14}|{metric}|{value}|{old}{change}",
+            labels,
+            ".",
+            ",",
+        )
+        .unwrap();
+        assert_eq!(
+            template.line(
+                "instructions",
+                "Instructions",
+                1234567,
+                Some(999),
+                " (+23.5%)",
+                ""
+            ),
+            "Instruktionen |instructions|1.234.567|999 (+23,5%)"
+        );
+        assert_eq!(
+            template.line("l1_accesses", "L1 Accesses", 100, None, "", ""),
+            "L1 Accesses   |l1_accesses|100|"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let error = |line| Template::new(line, BTreeMap::new(), "", ".").unwrap_err();
+        assert_eq!(error("{label"), "unclosed { in \"{label\"");
+        assert_eq!(error("{value:>wide}"), "invalid width in {value:>wide}");
+        assert_eq!(error("{col:}"), "invalid width in {col:}");
+        assert!(error("{name}").starts_with("unknown placeholder {name}"));
+    }
+}
@@ -0,0 +1,134 @@
+//! A fallback for machines without valgrind (`--wall-clock-fallback` or `IAI_WALL_CLOCK`): every
+//! benchmark is run `IAI_WALL_CLOCK_RUNS` times (100 by default) in a process of its own, and the
+//! median and median absolute deviation of the wall-clock time of a call are reported.
+//!
+//! These timings depend on the machine, its load and its frequency scaling, so they only give a
+//! rough idea; they are neither stored nor compared with anything.
+
+use std::{process::Command, time::Instant};
+
+use crate::{
+    backend, json::RunEvent, report::Format, Benchmark, EstimatedTime, Target,
+    BENCHMARK_PROCESS_VAR, HARNESS_VERSION,
+};
+
+/// The argument that makes a benchmark process time this many calls.
+pub(crate) const CHILD_ARG: &str = "--iai-wall-clock";
+const PREFIX: &str = "iai-wall-clock ";
+
+fn runs() -> usize {
+    std::env::var("IAI_WALL_CLOCK_RUNS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(100)
+}
+
+/// Called by the benchmark process: times `runs` calls of `call`, after one to warm up, and
+/// reports them in nanoseconds.
+pub(crate) fn time(runs: usize, call: impl Fn()) {
+    call();
+    let times: Vec<String> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            call();
+            start.elapsed().as_nanos().to_string()
+        })
+        .collect();
+    eprintln!("{}{}", PREFIX, times.join(" "));
+}
+
+/// Times `benches` and reports them, and returns the names of those that failed.
+pub(crate) fn run<'b>(
+    executable: &str,
+    benches: &[(usize, &'b Benchmark)],
+    format: Format,
+) -> Vec<&'b str> {
+    let mut failed = Vec::new();
+    let runs = runs();
+    if format != Format::Json {
+        println!(
+            "Valgrind is unavailable, so these are wall-clock timings instead. They are UNSTABLE: \
+             they vary with the machine and its load, and can't be compared with instruction \
+             counts.\n"
+        );
+    }
+    for &(index, bench) in benches {
+        let target = Target::Bench {
+            index,
+            name: bench.name,
+        };
+        let mut cmd = Command::new(executable);
+        cmd.env(BENCHMARK_PROCESS_VAR, "1")
+            .args(target.child_args())
+            .arg("--iai-harness")
+            .arg(HARNESS_VERSION)
+            .arg(CHILD_ARG)
+            .arg(runs.to_string());
+        let output = match backend::run_child("the wall-clock fallback", cmd) {
+            Ok(output) => output,
+            Err(e) => {
+                if format == Format::Json {
+                    RunEvent::Failed {
+                        benchmark: bench.name,
+                        error: &e.to_string(),
+                    }
+                    .print();
+                } else {
+                    eprintln!("iai: {} failed: {}", bench.name, e);
+                }
+                failed.push(bench.name);
+                continue;
+            }
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut times: Vec<f64> = stderr
+            .lines()
+            .find_map(|line| line.strip_prefix(PREFIX))
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|time| time.parse().ok())
+            .collect();
+        let middle = median(&mut times);
+        let mut deviations: Vec<f64> = times.iter().map(|time| (time - middle).abs()).collect();
+        let mad = median(&mut deviations);
+
+        if format == Format::Json {
+            RunEvent::WallClock {
+                benchmark: bench.name,
+                runs,
+                median_ns: middle,
+                mad_ns: mad,
+            }
+            .print();
+        } else {
+            println!("{}", bench.name);
+            println!(
+                "  Wall Time (unstable): {} ± {} (median ± MAD of {} runs)\n",
+                nanoseconds(middle),
+                nanoseconds(mad),
+                runs
+            );
+        }
+    }
+    failed
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_unstable_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Formats like the estimated times, which are what these stand in for.
+fn nanoseconds(ns: f64) -> String {
+    // A clock of 1GHz ticks once per nanosecond
+    EstimatedTime::from_cycles(ns.round() as u64, 1_000_000_000).to_string()
+}